@@ -4,8 +4,10 @@
 //
 use muon_rs::Error as MuonError;
 use std::fmt;
+use std::io::ErrorKind;
 use std::net::AddrParseError;
-use std::num::{ParseIntError, TryFromIntError};
+use std::num::{ParseFloatError, ParseIntError, TryFromIntError};
+use std::str::ParseBoolError;
 use std::str::Utf8Error;
 
 /// Streambed errors
@@ -19,12 +21,22 @@ pub enum Error {
     ConnectSignal(&'static str),
     /// Error while adding an element to a pipeline
     PipelineAdd(),
+    /// Error while changing pipeline state
+    StateChange(),
+    /// Error while building a flow (flow index, pipeline stage, cause)
+    Build(usize, &'static str, Box<Error>),
     /// Invalid MatrixCrop definition
     InvalidCrop(),
     /// Error parsing IP address
     ParseAddr(AddrParseError),
+    /// Invalid sink address (flow index, address, cause)
+    SinkAddress(usize, String, AddrParseError),
     /// Error parsing integer
     ParseInt(ParseIntError),
+    /// Error parsing float
+    ParseFloat(ParseFloatError),
+    /// Error parsing boolean
+    ParseBool(ParseBoolError),
     /// Error converting from integer
     TryFromInt(TryFromIntError),
     /// Error converting UTF-8
@@ -33,6 +45,8 @@ pub enum Error {
     Io(std::io::Error),
     /// Muon error
     Muon(MuonError),
+    /// JSON error
+    Json(serde_json::Error),
     /// Other error
     Other(&'static str),
 }
@@ -44,23 +58,56 @@ impl fmt::Display for Error {
             Error::InvalidProperty(e) => write!(f, "invalid property: {}", e),
             Error::ConnectSignal(e) => write!(f, "connect signal: {}", e),
             Error::PipelineAdd() => write!(f, "pipeline add"),
+            Error::StateChange() => write!(f, "state change"),
+            Error::Build(_, stage, e) => write!(f, "{} stage: {}", stage, e),
             Error::InvalidCrop() => write!(f, "invalid crop"),
             Error::ParseAddr(e) => write!(f, "parse {:?}", e),
+            Error::SinkAddress(idx, addr, _) => {
+                write!(f, "Flow{} invalid sink address {:?}", idx, addr)
+            },
             Error::ParseInt(e) => write!(f, "parse {:?}", e),
+            Error::ParseFloat(e) => write!(f, "parse {:?}", e),
+            Error::ParseBool(e) => write!(f, "parse {:?}", e),
             Error::TryFromInt(e) => write!(f, "try_from {:?}", e),
             Error::InvalidUtf8(e) => write!(f, "utf-8 {:?}", e),
             Error::Io(e) => write!(f, "IO {:?}", e),
             Error::Muon(e) => write!(f, "muon {:?}", e),
+            Error::Json(e) => write!(f, "json {:?}", e),
             Error::Other(e) => write!(f, "{:?}", e),
         }
     }
 }
 
+impl Error {
+    /// Check if an error is a normal disconnect of a network peer
+    ///
+    /// This includes a closed or reset connection, as well as a read
+    /// timeout.  Other I/O errors (such as connection refused) are not.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Error::Io(e) => match e.kind() {
+                ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::TimedOut
+                | ErrorKind::WouldBlock => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
+            Error::Build(_, _, e) => Some(e.as_ref()),
             Error::ParseAddr(e) => Some(e),
+            Error::SinkAddress(_, _, e) => Some(e),
             Error::ParseInt(e) => Some(e),
+            Error::ParseFloat(e) => Some(e),
+            Error::ParseBool(e) => Some(e),
             Error::TryFromInt(e) => Some(e),
             Error::InvalidUtf8(e) => Some(e),
             _ => None,
@@ -86,6 +133,12 @@ impl From<gstreamer::structure::GetError<'_>> for Error {
     }
 }
 
+impl From<gstreamer::StateChangeError> for Error {
+    fn from(_e: gstreamer::StateChangeError) -> Self {
+        Error::StateChange()
+    }
+}
+
 impl From<AddrParseError> for Error {
     fn from(e: AddrParseError) -> Self {
         Error::ParseAddr(e)
@@ -98,6 +151,18 @@ impl From<ParseIntError> for Error {
     }
 }
 
+impl From<ParseFloatError> for Error {
+    fn from(e: ParseFloatError) -> Self {
+        Error::ParseFloat(e)
+    }
+}
+
+impl From<ParseBoolError> for Error {
+    fn from(e: ParseBoolError) -> Self {
+        Error::ParseBool(e)
+    }
+}
+
 impl From<TryFromIntError> for Error {
     fn from(e: TryFromIntError) -> Self {
         Error::TryFromInt(e)
@@ -121,3 +186,9 @@ impl From<MuonError> for Error {
         Error::Muon(e)
     }
 }
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
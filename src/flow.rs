@@ -3,19 +3,50 @@
 // Copyright (C) 2019-2020  Minnesota Department of Transportation
 //
 use crate::error::Error;
-use glib::{Cast, ObjectExt, ToSendValue, ToValue, WeakRef};
-use gstreamer::message;
+use crate::http;
+use glib::{Cast, ObjectExt, StaticType, ToSendValue, ToValue, WeakRef};
+use gstreamer::{event, message, query, tags, TagList, TagMergeMode};
 use gstreamer::{
-    Bus, Caps, ClockTime, Element, ElementExt, ElementExtManual,
-    ElementFactory, GObjectExtManualGst, GstBinExt, GstObjectExt, Message,
-    MessageView, Pad, PadExt, PadExtManual, Pipeline, Sample, State, Structure,
+    Bin, Bus, BusSyncReply, Caps, CapsRef, Clock, ClockExt, ClockTime,
+    ClockType, Context, Element, ElementExt, ElementExtManual, ElementFactory,
+    EventType, EventView, FlowReturn, Fraction, GObjectExtManualGst, GstBinExt,
+    GstBinExtManual, GstObjectExt, Message, MessageView, Pad, PadExt,
+    PadExtManual, PadProbeData, PadProbeReturn, PadProbeType, Pipeline,
+    PipelineExt, Promise, PromiseError, Sample, SeekFlags, State, StreamError,
+    StreamStatusType, Structure, StructureRef, SystemClock,
 };
-use gstreamer_video::{VideoOverlay, VideoOverlayExtManual};
-use log::{debug, error, trace, warn};
+use gstreamer_gl::{ContextGLExt, GLContext, GLContextExt, GLDisplay};
+use gstreamer_sdp::SDPMessage;
+use gstreamer_video::{
+    UpstreamForceKeyUnitEvent, VideoOverlay, VideoOverlayExt,
+    VideoOverlayExtManual,
+};
+use gstreamer_webrtc::{WebRTCSDPType, WebRTCSessionDescription};
+use log::{debug, error, info, trace, warn};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::fs::OpenOptions;
+use std::net::IpAddr;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Log a flow message, with flow index and name as key-values
+///
+/// The flow is any value with `idx` and `name` fields.
+macro_rules! flow_log {
+    ($lvl:ident, $flow:expr, $($arg:tt)+) => {
+        log::$lvl!(
+            flow = $flow.idx, name = $flow.name.as_deref(); $($arg)+
+        )
+    };
+}
 
 /// One second (microsecond units)
 const SEC_US: u64 = 1_000_000;
@@ -26,17 +57,40 @@ const SEC_NS: u64 = 1_000_000_000;
 /// RTSP stream number for video
 const STREAM_NUM_VIDEO: u32 = 0;
 
+/// Live caps fields copied to a gap fill source
+const FILL_CAPS_FIELDS: &[&str] = &[
+    "format",
+    "width",
+    "height",
+    "framerate",
+    "pixel-aspect-ratio",
+    "interlace-mode",
+    "rate",
+    "channels",
+    "layout",
+    "channel-mask",
+];
+
 /// Default source timeout (sec)
 const DEFAULT_TIMEOUT_SEC: u16 = 2;
 
 /// Default buffering latency (ms)
 const DEFAULT_LATENCY_MS: u32 = 100;
 
+/// Default maximum RTP dropout time (ms)
+pub const DEFAULT_MAX_DROPOUT_MS: u32 = 60;
+
+/// Default PNG refresh interval (ms)
+pub const DEFAULT_REFRESH_MS: u32 = 1_000;
+
 /// Time-To-Live for multicast packets
 const TTL_MULTICAST: i32 = 15;
 
+/// Caps for VAAPI encoder input
+const VAAPI_ENCODE_CAPS: &str = "video/x-raw(memory:VASurface),format=NV12";
+
 /// Clock rate for RTP video packets
-const RTP_VIDEO_CLOCK_RATE: i32 = 90_000;
+pub const RTP_VIDEO_CLOCK_RATE: i32 = 90_000;
 
 /// Number of times to check PTS before giving up
 const PTS_CHECK_TRIES: usize = 4;
@@ -47,12 +101,87 @@ const FONT_SZ: u32 = 14;
 /// Text overlay color (ARGB; yellowish white)
 const OVERLAY_COLOR: u32 = 0xFF_FF_FF_E0;
 
+/// Delay before restarting after an RTSP server is unavailable (sec)
+const RTSP_RETRY_AFTER_SEC: u64 = 10;
+
+/// Gap without decoded data before filling with black / silence (ms)
+const GAP_FILL_MS: u64 = 500;
+
+/// Interval to check for gaps in decoded data (ms)
+const GAP_CHECK_MS: u32 = 100;
+
+/// Delay to allow a source to add a new pad after removing one (ms)
+const PAD_RELINK_MS: u32 = 1_000;
+
+/// Timeout waiting for a pipeline to stop when a flow is dropped (sec)
+const TEARDOWN_TIMEOUT_SEC: u64 = 5;
+
+/// Interval to retry the live source while showing fallback bars (sec)
+const FALLBACK_RETRY_SEC: u64 = 10;
+
+/// Default stats updates for an alert condition to be raised or cleared
+const ALERT_INTERVALS: u32 = 3;
+
+/// Interval to check for banner text updates (ms)
+const BANNER_POLL_MS: u32 = 100;
+
+/// Default interval to re-read overlay text file (ms)
+const OVERLAY_FILE_POLL_MS: u32 = 1_000;
+
+/// Interval to update ticker overlay position (ms)
+const TICKER_INTERVAL_MS: u32 = 40;
+
+/// Default ticker speed (px per second, using default height)
+const TICKER_SPEED: u32 = 40;
+
+/// Buffers queued for an HTTP client before skipping frames
+const MJPEG_SOFT_MAX: i64 = 8;
+
+/// HLS segment target duration (sec)
+const HLS_TARGET_DURATION_SEC: u32 = 2;
+
+/// HLS segments listed in each playlist
+const HLS_PLAYLIST_LENGTH: u32 = 5;
+
+/// HLS segment files kept on disk
+const HLS_MAX_FILES: u32 = 10;
+
+/// Offset from NTP epoch (1900) to Unix epoch (1970) in seconds
+const NTP_EPOCH_OFFSET_SEC: u64 = 2_208_988_800;
+
 /// Default height (px)
 const DEFAULT_HEIGHT: u32 = 240;
 
+/// GL display context type shared between flows
+const GL_DISPLAY_CONTEXT: &str = "gst.gl.GLDisplay";
+
+/// GL application context type shared between flows
+const GL_APP_CONTEXT: &str = "gst.gl.app_context";
+
 /// User agent including version
 const AGENT: &'static str = concat!("streambed/", env!("CARGO_PKG_VERSION"));
 
+/// Source URI scheme
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scheme {
+    /// Video test source (empty location, `test` or `test:`)
+    Test,
+    /// RTP over UDP (`udp://`)
+    Udp,
+    /// RTSP (`rtsp://`)
+    Rtsp,
+    /// RTSP over TLS (`rtsps://`)
+    Rtsps,
+    /// HTTP (`http://`)
+    Http,
+    /// HTTP over TLS (`https://`)
+    Https,
+    /// Local file (`file://`)
+    File,
+    /// Video4Linux2 capture device (`v4l2://`)
+    V4l2,
+}
+
 /// Network transport
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Transport {
@@ -91,6 +220,17 @@ pub enum Encoding {
     AV1,
 }
 
+/// Audio encoding (audio-only flows)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioEncoding {
+    /// G.711 mu-law
+    PCMU,
+    /// G.711 A-law
+    PCMA,
+    /// Opus
+    OPUS,
+}
+
 /// Video source
 pub struct Source {
     /// Source location URI
@@ -101,10 +241,60 @@ pub struct Source {
     encoding: Encoding,
     /// RTP source properties (from SDP)
     sprops: Option<String>,
+    /// RTP source caps (overrides defaults)
+    rtp_caps: Option<String>,
+    /// RTP video clock rate (Hz)
+    clock_rate: i32,
+    /// Bytes to skip at the start of each UDP packet
+    skip_first_bytes: u16,
+    /// Audio encoding (audio-only source)
+    audio: Option<AudioEncoding>,
     /// Source timeout (sec)
     timeout: u16,
+    /// Initial connect timeout (sec)
+    connect_timeout: Option<u16>,
     /// Buffering latency (ms)
     latency: u32,
+    /// Buffering latency for RTSP over TCP (ms)
+    tcp_latency: Option<u32>,
+    /// Maximum RTP dropout time (ms)
+    max_dropout: u32,
+    /// Validate TLS certificates
+    tls_validation: bool,
+    /// HTTP proxy URI
+    proxy: Option<String>,
+    /// Strict HTTPS certificate checking
+    ssl_strict: bool,
+    /// Extra HTTP request headers
+    headers: Vec<(String, String)>,
+    /// HTTP PNG refresh interval (ms; 0 for none)
+    refresh: u32,
+    /// Loop on end of stream (non-live sources)
+    looping: bool,
+    /// Application source caps (`appsrc`)
+    app_caps: Option<String>,
+}
+
+/// Action when a flow is stuck (sink not updating)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StuckAction {
+    /// Post end-of-stream, stopping and restarting the flow
+    RESTART,
+    /// Log a warning and keep waiting
+    WARN,
+    /// Flush the pipeline without tearing it down
+    FLUSH,
+}
+
+/// Flow alert, raised from statistics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alert {
+    /// Packet loss above threshold
+    LOSS,
+    /// No packets received
+    DOWN,
+    /// Previous alert condition cleared
+    RECOVERED,
 }
 
 /// Pixel aspect ratio handling
@@ -139,7 +329,38 @@ pub struct MatrixCrop {
     vgap: u32,
 }
 
+/// Window sink element
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowSink {
+    /// Choose from acceleration (`vaapisink` or `gtksink`)
+    AUTO,
+    /// GTK widget (`gtksink`)
+    GTK,
+    /// VA-API (`vaapisink`)
+    VAAPI,
+    /// Wayland (`waylandsink`)
+    WAYLAND,
+    /// OpenGL (`glimagesink`)
+    GL,
+    /// X Video (`xvimagesink`)
+    XV,
+}
+
+/// Shared OpenGL display and context for window sinks
+///
+/// Flows built with clones of one `GlShare` give all `glimagesink` windows
+/// (`WindowSink::GL`) a single display connection and a shared GL context,
+/// created when the first sink asks for them.  Each window still creates a
+/// GL context of its own to draw with, but in the share group of the shared
+/// context, so textures, shaders and buffers are not duplicated per window.
+#[derive(Clone, Default)]
+pub struct GlShare {
+    /// Shared (display, application) contexts
+    contexts: Arc<Mutex<Option<(Context, Context)>>>,
+}
+
 /// Video sink
+#[derive(Clone)]
 pub enum Sink {
     /// Fake sink (for testing)
     FAKE,
@@ -147,6 +368,106 @@ pub enum Sink {
     RTP(String, i32, Encoding, bool),
     /// Window sink
     WINDOW(MatrixCrop),
+    /// MJPEG over HTTP server (port, path)
+    ///
+    /// Frames are served as `multipart/x-mixed-replace` to any number of
+    /// clients.  Slow clients skip frames rather than stalling the flow.
+    HTTP(u16, String),
+    /// WebRTC sink (encoding, signaling)
+    ///
+    /// A single peer is supported, with streambed sending the offer.  Offers
+    /// and local ICE candidates are sent on the signaling channel; the
+    /// answer and remote candidates must be provided with
+    /// [Flow::set_webrtc_answer] and [Flow::add_ice_candidate].
+    ///
+    /// Requires `webrtcbin`, `dtls` and `srtp` from gst-plugins-bad, plus
+    /// `nice` from libnice.
+    WEBRTC(Encoding, Sender<Signal>),
+    /// HTTP Live Streaming sink (directory)
+    ///
+    /// H.264 segments and playlists are written to the directory, to be
+    /// served by a web server.  Requires `hlssink2` from gst-plugins-bad.
+    HLS(String),
+    /// Pipe sink (target, encoding)
+    ///
+    /// Frames are written to an inherited file descriptor (`fd:N`) or a named
+    /// pipe (path), for a separate process to read.  A named pipe must have a
+    /// reader when the flow starts.  If the reader goes away, the write error
+    /// (EPIPE) stops the flow, which restarts normally.
+    PIPE(String, Encoding),
+}
+
+/// Additional RTP sink destination
+#[derive(Clone, Debug, PartialEq)]
+pub struct Destination {
+    /// Address (IP or host name)
+    address: String,
+    /// UDP port
+    port: i32,
+    /// Multicast TTL
+    ttl: Option<i32>,
+    /// Multicast interface
+    iface: Option<String>,
+}
+
+/// Adaptive bitrate rendition for HLS sinks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rendition {
+    /// Width (px)
+    width: i32,
+    /// Height (px)
+    height: i32,
+    /// Target bitrate (kbps)
+    bitrate: u32,
+}
+
+/// WebRTC signaling message
+pub enum Signal {
+    /// Local SDP offer
+    Offer(String),
+    /// Local ICE candidate (SDP m-line index, candidate)
+    IceCandidate(u32, String),
+}
+
+/// Ticker overlay update
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ticker {
+    /// Ticker text
+    Text(String),
+    /// Ticker speed (px per second, using default height; `None` for default)
+    Speed(Option<u32>),
+}
+
+/// Flow statistics for one update interval
+///
+/// Lost and late packets are only counted for RTP sources.  For other
+/// sources, pushed is the count of buffers from the source.  Dropped frames
+/// are reported by elements in QoS messages, and latency is the measured
+/// pipeline latency.  Jitter and round-trip time are from the jitter buffer
+/// of RTP and RTSP sources, when available (RTT requires RTCP retransmission
+/// feedback).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Packets (or buffers) pushed from the source
+    pub pushed: u64,
+    /// Lost packets
+    pub lost: u64,
+    /// Late packets
+    pub late: u64,
+    /// Egress buffers
+    pub buffers: u64,
+    /// Egress bytes
+    pub bytes: u64,
+    /// Dropped frames
+    pub dropped: u64,
+    /// Pipeline latency (ms)
+    pub latency: u64,
+    /// Ingress bytes
+    pub received: u64,
+    /// Jitter (us)
+    pub jitter: Option<u64>,
+    /// Round-trip time (us)
+    pub rtt: Option<u64>,
 }
 
 /// Flow feedback
@@ -155,8 +476,23 @@ pub enum Feedback {
     Playing(usize),
     /// Flow stopped
     Stopped(usize),
+    /// Flow paused
+    Paused(usize),
     /// Update statistics
-    Stats(usize, u64, u64, u64),
+    Stats(usize, Stats),
+    /// Pipeline error (element name, error message)
+    Error(usize, String, String),
+    /// Software fallback (missing hardware element)
+    Fallback(usize, String),
+    /// Flow stuck (action taken)
+    Stuck(usize, StuckAction),
+    /// Source redirected (new location)
+    Redirect(usize, String),
+    /// Alert raised or cleared
+    ///
+    /// Alerts are raised when a condition lasts for several stats updates,
+    /// and `RECOVERED` is sent once it has cleared for as long.
+    Alert(usize, Alert),
 }
 
 impl fmt::Display for Feedback {
@@ -164,17 +500,51 @@ impl fmt::Display for Feedback {
         match self {
             Feedback::Playing(idx) => write!(f, "Flow{} playing", idx),
             Feedback::Stopped(idx) => write!(f, "Flow{} stopped", idx),
-            Feedback::Stats(idx, pushed, lost, late) => write!(
-                f,
-                "Flow{} stats: {} pushed, {} lost, {} late",
-                idx, pushed, lost, late
-            ),
+            Feedback::Paused(idx) => write!(f, "Flow{} paused", idx),
+            Feedback::Stats(idx, stats) => {
+                write!(
+                    f,
+                    "Flow{} stats: {} pushed, {} lost, {} late, {} buffers, \
+                     {} bytes, {} dropped, {} ms latency, {} received",
+                    idx,
+                    stats.pushed,
+                    stats.lost,
+                    stats.late,
+                    stats.buffers,
+                    stats.bytes,
+                    stats.dropped,
+                    stats.latency,
+                    stats.received
+                )?;
+                if let Some(jitter) = stats.jitter {
+                    write!(f, ", {} us jitter", jitter)?;
+                }
+                if let Some(rtt) = stats.rtt {
+                    write!(f, ", {} us rtt", rtt)?;
+                }
+                Ok(())
+            },
+            Feedback::Error(idx, elem, msg) => {
+                write!(f, "Flow{} error: {} {}", idx, elem, msg)
+            },
+            Feedback::Redirect(idx, location) => {
+                write!(f, "Flow{} redirect: {}", idx, location)
+            },
+            Feedback::Fallback(idx, elem) => {
+                write!(f, "Flow{} software fallback: missing {}", idx, elem)
+            },
+            Feedback::Stuck(idx, action) => {
+                write!(f, "Flow{} stuck: {:?}", idx, action)
+            },
+            Feedback::Alert(idx, alert) => {
+                write!(f, "Flow{} alert: {:?}", idx, alert)
+            },
         }
     }
 }
 
 /// Hardware video acceleration
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Acceleration {
     /// No video acceleration
     NONE,
@@ -192,50 +562,406 @@ pub enum Acceleration {
 pub struct FlowBuilder {
     /// Index of flow
     idx: usize,
+    /// Flow name
+    name: Option<String>,
     /// Video source config
     source: Source,
-    /// Video sink config
-    sink: Sink,
+    /// Video sink configs
+    sinks: Vec<Sink>,
     /// Hardware acceleration
     acceleration: Acceleration,
     /// Overlay text
     overlay_text: Option<String>,
+    /// Overlay text file path
+    overlay_file: Option<String>,
+    /// Interval to re-read overlay text file (ms)
+    overlay_file_ms: u32,
+    /// Statistics overlay format
+    stats_format: Option<String>,
+    /// Overlay text color (ARGB)
+    overlay_color: Option<u32>,
+    /// Overlay background shading (1-255)
+    overlay_shading: Option<u8>,
+    /// Banner overlay text updates
+    banner: Option<Receiver<String>>,
+    /// Ticker overlay text
+    ticker: Option<String>,
+    /// Ticker overlay speed (px per second, using default height)
+    ticker_speed: u32,
+    /// Overlay font size
+    font: FontSize,
+    /// Timestamp overlay
+    timestamp: bool,
+    /// Pipeline clock
+    clock: Option<Clock>,
+    /// Maximum sink bitrate (bits per second)
+    max_bitrate: Option<u64>,
+    /// Sink socket send buffer size (bytes)
+    sink_buffer_size: Option<u32>,
+    /// Sink clock sync (`None` for sink default)
+    sync: Option<bool>,
+    /// Sink maximum lateness (ms, -1 for unlimited)
+    max_lateness: Option<i64>,
+    /// In-band parameter set interval (sec, -1 for every IDR)
+    config_interval: Option<i32>,
+    /// H.264 stream format for RTP payloader (`byte-stream`, `avc`, `avc3`)
+    h264_stream_format: Option<String>,
+    /// H.264 alignment for RTP payloader (`au`, `nal`)
+    h264_alignment: Option<String>,
+    /// Multicast auto-join disabled
+    no_auto_multicast: bool,
+    /// Multicast loopback enabled
+    multicast_loop: bool,
+    /// Multicast TTL for RTP sinks (`None` for default)
+    ttl: Option<i32>,
+    /// DSCP for RTP sinks (`None` for unmarked)
+    dscp: Option<i32>,
+    /// Additional RTP sink destinations
+    destinations: Vec<Destination>,
+    /// Adaptive bitrate renditions for HLS sinks
+    renditions: Vec<Rendition>,
+    /// Index of rendition being added
+    rendition: Option<usize>,
+    /// Fall back to software when hardware elements are missing
+    software_fallback: bool,
+    /// Maximum B-frames between reference frames (0 for low latency)
+    bframes: u32,
+    /// Tune encoders for low latency
+    low_latency: bool,
+    /// Software encoder threads (`None` for element default)
+    encoder_threads: Option<u32>,
+    /// Sliced threads for software h.264 encoder
+    sliced_threads: bool,
+    /// Limit h.264 encoding to constrained-baseline profile
+    constrained_baseline: bool,
+    /// Lost packets (per stats interval) to request a key frame
+    keyframe_loss: Option<u64>,
+    /// Receive RTCP feedback on RTP sink
+    rtcp_feedback: bool,
+    /// Packet loss percentage to raise alert
+    loss_alert: Option<f32>,
+    /// Raise alert when no packets are received
+    down_alert: bool,
+    /// Stats updates for an alert condition to be raised or cleared
+    alert_intervals: u32,
+    /// Current alert condition
+    alert_cond: Option<Alert>,
+    /// Consecutive stats updates with current alert condition
+    alert_count: u32,
+    /// Raised alert
+    alert: Option<Alert>,
+    /// Scaled resolution (width, height)
+    resolution: Option<(i32, i32)>,
+    /// Aspect ratio handling for scaling
+    scale_aspect: AspectRatio,
     /// Flow feedback
     feedback: Option<Sender<Feedback>>,
+    /// Window sink element
+    window_sink: WindowSink,
+    /// Shared GL display for window sinks
+    gl_share: Option<GlShare>,
+    /// Streaming thread priority (nice value)
+    priority: Option<i32>,
     /// Video overlay handle
     handle: Option<usize>,
+    /// Window title
+    window_title: Option<String>,
+    /// Window geometry (x, y, width, height)
+    window_geometry: Option<(i32, i32, i32, i32)>,
+    /// Window display name (or DRM device path for VAAPI)
+    window_display: Option<String>,
+    /// Freeze last frame in window while stopped
+    freeze_frame: bool,
+    /// Pipeline displaying frozen frame
+    freeze: Option<FreezeFrame>,
+    /// STUN server for WebRTC sink
+    stun_server: Option<String>,
+    /// Audio sink encoding (audio-only flows)
+    audio_sink: Option<AudioEncoding>,
+    /// Element factory
+    elements: Box<dyn Elements>,
     /// Pipeline for flow
     pipeline: WeakRef<Pipeline>,
     /// Head element of pipeline
     head: Option<Element>,
+    /// Index of sink branch being added
+    branch: usize,
+    /// Pipeline stage being added (for build errors)
+    stage: &'static str,
     /// Number of pushed packets
     pushed: u64,
     /// Number of lost packets
     lost: u64,
     /// Number of late packets
     late: u64,
+    /// Number of egress buffers
+    buffers: u64,
+    /// Number of egress bytes
+    bytes: u64,
+    /// Number of frames dropped (QoS)
+    dropped: u64,
+    /// Dropped frames reported by each element (QoS)
+    qos_dropped: HashMap<String, u64>,
+    /// Measured pipeline latency (ms)
+    latency_ms: u64,
+    /// Number of ingress bytes
+    received: u64,
+    /// Average jitter from jitter buffer (us)
+    jitter_us: Option<u64>,
+    /// Round-trip time from jitter buffer (us)
+    rtt_us: Option<u64>,
+    /// Ingress buffer counter (updated by source probe)
+    ingress_buffers: Arc<AtomicU64>,
+    /// Ingress byte counter (updated by source probe)
+    ingress_bytes: Arc<AtomicU64>,
+    /// Egress buffer counter (updated by sink pad probe)
+    egress_buffers: Arc<AtomicU64>,
+    /// Egress byte counter (updated by sink pad probe)
+    egress_bytes: Arc<AtomicU64>,
+    /// Last state change (playing, time)
+    state_change: Arc<Mutex<Option<(bool, SystemTime)>>>,
+    /// Count of pipeline state changes (cancels pending link checks)
+    state_changes: Arc<AtomicUsize>,
+    /// Automatic restart disabled
+    no_restart: bool,
+    /// Count of restarts
+    restarts: Arc<AtomicUsize>,
+    /// Timeout waiting for playing state in build (seconds)
+    play_timeout: Option<u16>,
+    /// Action when flow is stuck
+    stuck_action: StuckAction,
+    /// Stuck check disabled
+    no_stuck_check: bool,
+    /// Fill gaps in decoded stream with black / silence
+    gap_fill: bool,
+    /// Failures before falling back to test source
+    fallback_failures: Option<u32>,
+    /// Consecutive failures before playing
+    failures: u32,
+    /// Live source branch elements (for fallback)
+    live_branch: Vec<WeakRef<Element>>,
+    /// Adding live source branch elements
+    adding_live: bool,
+    /// Time to retry live source branch (ms since epoch; 0 until fallback)
+    fallback_retry_at: u64,
+    /// Flow paused
+    paused: Arc<AtomicBool>,
+    /// Pipeline paused for buffering
+    buffering: Arc<AtomicBool>,
+    /// Source redirect followed
+    redirected: bool,
+    /// Time to allow restart (ms since epoch)
+    retry_at: Arc<AtomicU64>,
+    /// Source has connected since built
+    connected: Arc<AtomicBool>,
 }
 
 /// Video flow
 pub struct Flow {
     /// Index of flow
     idx: usize,
+    /// Flow name
+    name: Option<String>,
     /// Video pipeline
     pipeline: Pipeline,
     /// Pipeline message bus
     bus: Bus,
+    /// Last state change (playing, time)
+    state_change: Arc<Mutex<Option<(bool, SystemTime)>>>,
+    /// Ticker overlay updates
+    ticker: Option<Sender<Ticker>>,
+    /// Flow feedback
+    feedback: Option<Sender<Feedback>>,
+    /// Flow paused
+    paused: Arc<AtomicBool>,
+}
+
+/// Pipeline displaying a frozen frame (stopped when dropped)
+struct FreezeFrame(Pipeline);
+
+/// Overlay font size
+#[derive(Clone, Copy)]
+struct FontSize {
+    /// Size (pt) at reference height
+    size: u32,
+    /// Reference height (px; 0 for no scaling)
+    height: u32,
+}
+
+/// Ticker overlay scroller
+struct TickerScroller {
+    /// Index of flow
+    idx: usize,
+    /// Flow pipeline
+    pipeline: WeakRef<Pipeline>,
+    /// Ticker updates
+    updates: Receiver<Ticker>,
+    /// Count of characters in text
+    chars: usize,
+    /// Speed (px per second, using default height)
+    speed: u32,
+    /// Font size
+    font: FontSize,
+    /// Horizontal offset of text (px; `None` to start at right edge)
+    offset: Option<f64>,
 }
 
 /// Periodic flow checker
 struct FlowChecker {
     /// Index of flow
     idx: usize,
+    /// Flow name
+    name: Option<String>,
     /// Flow pipeline
     pipeline: WeakRef<Pipeline>,
     /// Count of checks
     count: usize,
     /// Most recent presentation time stamp
     last_pts: ClockTime,
+    /// Automatic restart disabled
+    no_restart: bool,
+    /// Count of restarts
+    restarts: Arc<AtomicUsize>,
+    /// Action when flow is stuck (`None` to skip check)
+    stuck_action: Option<StuckAction>,
+    /// Flow feedback
+    feedback: Option<Sender<Feedback>>,
+    /// Flow paused
+    paused: Arc<AtomicBool>,
+    /// Pipeline paused for buffering
+    buffering: Arc<AtomicBool>,
+    /// Time to allow restart (ms since epoch)
+    retry_at: Arc<AtomicU64>,
+    /// Source has connected since built
+    connected: Arc<AtomicBool>,
+    /// Checks to allow before restarting, until connected
+    connect_checks: usize,
+}
+
+impl Default for FontSize {
+    fn default() -> Self {
+        FontSize {
+            size: FONT_SZ,
+            height: DEFAULT_HEIGHT,
+        }
+    }
+}
+
+impl FontSize {
+    /// Get font size (pt) scaled for a frame height
+    fn scaled(&self, height: u32) -> u32 {
+        match self.height {
+            0 => self.size,
+            h => self.size * height / h,
+        }
+    }
+}
+
+impl TickerScroller {
+    /// Create a new ticker scroller
+    fn new(
+        idx: usize,
+        pipeline: WeakRef<Pipeline>,
+        updates: Receiver<Ticker>,
+        text: &str,
+        speed: u32,
+        font: FontSize,
+    ) -> Self {
+        TickerScroller {
+            idx,
+            pipeline,
+            updates,
+            chars: text.chars().count(),
+            speed,
+            font,
+            offset: None,
+        }
+    }
+
+    /// Apply queued ticker updates
+    fn do_updates(&mut self, txt: &Element) -> glib::Continue {
+        loop {
+            match self.updates.try_recv() {
+                Ok(Ticker::Text(text)) => {
+                    if let Err(e) = set_property(txt, "text", &text) {
+                        warn!(
+                            flow = self.idx;
+                            "Flow{}: ticker text -- {}",
+                            self.idx, e
+                        );
+                    }
+                    self.chars = text.chars().count();
+                    self.offset = None;
+                },
+                Ok(Ticker::Speed(speed)) => {
+                    self.speed = speed.unwrap_or(TICKER_SPEED);
+                },
+                Err(TryRecvError::Empty) => return glib::Continue(true),
+                Err(TryRecvError::Disconnected) => {
+                    return glib::Continue(false)
+                },
+            }
+        }
+    }
+
+    /// Scroll the ticker text
+    fn do_scroll(&mut self) -> glib::Continue {
+        let txt = match self.pipeline.upgrade() {
+            Some(pipeline) => {
+                match pipeline.get_by_name(&elem_name(self.idx, "ticker")) {
+                    Some(txt) => txt,
+                    None => return glib::Continue(false),
+                }
+            },
+            None => return glib::Continue(false),
+        };
+        if let glib::Continue(false) = self.do_updates(&txt) {
+            return glib::Continue(false);
+        }
+        let caps = txt.get_static_pad("src").and_then(|p| p.get_current_caps());
+        if let Some(caps) = caps {
+            for s in caps.iter() {
+                if let (Ok(Some(width)), Ok(Some(height))) =
+                    (s.get::<i32>("width"), s.get::<i32>("height"))
+                {
+                    self.scroll(&txt, width, height);
+                }
+            }
+        }
+        glib::Continue(true)
+    }
+
+    /// Move the ticker text for a frame size
+    fn scroll(&mut self, txt: &Element, width: i32, height: i32) {
+        let scale = f64::from(height) / f64::from(DEFAULT_HEIGHT);
+        let step =
+            f64::from(self.speed) * scale * f64::from(TICKER_INTERVAL_MS)
+                / 1000.0;
+        // Estimate text width, erring on the wide side
+        let sz = self.font.scaled(u32::try_from(height).unwrap_or(0));
+        let text_width = self.chars as f64 * f64::from(sz);
+        let width = f64::from(width);
+        let offset = match self.offset {
+            Some(offset) if offset - step > -text_width => offset - step,
+            _ => width,
+        };
+        self.offset = Some(offset);
+        if let Err(e) = set_property(txt, "deltax", &(offset as i32)) {
+            warn!(flow = self.idx; "Flow{}: ticker offset -- {}", self.idx, e);
+        }
+    }
+}
+
+/// Format a flow index, with name if available
+fn fmt_flow(
+    f: &mut fmt::Formatter,
+    idx: usize,
+    name: &Option<String>,
+) -> fmt::Result {
+    match name {
+        Some(name) => write!(f, "Flow{} ({})", idx, name),
+        None => write!(f, "Flow{}", idx),
+    }
 }
 
 /// Make a pipeline element
@@ -261,437 +987,2461 @@ fn set_property(
     }
 }
 
-/// Link ghost pad with sink
-fn link_ghost_pad(idx: usize, src: &Element, src_pad: &Pad, sink: Element) {
-    match sink.get_static_pad("sink") {
-        Some(sink_pad) => {
-            let pn = src_pad.get_name();
-            let p0 = src.get_name();
-            let p1 = sink.get_name();
-            match src_pad.link(&sink_pad) {
-                Ok(_) => {
-                    trace!("Flow{} pad {} linked: {} => {}", idx, pn, p0, p1);
-                },
-                Err(_) => {
-                    debug!(
-                        "Flow{} pad {} not linked: {} => {}",
-                        idx, pn, p0, p1
-                    );
-                },
-            }
-        },
-        None => error!("Flow{}: no sink pad", idx),
+/// Pipeline element factory
+///
+/// Flows are built with `GstElements`.  Tests can substitute a double to
+/// check which elements and properties a `FlowBuilder` chooses, without
+/// GStreamer plugins installed.
+trait Elements: Send {
+    /// Check if an element factory is available
+    fn has_element(&self, factory_name: &'static str) -> bool;
+
+    /// Make a pipeline element
+    fn make_element(
+        &self,
+        factory_name: &'static str,
+        name: &str,
+    ) -> Result<Element, Error>;
+
+    /// Set a property of an element
+    fn set_property(
+        &self,
+        elem: &Element,
+        name: &'static str,
+        value: &dyn ToValue,
+    ) -> Result<(), Error>;
+}
+
+/// GStreamer element factory
+struct GstElements;
+
+impl Elements for GstElements {
+    fn has_element(&self, factory_name: &'static str) -> bool {
+        ElementFactory::find(factory_name).is_some()
+    }
+
+    fn make_element(
+        &self,
+        factory_name: &'static str,
+        name: &str,
+    ) -> Result<Element, Error> {
+        make_element(factory_name, Some(name))
+    }
+
+    fn set_property(
+        &self,
+        elem: &Element,
+        name: &'static str,
+        value: &dyn ToValue,
+    ) -> Result<(), Error> {
+        set_property(elem, name, value)
     }
 }
 
-impl Default for AspectRatio {
+impl Default for Box<dyn Elements> {
     fn default() -> Self {
-        AspectRatio::PRESERVE
+        Box::new(GstElements)
     }
 }
 
-impl Default for Acceleration {
-    fn default() -> Self {
-        Acceleration::NONE
+/// Create a WebRTC offer
+fn create_offer(
+    idx: usize,
+    webrtc: &Element,
+    signal: &Arc<Mutex<Sender<Signal>>>,
+) {
+    let weak = webrtc.downgrade();
+    let signal = Arc::clone(signal);
+    let promise = Promise::with_change_func(move |reply| {
+        if let Some(webrtc) = weak.upgrade() {
+            if let Err(e) = offer_created(&webrtc, &signal, reply) {
+                error!(flow = idx; "Flow{}: WebRTC offer {}", idx, e);
+            }
+        }
+    });
+    if let Err(_) = webrtc.emit("create-offer", &[&None::<Structure>, &promise])
+    {
+        error!(flow = idx; "Flow{}: create-offer failed", idx);
     }
 }
 
-impl FromStr for Acceleration {
-    type Err = Error;
+/// Handle a created WebRTC offer
+fn offer_created(
+    webrtc: &Element,
+    signal: &Mutex<Sender<Signal>>,
+    reply: Result<Option<&StructureRef>, PromiseError>,
+) -> Result<(), Error> {
+    let reply = reply
+        .map_err(|_| Error::Other("offer promise"))?
+        .ok_or(Error::Other("offer missing"))?;
+    let offer = reply
+        .get::<WebRTCSessionDescription>("offer")?
+        .ok_or(Error::Other("offer missing"))?;
+    webrtc.emit("set-local-description", &[&offer, &None::<Promise>])?;
+    let sdp = offer.get_sdp().as_text()?;
+    signal
+        .lock()
+        .unwrap()
+        .send(Signal::Offer(sdp))
+        .map_err(|_| Error::Other("signal channel closed"))
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "" | "NONE" => Ok(Self::NONE),
-            "VAAPI" => Ok(Self::VAAPI),
-            "OMX" => Ok(Self::OMX),
-            _ => Err(Error::Other("invalid acceleration")),
+/// Update banner overlay text from a channel
+fn update_banner(
+    idx: usize,
+    pipeline: &WeakRef<Pipeline>,
+    banner: &Receiver<String>,
+) -> glib::Continue {
+    let mut text = None;
+    loop {
+        match banner.try_recv() {
+            Ok(t) => text = Some(t),
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => return glib::Continue(false),
         }
     }
+    match pipeline.upgrade() {
+        Some(pipeline) => {
+            if let (Some(text), Some(txt)) =
+                (text, pipeline.get_by_name(&elem_name(idx, "banner")))
+            {
+                if let Err(e) = set_property(&txt, "text", &text) {
+                    warn!(flow = idx; "Flow{}: banner text -- {}", idx, e);
+                }
+            }
+            glib::Continue(true)
+        },
+        None => glib::Continue(false),
+    }
 }
 
-impl Default for Transport {
-    fn default() -> Self {
-        Transport::ANY
+/// Read overlay text from a file (without trailing whitespace)
+fn read_overlay_file(path: &str) -> Result<String, Error> {
+    Ok(std::fs::read_to_string(path)?.trim_end().to_string())
+}
+
+/// Update overlay text from a file, when changed
+///
+/// When the file can't be read, the last text is kept, and the error is only
+/// logged until the file is readable again.
+fn update_overlay_file(
+    idx: usize,
+    pipeline: &WeakRef<Pipeline>,
+    path: &str,
+    last: &mut String,
+    failed: &mut bool,
+) -> glib::Continue {
+    let pipeline = match pipeline.upgrade() {
+        Some(pipeline) => pipeline,
+        None => return glib::Continue(false),
+    };
+    match read_overlay_file(path) {
+        Ok(text) => {
+            *failed = false;
+            if text != *last {
+                if let Some(txt) = pipeline.get_by_name(&elem_name(idx, "txt"))
+                {
+                    match set_property(&txt, "text", &text) {
+                        Ok(()) => *last = text,
+                        Err(e) => {
+                            warn!(
+                                flow = idx;
+                                "Flow{}: overlay text -- {}",
+                                idx, e
+                            )
+                        },
+                    }
+                }
+            }
+        },
+        Err(e) => {
+            if !*failed {
+                warn!(
+                    flow = idx;
+                    "Flow{}: overlay file {} -- {}",
+                    idx, path, e
+                );
+                *failed = true;
+            }
+        },
     }
+    glib::Continue(true)
 }
 
-impl FromStr for Transport {
-    type Err = Error;
+/// Get current time in milliseconds since the Unix epoch
+fn epoch_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|d| u64::try_from(d.as_millis()).ok())
+        .unwrap_or(0)
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "ANY" => Ok(Self::ANY),
-            "UDP" => Ok(Self::UDP),
-            "MCAST" => Ok(Self::MCAST),
-            "TCP" => Ok(Self::TCP),
-            _ => Err(Error::Other("invalid transport")),
+/// Check if an error means the server is unavailable (RTSP 503)
+fn is_unavailable(error: &str, debug: Option<String>) -> bool {
+    let unavailable = |msg: &str| {
+        msg.contains("Service Unavailable") || msg.contains("(503)")
+    };
+    unavailable(error) || debug.iter().any(|dbg| unavailable(dbg))
+}
+
+/// Get monotonic time elapsed since an instant in milliseconds
+fn elapsed_ms(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Get caps for a gap fill source, matching a live stream
+///
+/// Only raw format fields are copied, which a test source can produce.
+/// Switching the selector then needs no downstream renegotiation.
+fn fill_caps(caps: &CapsRef) -> Option<Caps> {
+    let s = caps.get_structure(0)?;
+    let mut fill = Structure::new_empty(s.get_name());
+    for field in FILL_CAPS_FIELDS {
+        if let Ok(value) = s.get_value(field) {
+            fill.set_value(field, value.clone());
         }
     }
+    let mut caps = Caps::new_empty();
+    caps.get_mut()?.append_structure(fill);
+    Some(caps)
 }
 
-impl Default for Encoding {
-    fn default() -> Self {
-        Encoding::RAW
+/// Check for a gap in the live branch of a gap fill selector
+///
+/// The first sink pad is the fill branch, and the second is live.  Times
+/// are monotonic milliseconds since `start`, so clock steps are ignored.
+fn check_gap(
+    idx: usize,
+    selector: &WeakRef<Element>,
+    start: Instant,
+    last_ms: &AtomicU64,
+) -> glib::Continue {
+    let sel = match selector.upgrade() {
+        Some(sel) => sel,
+        None => return glib::Continue(false),
+    };
+    let pads = sel.get_sink_pads();
+    let (fill, live) = match &pads[..] {
+        [fill, live, ..] => (fill, live),
+        _ => return glib::Continue(true),
+    };
+    let gap = elapsed_ms(start).saturating_sub(last_ms.load(Ordering::Relaxed))
+        >= GAP_FILL_MS;
+    let pad = if gap { fill } else { live };
+    let active = sel
+        .get_property("active-pad")
+        .ok()
+        .and_then(|v| v.get::<Pad>().ok())
+        .flatten();
+    if active.as_ref() != Some(pad) {
+        if gap {
+            info!(flow = idx; "Flow{}: gap in stream, filling", idx);
+        } else {
+            info!(flow = idx; "Flow{}: stream resumed", idx);
+        }
+        if let Err(e) = sel.set_property("active-pad", pad) {
+            warn!(flow = idx; "Flow{}: gap fill -- {}", idx, e);
+        }
     }
+    glib::Continue(true)
 }
 
-impl FromStr for Encoding {
-    type Err = Error;
+/// Refresh an HTTP PNG source by fetching the image again
+fn refresh_png(idx: usize, pipeline: &WeakRef<Pipeline>) -> glib::Continue {
+    match pipeline.upgrade() {
+        Some(pipeline) => {
+            if let (Some(src), Some(dec)) = (
+                pipeline.get_by_name(&elem_name(idx, "httpsrc")),
+                pipeline.get_by_name(&elem_name(idx, "pngdec")),
+            ) {
+                trace!(flow = idx; "Flow{}: refreshing PNG", idx);
+                // Restarting the source sends a new stream-start, which
+                // clears the EOS from the previous fetch
+                for elem in &[&dec, &src] {
+                    if elem.set_state(State::Null).is_err()
+                        || elem.sync_state_with_parent().is_err()
+                    {
+                        warn!(flow = idx; "Flow{}: PNG refresh failed", idx);
+                    }
+                }
+            }
+            glib::Continue(true)
+        },
+        None => glib::Continue(false),
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "RAW" => Ok(Self::RAW),
-            "PNG" => Ok(Self::PNG),
-            "MJPEG" => Ok(Self::MJPEG),
-            "MPEG2" => Ok(Self::MPEG2),
-            "MPEG4" => Ok(Self::MPEG4),
-            "H264" | "" => Ok(Self::H264),
-            "H265" => Ok(Self::H265),
-            "VP8" => Ok(Self::VP8),
-            "VP9" => Ok(Self::VP9),
-            _ => Err(Error::Other("invalid encoding")),
+/// H.264 parameter sets
+#[derive(Default)]
+struct ParamSets {
+    /// Sequence parameter set
+    sps: Option<Vec<u8>>,
+    /// Picture parameter set
+    pps: Option<Vec<u8>>,
+}
+
+impl ParamSets {
+    /// Update with a NAL unit
+    fn update(&mut self, nal: &[u8]) {
+        match nal.first().map(|n| n & 0x1F) {
+            Some(7) => self.sps = Some(nal.to_vec()),
+            Some(8) => self.pps = Some(nal.to_vec()),
+            _ => (),
+        }
+    }
+
+    /// Get `sprop-parameter-sets` value, if both SPS and PPS are known
+    fn sprops(&self) -> Option<String> {
+        match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) => {
+                let sps = glib::base64_encode(sps)?;
+                let pps = glib::base64_encode(pps)?;
+                Some(format!("{},{}", sps, pps))
+            },
+            _ => None,
         }
     }
 }
 
-impl Encoding {
-    /// Get RTP depayload factory name
-    fn rtp_depay(&self) -> Result<&'static str, Error> {
+/// Get H.264 NAL units from an RTP packet (single NAL unit or STAP-A)
+fn rtp_h264_nals(packet: &[u8]) -> Vec<&[u8]> {
+    if packet.len() < 12 {
+        return vec![];
+    }
+    let csrc_count = usize::from(packet[0] & 0x0F);
+    let mut start = 12 + csrc_count * 4;
+    if packet[0] & 0x10 != 0 {
+        // Header extension
+        match packet.get(start + 2..start + 4) {
+            Some(len) => {
+                start +=
+                    4 + usize::from(u16::from_be_bytes([len[0], len[1]])) * 4
+            },
+            None => return vec![],
+        }
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        // Padding
+        end = end.saturating_sub(usize::from(packet[end - 1]));
+    }
+    let payload = match packet.get(start..end) {
+        Some(payload) if !payload.is_empty() => payload,
+        _ => return vec![],
+    };
+    match payload[0] & 0x1F {
+        1..=23 => vec![payload],
+        24 => {
+            // STAP-A: each NAL unit has a 16-bit size prefix
+            let mut nals = vec![];
+            let mut p = &payload[1..];
+            while p.len() > 2 {
+                let sz = usize::from(u16::from_be_bytes([p[0], p[1]]));
+                match p.get(2..2 + sz) {
+                    Some(nal) => nals.push(nal),
+                    None => break,
+                }
+                p = &p[2 + sz..];
+            }
+            nals
+        },
+        _ => vec![],
+    }
+}
+
+/// Get timestamp overlay text for a buffer PTS
+fn timestamp_text(
+    pad: &Pad,
+    elem: &Element,
+    pts: ClockTime,
+    offset: bool,
+) -> Option<String> {
+    let segment = pad.get_sticky_event(EventType::Segment, 0)?;
+    let running = match segment.view() {
+        EventView::Segment(seg) => seg
+            .get_segment()
+            .downcast_ref::<ClockTime>()?
+            .to_running_time(pts),
+        _ => return None,
+    };
+    let clock = elem.get_clock()?;
+    let time = (elem.get_base_time() + running).nseconds()?;
+    let time = unix_time_ns(time)?;
+    let mut text = utc_text(time);
+    if offset {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+        let clock_now = unix_time_ns(clock.get_time().nseconds()?)?;
+        let diff = clock_now as i128 - now.as_nanos() as i128;
+        text.push_str(&format!(" ({:+} ms)", diff / 1_000_000));
+    }
+    Some(text)
+}
+
+/// Convert a clock time to nanoseconds since the Unix epoch
+fn unix_time_ns(time: u64) -> Option<u64> {
+    let ntp_offset = NTP_EPOCH_OFFSET_SEC * SEC_NS;
+    // NTP clocks report time since 1900 (more than 105 years ago)
+    if time > ntp_offset + ntp_offset / 2 {
+        time.checked_sub(ntp_offset)
+    } else {
+        Some(time)
+    }
+}
+
+/// Format nanoseconds since the Unix epoch as UTC text
+fn utc_text(ns: u64) -> String {
+    let secs = ns / SEC_NS;
+    let ms = (ns % SEC_NS) / 1_000_000;
+    let days = (secs / 86_400) as i64;
+    let sod = secs % 86_400;
+    // Convert days to civil date (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} UTC",
+        year,
+        month,
+        day,
+        sod / 3600,
+        (sod / 60) % 60,
+        sod % 60,
+        ms
+    )
+}
+
+/// Check if RTP caps are for an audio stream
+fn is_audio_caps(caps: &Caps) -> bool {
+    caps.iter()
+        .any(|s| s.get::<&str>("media").ok().flatten() == Some("audio"))
+}
+
+/// Format an optional statistic (`-` if not available)
+fn opt_string(value: Option<u64>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => String::from("-"),
+    }
+}
+
+/// Check if an element is a jitter buffer
+fn is_jitter_buffer(elem: &Element) -> bool {
+    match elem.get_factory() {
+        Some(factory) => factory.get_name() == "rtpjitterbuffer",
+        None => false,
+    }
+}
+
+/// Get the stats structure of a jitter buffer element
+fn jitter_stats(jitter: &Element) -> Result<Structure, Error> {
+    let prop = jitter.get_property("stats")?;
+    prop.get::<Structure>()?.ok_or(Error::Other("empty stats"))
+}
+
+/// Get a nanosecond field of jitter buffer stats as microseconds
+///
+/// Zero values are treated as missing, since they are reported before any
+/// packets arrive (jitter), or without RTCP retransmission feedback (RTT).
+fn stats_us(stats: &StructureRef, field: &str) -> Option<u64> {
+    match stats.get::<u64>(field) {
+        Ok(Some(ns)) if ns > 0 => Some(ns / 1_000),
+        _ => None,
+    }
+}
+
+/// Get the media type of caps (`video`, `audio`, `image`, etc.)
+///
+/// RTP caps use the `media` field; others use the media type prefix.
+fn caps_media(caps: &CapsRef) -> Option<&str> {
+    let s = caps.get_structure(0)?;
+    match s.get_name() {
+        "application/x-rtp" => s.get::<&str>("media").ok().flatten(),
+        name => name.split('/').next(),
+    }
+}
+
+/// Check if caps are for the wrong media (audio vs. video) for a flow
+///
+/// Other media types, such as `image` or containers, are not rejected.
+fn is_wrong_media(caps: &CapsRef, audio: bool) -> bool {
+    match caps_media(caps) {
+        Some("audio") => !audio,
+        Some("video") => audio,
+        _ => false,
+    }
+}
+
+/// Select RTSP streams (`select-stream` signal of `rtspsrc`)
+///
+/// Signal values are the element, stream number and caps.  Audio flows
+/// select the first stream with audio caps, tracked with `selected`; video
+/// flows select stream number `STREAM_NUM_VIDEO`.  If the caps can't be
+/// read, only `STREAM_NUM_VIDEO` is selected, and if the number can't be
+/// read, nothing is selected.  This never panics, since it runs on a
+/// streaming thread.
+fn select_stream(
+    idx: usize,
+    audio: bool,
+    selected: &AtomicBool,
+    values: &[glib::Value],
+) -> bool {
+    let num = match values.get(1).map(|v| v.get::<u32>()) {
+        Some(Ok(Some(num))) => num,
+        _ => {
+            warn!(flow = idx; "Flow{}: select-stream number invalid", idx);
+            return false;
+        },
+    };
+    if !audio {
+        return num == STREAM_NUM_VIDEO;
+    }
+    // Streams are offered in order, starting from 0 for each session
+    if num == 0 {
+        selected.store(false, Ordering::Relaxed);
+    }
+    match values.get(2).map(|v| v.get::<Caps>()) {
+        Some(Ok(Some(caps))) => {
+            is_audio_caps(&caps) && !selected.swap(true, Ordering::Relaxed)
+        },
+        _ => {
+            warn!(flow = idx; "Flow{}: select-stream caps invalid", idx);
+            num == STREAM_NUM_VIDEO
+        },
+    }
+}
+
+/// Check if a pipeline is playing, with no state change pending
+fn is_pipeline_playing(pipeline: &WeakRef<Pipeline>) -> bool {
+    let state = pipeline
+        .upgrade()
+        .map(|p| p.get_state(ClockTime::from_seconds(0)));
+    matches!(state, Some((_, State::Playing, State::VoidPending)))
+}
+
+/// Check that a sink is still linked to a source with dynamic pads
+///
+/// If the source removed the linked pad without adding another, or added
+/// all of its pads without one being linked, the flow would stall until the
+/// stuck check.  An error is posted instead, so it restarts right away.
+fn check_dynamic_link(
+    idx: usize,
+    src: &Element,
+    sink: &WeakRef<Element>,
+    reason: &str,
+) {
+    let linked = sink
+        .upgrade()
+        .and_then(|sink| sink.get_static_pad("sink"))
+        .map(|pad| pad.is_linked());
+    if let Some(false) = linked {
+        warn!(
+            flow = idx;
+            "Flow{}: {} unlinked ({})",
+            idx, src.get_name(), reason
+        );
+        let msg = message::Error::builder(StreamError::Failed, reason)
+            .src(src)
+            .build();
+        if src.post_message(msg).is_err() {
+            error!(flow = idx; "Flow{}: post error failed", idx);
+        }
+    }
+}
+
+/// Get the default streaming thread priority (nice value of main thread)
+fn default_priority() -> i32 {
+    let pid = unsafe { libc::getpid() };
+    unsafe { libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t) }
+}
+
+/// Set the priority of a streaming thread when it starts
+///
+/// `StreamStatus` messages of type `Enter` are posted from the new thread,
+/// so this must be called from a bus sync handler.  Streaming threads come
+/// from a shared pool, so a thread reused from another flow is reset to the
+/// default priority when this flow has none.
+fn set_thread_priority(idx: usize, msg: &Message, priority: Option<i32>) {
+    if let MessageView::StreamStatus(status) = msg.view() {
+        if let (StreamStatusType::Enter, owner) = status.get() {
+            let priority = priority.unwrap_or_else(default_priority);
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+            let res = unsafe {
+                libc::setpriority(
+                    libc::PRIO_PROCESS,
+                    tid as libc::id_t,
+                    priority,
+                )
+            };
+            if res < 0 {
+                warn!(
+                    flow = idx;
+                    "Flow{}: thread priority {} ({}) -- {}",
+                    idx,
+                    priority,
+                    owner.get_name(),
+                    std::io::Error::last_os_error()
+                );
+            } else {
+                trace!(
+                    flow = idx;
+                    "Flow{}: thread priority {} ({})",
+                    idx,
+                    priority,
+                    owner.get_name()
+                );
+            }
+        }
+    }
+}
+
+/// Get the name of the element which posted a message
+fn msg_src_name(msg: &Message) -> String {
+    match msg.get_src() {
+        Some(src) => src.get_name().to_string(),
+        None => String::from("(unknown)"),
+    }
+}
+
+/// Set the state of a pipeline, logging any failure
+fn set_state(flow: &dyn fmt::Display, pipeline: &Pipeline, state: State) {
+    if let Err(e) = pipeline.set_state(state) {
+        error!("{}: set state {:?} -- {}", flow, state, e);
+    }
+}
+
+/// Check if a string is a valid host name
+fn is_hostname(host: &str) -> bool {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    host.len() <= 253
+        && host.split('.').all(valid_label)
+        && !host
+            .rsplit('.')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .all(|c| c.is_ascii_digit())
+}
+
+/// Check if a string is a valid proxy URI (`http://[user:pass@]host[:port]`)
+fn is_proxy_uri(uri: &str) -> bool {
+    let authority = match uri
+        .strip_prefix("http://")
+        .or_else(|| uri.strip_prefix("https://"))
+    {
+        Some(rest) => rest.trim_end_matches('/'),
+        None => return false,
+    };
+    let host_port = authority.rsplit('@').next().unwrap_or_default();
+    let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+        match bracketed.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return false,
+            },
+            None => return false,
+        }
+    } else {
+        match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        }
+    };
+    let valid_port = match port {
+        Some(port) => port.parse::<u16>().is_ok(),
+        None => true,
+    };
+    valid_port && (host.parse::<IpAddr>().is_ok() || is_hostname(host))
+}
+
+/// Get the element name within a flow pipeline (`m{idx}_{name}`)
+fn elem_name(idx: usize, name: &str) -> String {
+    format!("m{}_{}", idx, name)
+}
+
+/// Get video width, height and framerate from caps
+fn video_caps(caps: &Caps) -> Option<(u32, u32, Fraction)> {
+    let s = caps.get_structure(0)?;
+    let width = s.get_some::<i32>("width").ok()?;
+    let height = s.get_some::<i32>("height").ok()?;
+    let framerate = s.get_some::<Fraction>("framerate").ok()?;
+    Some((
+        u32::try_from(width).ok()?,
+        u32::try_from(height).ok()?,
+        framerate,
+    ))
+}
+
+/// Get the element name for a sink branch (first branch has no suffix)
+fn branch_name(name: &str, i: usize) -> String {
+    if i > 0 {
+        format!("{}{}", name, i)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Link ghost pad with sink
+fn link_ghost_pad(idx: usize, src: &Element, src_pad: &Pad, sink: Element) {
+    match sink.get_static_pad("sink") {
+        Some(sink_pad) => {
+            let pn = src_pad.get_name();
+            let p0 = src.get_name();
+            let p1 = sink.get_name();
+            match src_pad.link(&sink_pad) {
+                Ok(_) => {
+                    trace!(
+                        flow = idx;
+                        "Flow{} pad {} linked: {} => {}",
+                        idx, pn, p0, p1
+                    );
+                },
+                Err(_) => {
+                    debug!(
+                        flow = idx;
+                        "Flow{} pad {} not linked: {} => {}",
+                        idx, pn, p0, p1
+                    );
+                },
+            }
+        },
+        None => error!(flow = idx; "Flow{}: no sink pad", idx),
+    }
+}
+
+impl Default for AspectRatio {
+    fn default() -> Self {
+        AspectRatio::PRESERVE
+    }
+}
+
+impl Default for StuckAction {
+    fn default() -> Self {
+        StuckAction::RESTART
+    }
+}
+
+impl FromStr for StuckAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RESTART" => Ok(Self::RESTART),
+            "WARN" => Ok(Self::WARN),
+            "FLUSH" => Ok(Self::FLUSH),
+            _ => Err(Error::Other("invalid stuck action")),
+        }
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "FILL" => Ok(Self::FILL),
+            "PRESERVE" => Ok(Self::PRESERVE),
+            _ => Err(Error::Other("invalid aspect ratio")),
+        }
+    }
+}
+
+impl Default for Acceleration {
+    fn default() -> Self {
+        Acceleration::NONE
+    }
+}
+
+impl FromStr for Acceleration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "NONE" => Ok(Self::NONE),
+            "VAAPI" => Ok(Self::VAAPI),
+            "OMX" => Ok(Self::OMX),
+            _ => Err(Error::Other("invalid acceleration")),
+        }
+    }
+}
+
+impl Default for WindowSink {
+    fn default() -> Self {
+        WindowSink::AUTO
+    }
+}
+
+impl FromStr for WindowSink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "AUTO" => Ok(Self::AUTO),
+            "GTK" => Ok(Self::GTK),
+            "VAAPI" => Ok(Self::VAAPI),
+            "WAYLAND" => Ok(Self::WAYLAND),
+            "GL" => Ok(Self::GL),
+            "XV" => Ok(Self::XV),
+            _ => Err(Error::Other("invalid window sink")),
+        }
+    }
+}
+
+impl GlShare {
+    /// Create a new shared GL display and context
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the shared (display, application) contexts, creating if needed
+    fn contexts(&self) -> Option<(Context, Context)> {
+        let mut contexts = self.contexts.lock().unwrap();
+        if contexts.is_none() {
+            match create_gl_contexts() {
+                Ok(ctx) => *contexts = Some(ctx),
+                Err(e) => warn!("Shared GL context: {}", e),
+            }
+        }
+        contexts.clone()
+    }
+
+    /// Handle a bus message on the streaming thread
+    ///
+    /// `NeedContext` must be answered synchronously, before the element
+    /// creates its own display or context.
+    fn handle_sync(&self, msg: &Message) {
+        if let MessageView::NeedContext(need) = msg.view() {
+            let ctx = match need.get_context_type() {
+                GL_DISPLAY_CONTEXT => self.contexts().map(|c| c.0),
+                GL_APP_CONTEXT => self.contexts().map(|c| c.1),
+                _ => return,
+            };
+            let elem = msg.get_src().and_then(|s| s.downcast::<Element>().ok());
+            if let (Some(ctx), Some(elem)) = (ctx, elem) {
+                debug!(
+                    "{} shared with {}",
+                    ctx.get_context_type(),
+                    elem.get_name()
+                );
+                elem.set_context(&ctx);
+            }
+        }
+    }
+}
+
+/// Create a GL display and context for sharing between window sinks
+fn create_gl_contexts() -> Result<(Context, Context), glib::Error> {
+    let display = GLDisplay::new();
+    let gl_context = GLContext::new(&display);
+    gl_context.create(None::<&GLContext>)?;
+    let display_ctx = Context::new(GL_DISPLAY_CONTEXT, true);
+    display_ctx.set_gl_display(&display);
+    let mut app_ctx = Context::new(GL_APP_CONTEXT, true);
+    app_ctx
+        .get_mut()
+        .unwrap()
+        .get_mut_structure()
+        .set("context", &gl_context);
+    Ok((display_ctx, app_ctx))
+}
+
+impl WindowSink {
+    /// Get the gstreamer factory name
+    fn factory_name(&self, acceleration: Acceleration) -> &'static str {
+        match (self, acceleration) {
+            (WindowSink::AUTO, Acceleration::VAAPI) => "vaapisink",
+            (WindowSink::AUTO, _) => "gtksink",
+            (WindowSink::GTK, _) => "gtksink",
+            (WindowSink::VAAPI, _) => "vaapisink",
+            (WindowSink::WAYLAND, _) => "waylandsink",
+            (WindowSink::GL, _) => "glimagesink",
+            (WindowSink::XV, _) => "xvimagesink",
+        }
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::ANY
+    }
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ANY" => Ok(Self::ANY),
+            "UDP" => Ok(Self::UDP),
+            "MCAST" => Ok(Self::MCAST),
+            "TCP" => Ok(Self::TCP),
+            _ => Err(Error::Other("invalid transport")),
+        }
+    }
+}
+
+impl Scheme {
+    /// Get the scheme of a source location
+    fn from_location(location: &str) -> Option<Self> {
+        if location.is_empty()
+            || location == "test"
+            || location.starts_with("test:")
+        {
+            Some(Scheme::Test)
+        } else if location.starts_with("udp://") {
+            Some(Scheme::Udp)
+        } else if location.starts_with("rtsp://") {
+            Some(Scheme::Rtsp)
+        } else if location.starts_with("rtsps://") {
+            Some(Scheme::Rtsps)
+        } else if location.starts_with("http://") {
+            Some(Scheme::Http)
+        } else if location.starts_with("https://") {
+            Some(Scheme::Https)
+        } else if location.starts_with("file://") {
+            Some(Scheme::File)
+        } else if location.starts_with("v4l2://") {
+            Some(Scheme::V4l2)
+        } else {
+            None
+        }
+    }
+
+    /// Get the default encoding for a location with this scheme
+    fn default_encoding(self, location: &str) -> Encoding {
+        let path = location.split('?').next().unwrap_or(location);
+        let file = path.rsplit('/').next().unwrap_or(path);
+        let ext = match file.rsplit_once('.') {
+            Some((_, ext)) => ext.to_ascii_lowercase(),
+            None => String::new(),
+        };
+        match self {
+            Scheme::Test | Scheme::V4l2 => Encoding::RAW,
+            Scheme::Udp | Scheme::Rtsp | Scheme::Rtsps => Encoding::H264,
+            Scheme::Http | Scheme::Https => match ext.as_str() {
+                "png" => Encoding::PNG,
+                _ => Encoding::MJPEG,
+            },
+            Scheme::File => match ext.as_str() {
+                "png" => Encoding::PNG,
+                "jpg" | "jpeg" | "mjpg" | "mjpeg" => Encoding::MJPEG,
+                "ts" | "mpg" | "mpeg" => Encoding::MPEG2,
+                "m4v" => Encoding::MPEG4,
+                "h265" | "265" | "hevc" => Encoding::H265,
+                "ivf" => Encoding::VP8,
+                _ => Encoding::H264,
+            },
+        }
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::RAW
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "RAW" => Ok(Self::RAW),
+            "PNG" => Ok(Self::PNG),
+            "MJPEG" => Ok(Self::MJPEG),
+            "MPEG2" => Ok(Self::MPEG2),
+            "MPEG4" => Ok(Self::MPEG4),
+            "H264" | "" => Ok(Self::H264),
+            "H265" => Ok(Self::H265),
+            "VP8" => Ok(Self::VP8),
+            "VP9" => Ok(Self::VP9),
+            _ => Err(Error::Other("invalid encoding")),
+        }
+    }
+}
+
+impl FromStr for AudioEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PCMU" => Ok(Self::PCMU),
+            "PCMA" => Ok(Self::PCMA),
+            "OPUS" => Ok(Self::OPUS),
+            _ => Err(Error::Other("invalid audio encoding")),
+        }
+    }
+}
+
+impl AudioEncoding {
+    /// Get RTP depayload factory name
+    fn rtp_depay(&self) -> &'static str {
+        match self {
+            AudioEncoding::PCMU => "rtppcmudepay",
+            AudioEncoding::PCMA => "rtppcmadepay",
+            AudioEncoding::OPUS => "rtpopusdepay",
+        }
+    }
+
+    /// Get RTP payload factory name
+    fn rtp_pay(&self) -> &'static str {
+        match self {
+            AudioEncoding::PCMU => "rtppcmupay",
+            AudioEncoding::PCMA => "rtppcmapay",
+            AudioEncoding::OPUS => "rtpopuspay",
+        }
+    }
+
+    /// Get decoder factory name
+    fn decoder(&self) -> &'static str {
+        match self {
+            AudioEncoding::PCMU => "mulawdec",
+            AudioEncoding::PCMA => "alawdec",
+            AudioEncoding::OPUS => "opusdec",
+        }
+    }
+
+    /// Get encoder factory name
+    fn encoder(&self) -> &'static str {
+        match self {
+            AudioEncoding::PCMU => "mulawenc",
+            AudioEncoding::PCMA => "alawenc",
+            AudioEncoding::OPUS => "opusenc",
+        }
+    }
+
+    /// Get RTP encoding name for caps
+    fn rtp_encoding_name(&self) -> &'static str {
+        match self {
+            AudioEncoding::PCMU => "PCMU",
+            AudioEncoding::PCMA => "PCMA",
+            AudioEncoding::OPUS => "OPUS",
+        }
+    }
+
+    /// Get RTP clock rate
+    fn rtp_clock_rate(&self) -> i32 {
+        match self {
+            AudioEncoding::PCMU | AudioEncoding::PCMA => 8_000,
+            AudioEncoding::OPUS => 48_000,
+        }
+    }
+}
+
+impl Acceleration {
+    /// All acceleration methods
+    pub const ALL: &'static [Acceleration] =
+        &[Acceleration::NONE, Acceleration::VAAPI, Acceleration::OMX];
+}
+
+impl Encoding {
+    /// All video encodings
+    pub const ALL: &'static [Encoding] = &[
+        Encoding::RAW,
+        Encoding::PNG,
+        Encoding::MJPEG,
+        Encoding::MPEG2,
+        Encoding::MPEG4,
+        Encoding::H264,
+        Encoding::H265,
+        Encoding::VP8,
+        Encoding::VP9,
+        Encoding::AV1,
+    ];
+
+    /// Get factory names needed to encode (None if not supported)
+    pub fn encode_factories(
+        &self,
+        acceleration: Acceleration,
+    ) -> Option<&'static [&'static str]> {
+        match (self, acceleration) {
+            (Encoding::RAW, _) => Some(&[]),
+            (Encoding::PNG, _) => None,
+            (Encoding::MJPEG, _) => Some(&["jpegenc"]),
+            (Encoding::MPEG2, _) => Some(&["mpegtsmux", "mpeg2enc"]),
+            (Encoding::MPEG4, _) => Some(&["avenc_mpeg4"]),
+            (Encoding::H264, Acceleration::VAAPI) => Some(&["vaapih264enc"]),
+            (Encoding::H264, Acceleration::OMX) => Some(&["omxh264enc"]),
+            (Encoding::H264, _) => Some(&["x264enc"]),
+            (Encoding::H265, Acceleration::VAAPI) => Some(&["vaapih265enc"]),
+            (Encoding::H265, _) => Some(&["x265enc"]),
+            (Encoding::VP8, Acceleration::VAAPI) => Some(&["vaapivp8enc"]),
+            (Encoding::VP8, _) => Some(&["vp8enc"]),
+            (Encoding::VP9, Acceleration::VAAPI) => Some(&["vaapivp9enc"]),
+            (Encoding::VP9, _) => Some(&["vp9enc"]),
+            (Encoding::AV1, _) => Some(&["av1enc"]),
+        }
+    }
+
+    /// Get factory names needed to decode (None if not supported)
+    pub fn decode_factories(
+        &self,
+        acceleration: Acceleration,
+    ) -> Option<&'static [&'static str]> {
+        match (self, acceleration) {
+            (Encoding::RAW, _) => Some(&[]),
+            (Encoding::PNG, _) => {
+                Some(&["imagefreeze", "videoconvert", "pngdec"])
+            },
+            (Encoding::MJPEG, _) => Some(&["jpegdec"]),
+            (Encoding::MPEG2, _) => Some(&["mpeg2dec", "tsdemux"]),
+            (Encoding::MPEG4, _) => Some(&["avdec_mpeg4"]),
+            (Encoding::H264, Acceleration::VAAPI) => Some(&["vaapih264dec"]),
+            (Encoding::H264, Acceleration::OMX) => Some(&["omxh264dec"]),
+            (Encoding::H264, _) => Some(&["avdec_h264"]),
+            (Encoding::H265, Acceleration::VAAPI) => Some(&["vaapih265dec"]),
+            (Encoding::H265, _) => Some(&["libde265dec"]),
+            (Encoding::VP8, Acceleration::VAAPI) => Some(&["vaapivp8dec"]),
+            (Encoding::VP8, Acceleration::OMX) => Some(&["omxvp8dec"]),
+            (Encoding::VP8, _) => Some(&["vp8dec"]),
+            (Encoding::VP9, Acceleration::VAAPI) => Some(&["vaapivp9dec"]),
+            (Encoding::VP9, _) => Some(&["vp9dec"]),
+            (Encoding::AV1, _) => Some(&["av1dec"]),
+        }
+    }
+
+    /// Get RTP payload factory name (None if not supported)
+    pub fn pay_factory(&self) -> Option<&'static str> {
+        self.rtp_pay().ok()
+    }
+
+    /// Get RTP depayload factory name (None if not supported)
+    pub fn depay_factory(&self) -> Option<&'static str> {
+        self.rtp_depay().ok()
+    }
+
+    /// Get RTP encoding name for caps (None if not supported)
+    pub fn rtp_encoding_name(&self) -> Option<&'static str> {
+        match self {
+            Encoding::RAW => Some("RAW"),
+            Encoding::MJPEG => Some("JPEG"),
+            Encoding::MPEG2 => Some("MP2T"),
+            Encoding::MPEG4 => Some("MP4V-ES"),
+            Encoding::H264 => Some("H264"),
+            Encoding::H265 => Some("H265"),
+            Encoding::VP8 => Some("VP8"),
+            Encoding::VP9 => Some("VP9"),
+            Encoding::AV1 => Some("AV1"),
+            _ => None,
+        }
+    }
+
+    /// Get RTP depayload factory name
+    fn rtp_depay(&self) -> Result<&'static str, Error> {
+        match self {
+            Encoding::RAW => Ok("rtpvrawdepay"),
+            Encoding::MJPEG => Ok("rtpjpegdepay"),
+            Encoding::MPEG2 => Ok("rtpmp2tdepay"),
+            Encoding::MPEG4 => Ok("rtpmp4vdepay"),
+            Encoding::H264 => Ok("rtph264depay"),
+            Encoding::H265 => Ok("rtph265depay"),
+            Encoding::VP8 => Ok("rtpvp8depay"),
+            Encoding::VP9 => Ok("rtpvp9depay"),
+            _ => Err(Error::Other("invalid encoding for RTP")),
+        }
+    }
+    /// Get RTP payload factory name
+    fn rtp_pay(&self) -> Result<&'static str, Error> {
+        match self {
+            Encoding::RAW => Ok("rtpvrawpay"),
+            Encoding::MJPEG => Ok("rtpjpegpay"),
+            Encoding::MPEG2 => Ok("rtpmp2tpay"),
+            Encoding::MPEG4 => Ok("rtpmp4vpay"),
+            Encoding::H264 => Ok("rtph264pay"),
+            Encoding::H265 => Ok("rtph265pay"),
+            Encoding::VP8 => Ok("rtpvp8pay"),
+            Encoding::VP9 => Ok("rtpvp9pay"),
+            _ => Err(Error::Other("invalid encoding for RTP")),
+        }
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source {
+            location: String::new(),
+            rtsp_transport: Transport::default(),
+            encoding: Encoding::default(),
+            sprops: None,
+            rtp_caps: None,
+            clock_rate: RTP_VIDEO_CLOCK_RATE,
+            skip_first_bytes: 0,
+            audio: None,
+            timeout: DEFAULT_TIMEOUT_SEC,
+            connect_timeout: None,
+            latency: DEFAULT_LATENCY_MS,
+            tcp_latency: None,
+            max_dropout: DEFAULT_MAX_DROPOUT_MS,
+            tls_validation: true,
+            proxy: None,
+            ssl_strict: true,
+            headers: vec![],
+            refresh: DEFAULT_REFRESH_MS,
+            looping: false,
+            app_caps: None,
+        }
+    }
+}
+
+impl Source {
+    /// Create a source from a location URI
+    ///
+    /// The scheme selects the source type: `udp://`, `rtsp://`, `rtsps://`,
+    /// `http://`, `https://`, `file://`, `v4l2://`, or an empty location,
+    /// `test` or `test:` for a video test source.  The encoding defaults to H.264 for
+    /// RTP and RTSP, PNG for HTTP locations ending in `.png`, MJPEG for other
+    /// HTTP locations, and raw for test and V4L2 sources.  For files, it is
+    /// chosen by extension (H.264 if not known).  Use `with_encoding` to
+    /// override it.
+    pub fn from_uri(uri: &str) -> Result<Self, Error> {
+        match Scheme::from_location(uri) {
+            Some(scheme) => Ok(Source::default()
+                .with_location(uri)
+                .with_encoding(scheme.default_encoding(uri))),
+            None => Err(Error::Other("unsupported source scheme")),
+        }
+    }
+
+    /// Use the specified location
+    pub fn with_location(mut self, location: &str) -> Self {
+        self.location = location.to_string();
+        self
+    }
+
+    /// Use the specified transport
+    pub fn with_rtsp_transport(mut self, rtsp_transport: Transport) -> Self {
+        self.rtsp_transport = rtsp_transport;
+        self
+    }
+
+    /// Use the specified encoding
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Use the specified SDP properties
+    ///
+    /// For `udp://` H.264 sources without sprops, they are learned from
+    /// in-band SPS / PPS when first received.
+    pub fn with_sprops(mut self, sprops: Option<&str>) -> Self {
+        self.sprops = sprops.map(|s| s.to_string());
+        self
+    }
+
+    /// Use the specified audio encoding for an audio-only source
+    ///
+    /// Audio-only flows select the first audio stream from RTSP sources,
+    /// and have no video elements (overlays, crop and video encoding are
+    /// not available).  The sink must be RTP or fake.
+    pub fn with_audio_encoding(mut self, audio: Option<AudioEncoding>) -> Self {
+        self.audio = audio;
+        self
+    }
+
+    /// Use the specified RTP caps (`udp://` sources)
+    ///
+    /// The caps string replaces the default caps entirely, for example
+    /// `application/x-rtp, media=video, payload=96, clock-rate=90000,
+    /// encoding-name=H264`.  By default, `media`, `clock-rate` and
+    /// `encoding-name` are derived from the source encoding.
+    pub fn with_rtp_caps(mut self, rtp_caps: Option<&str>) -> Self {
+        self.rtp_caps = rtp_caps.map(|c| c.to_string());
+        self
+    }
+
+    /// Use the specified RTP video clock rate (Hz, `udp://` sources)
+    ///
+    /// The standard rate for video is 90 kHz (default), but some older
+    /// encoders use a different rate.
+    pub fn with_clock_rate(mut self, clock_rate: i32) -> Self {
+        self.clock_rate = clock_rate;
+        self
+    }
+
+    /// Skip bytes at the start of each packet (`udp://` sources)
+    ///
+    /// Some senders prepend a proprietary header to each RTP packet, which
+    /// must be stripped before depayloading.  Combine with `with_rtp_caps` to
+    /// declare the format of the remaining data.
+    pub fn with_skip_first_bytes(mut self, skip_first_bytes: u16) -> Self {
+        self.skip_first_bytes = skip_first_bytes;
+        self
+    }
+
+    /// Use the specified timeout (sec)
+    pub fn with_timeout(mut self, timeout: u16) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Use the specified initial connect timeout (sec)
+    ///
+    /// Until the flow first plays, this replaces the `with_timeout` value
+    /// for RTSP connection requests and periodic restarts, so cameras which
+    /// are slow to boot are not retried every few seconds.  Once connected,
+    /// the normal timeout is used.
+    pub fn with_connect_timeout(mut self, timeout: Option<u16>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Use the specified buffering latency (ms)
+    pub fn with_latency(mut self, latency: u32) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Use the specified buffering latency for RTSP over TCP (ms)
+    ///
+    /// TCP retransmits lost packets, so arrival is burstier and typically
+    /// needs more buffering than UDP.  Defaults to the `with_latency` value.
+    pub fn with_tcp_latency(mut self, latency: Option<u32>) -> Self {
+        self.tcp_latency = latency;
+        self
+    }
+
+    /// Use the specified maximum RTP dropout time (ms, `udp://` sources)
+    ///
+    /// This is the largest gap in packet timestamps which the jitter buffer
+    /// treats as loss rather than a stream discontinuity.  It is independent
+    /// of the timeout: a stalled source is still detected by the udpsrc
+    /// timeout and the periodic flow check, which restart the flow if no new
+    /// frames arrive within the timeout.
+    pub fn with_max_dropout(mut self, max_dropout: u32) -> Self {
+        self.max_dropout = max_dropout;
+        self
+    }
+
+    /// Use the specified TLS certificate validation (`rtsps://`)
+    ///
+    /// Disabling validation accepts self-signed certificates, which many
+    /// cameras ship with.  Expired and revoked certificates are still
+    /// rejected.
+    pub fn with_tls_validation(mut self, tls_validation: bool) -> Self {
+        self.tls_validation = tls_validation;
+        self
+    }
+
+    /// Use the specified HTTP proxy URI
+    pub fn with_proxy(mut self, proxy: Option<&str>) -> Self {
+        self.proxy = proxy.map(|p| p.to_string());
+        self
+    }
+
+    /// Use the specified strict HTTPS certificate checking
+    pub fn with_ssl_strict(mut self, ssl_strict: bool) -> Self {
+        self.ssl_strict = ssl_strict;
+        self
+    }
+
+    /// Add an extra HTTP request header
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Loop playback on end of stream, instead of stopping
+    ///
+    /// This is for non-live sources, such as a clip served over HTTP.  It
+    /// has no effect on RTP or RTSP sources.
+    pub fn with_loop(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Use the specified refresh interval for HTTP PNG sources (ms)
+    ///
+    /// The image is fetched again at this interval, replacing the frozen
+    /// frame.  Zero fetches it only once.
+    pub fn with_refresh(mut self, refresh: u32) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Use an application source (`appsrc`) with the specified caps
+    ///
+    /// The location is ignored; samples are fed with `Flow::push_sample`.
+    /// The caps must describe the pushed samples, and the encoding must match
+    /// (`RAW` for `video/x-raw`).  Samples are timestamped when pushed.
+    pub fn with_app_caps(mut self, caps: Option<&str>) -> Self {
+        self.app_caps = caps.map(|c| c.to_string());
+        self
+    }
+
+    /// Get timeout as seconds
+    fn timeout_s(&self) -> u32 {
+        u32::from(self.timeout)
+    }
+
+    /// Get timeout as milliseconds
+    fn timeout_ms(&self) -> u32 {
+        u32::from(self.timeout) * 1_000
+    }
+
+    /// Get timeout as microseconds
+    fn timeout_us(&self) -> u64 {
+        u64::from(self.timeout) * SEC_US
+    }
+
+    /// Get timeout as nanoseconds
+    fn timeout_ns(&self) -> u64 {
+        u64::from(self.timeout) * SEC_NS
+    }
+
+    /// Get initial connect timeout as microseconds
+    fn connect_timeout_us(&self) -> u64 {
+        u64::from(self.connect_timeout.unwrap_or(self.timeout)) * SEC_US
+    }
+
+    /// Get count of flow checks to allow for initial connect
+    fn connect_checks(&self) -> usize {
+        match self.connect_timeout {
+            Some(t) if self.timeout > 0 => {
+                usize::from(t).div_ceil(usize::from(self.timeout))
+            },
+            _ => 0,
+        }
+    }
+
+    /// Get buffering latency for the transport (ms)
+    fn latency_ms(&self) -> u32 {
+        match (self.rtsp_transport, self.tcp_latency) {
+            (Transport::TCP, Some(latency)) if self.is_rtsp() => latency,
+            _ => self.latency,
+        }
+    }
+
+    /// Check if source is fed by the application
+    fn is_app(&self) -> bool {
+        self.app_caps.is_some()
+    }
+
+    /// Get the location scheme
+    fn scheme(&self) -> Option<Scheme> {
+        Scheme::from_location(&self.location)
+    }
+
+    /// Check if source is RTP
+    fn is_rtp(&self) -> bool {
+        self.scheme() == Some(Scheme::Udp)
+    }
+
+    /// Check if source is RTSP
+    fn is_rtsp(&self) -> bool {
+        self.scheme() == Some(Scheme::Rtsp) || self.is_rtsps()
+    }
+
+    /// Check if source is RTSP over TLS
+    fn is_rtsps(&self) -> bool {
+        self.scheme() == Some(Scheme::Rtsps)
+    }
+
+    /// Check if source is RTP or RTSP
+    fn is_rtp_or_rtsp(&self) -> bool {
+        self.is_rtp() || self.is_rtsp()
+    }
+
+    /// Check if source is HTTP
+    fn is_http(&self) -> bool {
+        match self.scheme() {
+            Some(Scheme::Http) | Some(Scheme::Https) => true,
+            _ => false,
+        }
+    }
+
+    /// Check if source should loop on end of stream
+    ///
+    /// RTP and RTSP sources never loop, since end of stream means the live
+    /// stream has ended.
+    fn is_looping(&self) -> bool {
+        self.looping && !self.is_rtp_or_rtsp()
+    }
+
+    /// Check if source is not live, and may pause for buffering
+    fn is_buffered(&self) -> bool {
+        self.is_http() && !self.is_app()
+    }
+
+    /// Get PNG refresh interval (ms), if refreshing
+    fn refresh_ms(&self) -> Option<u32> {
+        match (self.is_http(), self.encoding, self.refresh) {
+            (true, Encoding::PNG, refresh) if refresh > 0 => Some(refresh),
+            _ => None,
+        }
+    }
+}
+
+impl AspectRatio {
+    /// Get as boolean value
+    fn as_bool(&self) -> bool {
+        match self {
+            AspectRatio::FILL => false,
+            AspectRatio::PRESERVE => true,
+        }
+    }
+}
+
+impl Default for MatrixCrop {
+    fn default() -> Self {
+        MatrixCrop {
+            aspect: AspectRatio::default(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            hgap: 0,
+            vgap: 0,
+        }
+    }
+}
+
+impl TryFrom<&str> for MatrixCrop {
+    type Error = Error;
+
+    fn try_from(v: &str) -> Result<Self, Self::Error> {
+        let p: Vec<_> = v.split(',').collect();
+        if p.len() == 3 {
+            let mut crop = p[0].chars();
+            let x = MatrixCrop::position(crop.next().unwrap_or_default())?;
+            let y = MatrixCrop::position(crop.next().unwrap_or_default())?;
+            let width =
+                MatrixCrop::position(crop.next().unwrap_or_default())? + 1;
+            let height =
+                MatrixCrop::position(crop.next().unwrap_or_default())? + 1;
+            let hgap: u32 = p[1].parse()?;
+            let vgap: u32 = p[2].parse()?;
+            return MatrixCrop::new(x, y, width, height)?.with_gaps(hgap, vgap);
+        }
+        Err(Error::InvalidCrop())
+    }
+}
+
+impl MatrixCrop {
+    /// Percent of window, in hundredths
+    const PERCENT: u32 = 100_00;
+
+    /// Maximum width / height of matrix
+    const MAX_SIZE: u8 = 8;
+
+    /// Create a new matrix crop
+    ///
+    /// * `x` Position `0..width` in matrix
+    /// * `y` Position `0..height` in matrix
+    /// * `width` Width `1..=8` of matrix
+    /// * `height` Height `1..=8` of matrix
+    pub fn new(x: u8, y: u8, width: u8, height: u8) -> Result<Self, Error> {
+        if x < width
+            && y < height
+            && width <= MatrixCrop::MAX_SIZE
+            && height <= MatrixCrop::MAX_SIZE
+        {
+            Ok(MatrixCrop {
+                x,
+                y,
+                width,
+                height,
+                ..Default::default()
+            })
+        } else {
+            Err(Error::InvalidCrop())
+        }
+    }
+
+    /// Use the specified gaps at edges
+    ///
+    /// Values are in hundredths of percent of window, up to `50.00`.
+    pub fn with_gaps(mut self, hgap: u32, vgap: u32) -> Result<Self, Error> {
+        // Don't allow more than 50% gap
+        if hgap <= MatrixCrop::PERCENT / 2 && vgap <= MatrixCrop::PERCENT / 2 {
+            self.hgap = hgap;
+            self.vgap = vgap;
+            Ok(self)
+        } else {
+            Err(Error::InvalidCrop())
+        }
+    }
+
+    /// Use the specified aspect ratio setting
+    pub fn with_aspect(mut self, aspect: AspectRatio) -> Self {
+        self.aspect = aspect;
+        self
+    }
+
+    /// Get a matrix position from a crop code
+    fn position(c: char) -> Result<u8, Error> {
+        match c {
+            'A'..='H' => Ok(c as u8 - b'A'),
+            _ => Err(Error::InvalidCrop()),
+        }
+    }
+
+    /// Check if window is cropped
+    fn is_cropped(&self) -> bool {
+        self.width > 1 || self.height > 1
+    }
+
+    /// Get number of pixels to crop from top edge
+    fn top(&self, height: u32) -> u32 {
+        let num = u32::from(self.y);
+        let den = u32::from(self.height);
+        let pix = height * num / den;
+        let gap = height * self.vgap / (den * MatrixCrop::PERCENT * 2);
+        trace!("crop top: {} + {} = {}", pix, gap, pix + gap);
+        pix + gap
+    }
+
+    /// Get number of pixels to crop from bottom edge
+    fn bottom(&self, height: u32) -> u32 {
+        let num = u32::from(self.height - self.y - 1);
+        let den = u32::from(self.height);
+        let pix = height * num / den;
+        let gap = height * self.vgap / (den * MatrixCrop::PERCENT * 2);
+        trace!("crop bottom: {} + {} = {}", pix, gap, pix + gap);
+        pix + gap
+    }
+
+    /// Get number of pixels to crop from left edge
+    fn left(&self, width: u32) -> u32 {
+        let num = u32::from(self.x);
+        let den = u32::from(self.width);
+        let pix = width * num / den;
+        let gap = width * self.hgap / (den * MatrixCrop::PERCENT * 2);
+        trace!("crop left: {} + {} = {}", pix, gap, pix + gap);
+        pix + gap
+    }
+
+    /// Get number of pixels to crop from right edge
+    fn right(&self, width: u32) -> u32 {
+        let num = u32::from(self.width - self.x - 1);
+        let den = u32::from(self.width);
+        let pix = width * num / den;
+        let gap = width * self.hgap / (den * MatrixCrop::PERCENT * 2);
+        trace!("crop right: {} + {} = {}", pix, gap, pix + gap);
+        pix + gap
+    }
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink::FAKE
+    }
+}
+
+impl Sink {
+    /// Is the sink RTP?
+    fn is_rtp(&self) -> bool {
         match self {
-            Encoding::RAW => Ok("rtpvrawdepay"),
-            Encoding::MJPEG => Ok("rtpjpegdepay"),
-            Encoding::MPEG2 => Ok("rtpmp2tdepay"),
-            Encoding::MPEG4 => Ok("rtpmp4vdepay"),
-            Encoding::H264 => Ok("rtph264depay"),
-            Encoding::H265 => Ok("rtph265depay"),
-            Encoding::VP8 => Ok("rtpvp8depay"),
-            Encoding::VP9 => Ok("rtpvp9depay"),
-            _ => Err(Error::Other("invalid encoding for RTP")),
+            Sink::RTP(_, _, _, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Is the sink HTTP?
+    fn is_http(&self) -> bool {
+        match self {
+            Sink::HTTP(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Is the sink WebRTC?
+    fn is_webrtc(&self) -> bool {
+        match self {
+            Sink::WEBRTC(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Is the sink HLS?
+    fn is_hls(&self) -> bool {
+        match self {
+            Sink::HLS(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Is the sink a pipe?
+    fn is_pipe(&self) -> bool {
+        match self {
+            Sink::PIPE(_, _) => true,
+            _ => false,
         }
     }
-    /// Get RTP payload factory name
-    fn rtp_pay(&self) -> Result<&'static str, Error> {
-        match self {
-            Encoding::RAW => Ok("rtpvrawpay"),
-            Encoding::MJPEG => Ok("rtpjpegpay"),
-            Encoding::MPEG2 => Ok("rtpmp2tpay"),
-            Encoding::MPEG4 => Ok("rtpmp4vpay"),
-            Encoding::H264 => Ok("rtph264pay"),
-            Encoding::H265 => Ok("rtph265pay"),
-            Encoding::VP8 => Ok("rtpvp8pay"),
-            Encoding::VP9 => Ok("rtpvp9pay"),
-            _ => Err(Error::Other("invalid encoding for RTP")),
-        }
+
+    /// Get the gstreamer factory name
+    fn factory_name(
+        &self,
+        acceleration: Acceleration,
+        window_sink: WindowSink,
+    ) -> &'static str {
+        match self {
+            Sink::FAKE => "fakesink",
+            Sink::RTP(_, _, _, _) => "udpsink",
+            Sink::WEBRTC(_, _) => "webrtcbin",
+            Sink::HTTP(_, _) => "multifdsink",
+            Sink::HLS(_) => "hlssink2",
+            Sink::PIPE(_, _) => "fdsink",
+            Sink::WINDOW(_) => window_sink.factory_name(acceleration),
+        }
+    }
+
+    /// Get the matrix crop setting
+    fn crop(&self) -> MatrixCrop {
+        match self {
+            Sink::WINDOW(c) => *c,
+            _ => MatrixCrop::default(),
+        }
+    }
+
+    /// Get the sink encoding
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            Sink::RTP(_, _, encoding, _) => *encoding,
+            Sink::WEBRTC(encoding, _) => *encoding,
+            Sink::HTTP(_, _) => Encoding::MJPEG,
+            Sink::HLS(_) => Encoding::H264,
+            Sink::PIPE(_, encoding) => *encoding,
+            _ => Encoding::RAW,
+        }
+    }
+
+    /// Should config be inserted in-band?
+    fn insert_config(&self) -> bool {
+        match self {
+            Sink::RTP(_, _, _, true) => true,
+            // WebRTC peers may join at any time
+            Sink::WEBRTC(_, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Destination {
+    /// Create a new RTP destination
+    pub fn new(address: &str, port: i32) -> Self {
+        Destination {
+            address: address.to_string(),
+            port,
+            ttl: None,
+            iface: None,
+        }
+    }
+
+    /// Use the specified multicast TTL
+    pub fn with_ttl(mut self, ttl: Option<i32>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Use the specified multicast interface
+    pub fn with_iface(mut self, iface: Option<&str>) -> Self {
+        self.iface = iface.map(|i| i.to_string());
+        self
+    }
+
+    /// Check if the address is multicast
+    fn is_multicast(&self) -> bool {
+        match self.address.parse::<IpAddr>() {
+            Ok(addr) => addr.is_multicast(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for Rendition {
+    type Err = Error;
+
+    /// Parse a rendition (`WIDTHxHEIGHT@KBPS`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rb = s.splitn(2, '@');
+        let mut wh = rb.next().unwrap_or_default().splitn(2, 'x');
+        if let (Some(w), Some(h), Some(b)) = (wh.next(), wh.next(), rb.next()) {
+            let width = w.parse()?;
+            let height = h.parse()?;
+            let bitrate = b.parse()?;
+            if width > 0 && height > 0 && bitrate > 0 {
+                return Ok(Rendition::new(width, height, bitrate));
+            }
+        }
+        Err(Error::Other("invalid rendition"))
+    }
+}
+
+impl Rendition {
+    /// Create a new rendition (width, height, bitrate in kbps)
+    pub fn new(width: i32, height: i32, bitrate: u32) -> Self {
+        Rendition {
+            width,
+            height,
+            bitrate,
+        }
+    }
+
+    /// Get the sub-directory for a rendition index
+    fn dir(k: usize) -> String {
+        format!("r{}", k)
+    }
+
+    /// Get the master playlist entry for a rendition index
+    fn playlist_entry(&self, k: usize) -> String {
+        format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}\n{}/index.m3u8\n",
+            u64::from(self.bitrate) * 1000,
+            self.width,
+            self.height,
+            Rendition::dir(k),
+        )
+    }
+}
+
+impl fmt::Display for FlowBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_flow(f, self.idx, &self.name)
+    }
+}
+
+impl FlowBuilder {
+    /// Create a new flow builder
+    pub fn new(idx: usize) -> Self {
+        FlowBuilder {
+            idx,
+            ticker_speed: TICKER_SPEED,
+            overlay_file_ms: OVERLAY_FILE_POLL_MS,
+            alert_intervals: ALERT_INTERVALS,
+            low_latency: true,
+            ..Default::default()
+        }
+    }
+
+    /// Use the specified element factory
+    #[cfg(test)]
+    fn with_elements(mut self, elements: Box<dyn Elements>) -> Self {
+        self.elements = elements;
+        self
+    }
+
+    /// Use the specified flow name
+    ///
+    /// The name is included with the index in log messages.
+    pub fn with_name(mut self, name: Option<&str>) -> Self {
+        self.name = name.map(|n| n.to_string());
+        self
+    }
+
+    /// Use the specified source
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Use the specified sink
+    pub fn with_sink(mut self, sink: Sink) -> Self {
+        self.sinks = vec![sink];
+        self
+    }
+
+    /// Use the specified sinks
+    ///
+    /// With more than one sink, a `tee` splits the flow after decoding and
+    /// text overlays.  Each sink branch has its own encoder, if needed.
+    pub fn with_sinks(mut self, sinks: Vec<Sink>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Use the specified video acceleration
+    pub fn with_acceleration(mut self, acceleration: Acceleration) -> Self {
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Allow software fallback when hardware elements are missing
+    ///
+    /// By default, a missing hardware encoder or decoder is an error.  With
+    /// fallback, the software element is used instead, and a
+    /// `Feedback::Fallback` is sent.
+    pub fn with_software_fallback(mut self, fallback: bool) -> Self {
+        self.software_fallback = fallback;
+        self
+    }
+
+    /// Use the specified overlay text
+    pub fn with_overlay_text(mut self, overlay_text: Option<&str>) -> Self {
+        self.overlay_text = overlay_text.map(|t| t.to_string());
+        self
+    }
+
+    /// Use overlay text read from a file
+    ///
+    /// The file is re-read periodically, and the overlay updated live when
+    /// its contents change.  If it is missing or unreadable, the last text
+    /// is kept (initially, the overlay text, if any).
+    pub fn with_overlay_file(mut self, path: Option<&str>) -> Self {
+        self.overlay_file = path.map(|p| p.to_string());
+        self
+    }
+
+    /// Use the specified interval to re-read the overlay text file (ms)
+    pub fn with_overlay_file_interval(mut self, ms: Option<u32>) -> Self {
+        self.overlay_file_ms = match ms {
+            Some(ms) if ms > 0 => ms,
+            _ => OVERLAY_FILE_POLL_MS,
+        };
+        self
+    }
+
+    /// Use the specified statistics overlay format
+    ///
+    /// Packet statistics are displayed in the bottom-left corner, updating
+    /// live.  These placeholders are replaced with packet counts:
+    /// `{pushed}`, `{lost}` and `{late}` (for example `"loss {lost} late
+    /// {late}"`).  Lost and late packets are only available for RTP sources.
+    /// Egress counts `{buffers}` and `{bytes}`, ingress `{received}` bytes,
+    /// `{dropped}` frames and measured `{latency}` (ms) are available for
+    /// all flows.  Average `{jitter}` and round-trip time `{rtt}` (us) are
+    /// shown for RTP and RTSP sources when available, otherwise `-`.
+    pub fn with_stats_overlay(mut self, stats_format: Option<&str>) -> Self {
+        self.stats_format = stats_format.map(|t| t.to_string());
+        self
+    }
+
+    /// Use the specified overlay font size (pt at reference height)
+    ///
+    /// The default is 14 pt.  Applies to all text overlays.
+    pub fn with_font_size(mut self, size: Option<u32>) -> Self {
+        self.font.size = size.unwrap_or(FONT_SZ);
+        self
+    }
+
+    /// Use the specified reference height for font scaling (px)
+    ///
+    /// Font size is scaled in proportion to the frame height relative to
+    /// this height (default 240).  Zero uses the font size unscaled.
+    pub fn with_font_height(mut self, height: Option<u32>) -> Self {
+        self.font.height = height.unwrap_or(DEFAULT_HEIGHT);
+        self
+    }
+
+    /// Use the specified overlay text color (ARGB)
+    ///
+    /// Applies to all text overlays.  The default is a yellowish white.
+    pub fn with_overlay_color(mut self, color: Option<u32>) -> Self {
+        self.overlay_color = color;
+        self
     }
-}
 
-impl Default for Source {
-    fn default() -> Self {
-        Source {
-            location: String::new(),
-            rtsp_transport: Transport::default(),
-            encoding: Encoding::default(),
-            sprops: None,
-            timeout: DEFAULT_TIMEOUT_SEC,
-            latency: DEFAULT_LATENCY_MS,
-        }
+    /// Use a shaded background behind overlay text
+    ///
+    /// Shading ranges from 1 (nearly transparent) to 255 (black); zero or
+    /// `None` disables the background.  Applies to all text overlays.
+    pub fn with_overlay_background(mut self, shading: Option<u8>) -> Self {
+        self.overlay_shading = shading.filter(|s| *s > 0);
+        self
     }
-}
 
-impl Source {
-    /// Use the specified location
-    pub fn with_location(mut self, location: &str) -> Self {
-        self.location = location.to_string();
+    /// Use a timestamp overlay
+    ///
+    /// The time of each frame is displayed in UTC with millisecond
+    /// resolution.  It is the pipeline clock time when the frame is rendered
+    /// (base time plus running time of the buffer PTS), not the time it was
+    /// captured by the camera; the difference is roughly the source latency
+    /// plus network delay.  The system clock is used by default, in realtime
+    /// mode, so accuracy depends on how well the host is synchronized (NTP
+    /// on a LAN is typically within a few milliseconds).  See
+    /// [FlowBuilder::with_clock] to use another clock.
+    pub fn with_timestamp_overlay(mut self, timestamp: bool) -> Self {
+        self.timestamp = timestamp;
         self
     }
 
-    /// Use the specified transport
-    pub fn with_rtsp_transport(mut self, rtsp_transport: Transport) -> Self {
-        self.rtsp_transport = rtsp_transport;
+    /// Use the specified pipeline clock
+    ///
+    /// A network-synced clock, such as a `GstNtpClock` from the gstreamer
+    /// net library, may be used for timestamp overlays.  It must report
+    /// time since either the Unix or NTP epoch.  When a clock is specified,
+    /// its offset from the local system time is displayed after the
+    /// timestamp.  The offset is only as accurate as the clock itself,
+    /// which for a `GstNtpClock` is limited by round-trip jitter to the
+    /// server.
+    pub fn with_clock(mut self, clock: Option<Clock>) -> Self {
+        self.clock = clock;
         self
     }
 
-    /// Use the specified encoding
-    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
-        self.encoding = encoding;
+    /// Use a banner overlay, with text updates from a channel
+    ///
+    /// The banner is displayed across the bottom of the frame.  Unlike
+    /// overlay text, it is intended for frequent updates, such as incident
+    /// text received from another system.  When several updates are queued,
+    /// only the latest is displayed.
+    pub fn with_banner(mut self, banner: Option<Receiver<String>>) -> Self {
+        self.banner = banner;
         self
     }
 
-    /// Use the specified SDP properties
-    pub fn with_sprops(mut self, sprops: Option<&str>) -> Self {
-        self.sprops = sprops.map(|s| s.to_string());
+    /// Use a ticker overlay, scrolling text across the bottom of the frame
+    ///
+    /// The text and speed can be changed while playing with
+    /// `Flow::update_ticker`.
+    pub fn with_ticker(mut self, ticker: Option<&str>) -> Self {
+        self.ticker = ticker.map(|t| t.to_string());
         self
     }
 
-    /// Use the specified timeout (sec)
-    pub fn with_timeout(mut self, timeout: u16) -> Self {
-        self.timeout = timeout;
+    /// Use the specified ticker speed (px per second, using default height)
+    ///
+    /// The speed is scaled with the frame height, like the font size.
+    pub fn with_ticker_speed(mut self, speed: Option<u32>) -> Self {
+        self.ticker_speed = speed.unwrap_or(TICKER_SPEED);
         self
     }
 
-    /// Use the specified buffering latency (ms)
-    pub fn with_latency(mut self, latency: u32) -> Self {
-        self.latency = latency;
+    /// Use the specified maximum sink bitrate (bits per second)
+    ///
+    /// This limits the rate at which the RTP sink sends packets on the wire,
+    /// including packet overhead.  It is distinct from the encoder bitrate.
+    /// Bursts (such as large I-frames) are delayed to stay under the limit,
+    /// so buffers may be dropped if the average rate is too high.
+    pub fn with_max_bitrate(mut self, max_bitrate: Option<u64>) -> Self {
+        self.max_bitrate = max_bitrate;
         self
     }
 
-    /// Get timeout as seconds
-    fn timeout_s(&self) -> u32 {
-        u32::from(self.timeout)
+    /// Use the specified sink socket send buffer size (bytes, RTP only)
+    ///
+    /// A larger buffer (`SO_SNDBUF`) can absorb bursts from large I-frames.
+    /// The kernel limits the size to `net.core.wmem_max`, so that may need to
+    /// be raised with sysctl.  Receivers may also need `net.core.rmem_max`
+    /// raised for their receive buffers.
+    pub fn with_sink_buffer_size(mut self, buffer_size: Option<u32>) -> Self {
+        self.sink_buffer_size = buffer_size;
+        self
     }
 
-    /// Get timeout as milliseconds
-    fn timeout_ms(&self) -> u32 {
-        u32::from(self.timeout) * 1_000
+    /// Use the specified clock sync on sinks
+    ///
+    /// With sync disabled, buffers are rendered as soon as they arrive, and
+    /// are never dropped for being late; this suits low-latency live flows.
+    /// By default, each sink keeps its own behavior (HTTP sinks don't sync).
+    pub fn with_sync(mut self, sync: Option<bool>) -> Self {
+        self.sync = sync;
+        self
     }
 
-    /// Get timeout as microseconds
-    fn timeout_us(&self) -> u64 {
-        u64::from(self.timeout) * SEC_US
+    /// Use the specified maximum lateness on sinks (ms, -1 for unlimited)
+    ///
+    /// Synced sinks drop buffers later than this, and report them in QoS
+    /// messages as dropped frames.  By default, each sink keeps its own
+    /// setting (20 ms for video sinks, unlimited for others).
+    pub fn with_max_lateness(mut self, max_lateness: Option<i64>) -> Self {
+        self.max_lateness = max_lateness;
+        self
     }
 
-    /// Get timeout as nanoseconds
-    fn timeout_ns(&self) -> u64 {
-        u64::from(self.timeout) * SEC_NS
+    /// Use the specified in-band parameter set interval (sec)
+    ///
+    /// This applies to RTP sinks inserting config in-band.  For H.264 and
+    /// H.265, `-1` sends SPS / PPS with every IDR frame (default), and a
+    /// positive value sends them every N seconds.  MPEG-4 sends config once
+    /// per second by default, and does not support `-1`.  Zero disables it.
+    pub fn with_config_interval(
+        mut self,
+        config_interval: Option<i32>,
+    ) -> Self {
+        self.config_interval = config_interval;
+        self
     }
 
-    /// Check if source is RTP
-    fn is_rtp(&self) -> bool {
-        self.location.starts_with("udp://")
+    /// Use the specified H.264 stream format and alignment for RTP sinks
+    ///
+    /// When either is set, an `h264parse` converts the stream before the
+    /// payloader, for receivers which only accept one format.  RTP is then
+    /// never passed through unchanged.  By default, the payloader's
+    /// preferred format is negotiated.
+    pub fn with_h264_format(
+        mut self,
+        stream_format: Option<&str>,
+        alignment: Option<&str>,
+    ) -> Self {
+        self.h264_stream_format = stream_format.map(|f| f.to_string());
+        self.h264_alignment = alignment.map(|a| a.to_string());
+        self
     }
 
-    /// Check if source is RTSP
-    fn is_rtsp(&self) -> bool {
-        self.location.starts_with("rtsp://")
+    /// Use automatic multicast group join on RTP sinks (enabled by default)
+    ///
+    /// Only applies when the sink address is multicast.
+    pub fn with_auto_multicast(mut self, auto: bool) -> Self {
+        self.no_auto_multicast = !auto;
+        self
     }
 
-    /// Check if source is RTP or RTSP
-    fn is_rtp_or_rtsp(&self) -> bool {
-        self.is_rtp() || self.is_rtsp()
+    /// Use multicast loopback on RTP sinks (disabled by default)
+    ///
+    /// Only applies when the sink address is multicast.  When enabled,
+    /// receivers on the same host also get packets sent by this flow, which
+    /// can cause duplicate packets.
+    pub fn with_multicast_loop(mut self, looping: bool) -> Self {
+        self.multicast_loop = looping;
+        self
     }
 
-    /// Check if source is HTTP
-    fn is_http(&self) -> bool {
-        self.location.starts_with("http://")
+    /// Use the specified multicast TTL on RTP sinks (default 15)
+    ///
+    /// A destination with its own TTL overrides this.
+    pub fn with_ttl(mut self, ttl: Option<i32>) -> Self {
+        self.ttl = ttl;
+        self
     }
-}
 
-impl AspectRatio {
-    /// Get as boolean value
-    fn as_bool(&self) -> bool {
-        match self {
-            AspectRatio::FILL => false,
-            AspectRatio::PRESERVE => true,
-        }
+    /// Use the specified DSCP (0 to 63) on RTP sinks
+    ///
+    /// By default, packets are not marked.
+    pub fn with_dscp(mut self, dscp: Option<i32>) -> Self {
+        self.dscp = dscp;
+        self
     }
-}
 
-impl Default for MatrixCrop {
-    fn default() -> Self {
-        MatrixCrop {
-            aspect: AspectRatio::default(),
-            x: 0,
-            y: 0,
-            width: 1,
-            height: 1,
-            hgap: 0,
-            vgap: 0,
-        }
+    /// Use additional destinations for RTP sinks
+    ///
+    /// The stream is encoded once, then sent to each destination through a
+    /// `tee`.  This can be used to send to redundant multicast groups.
+    pub fn with_destinations(mut self, destinations: Vec<Destination>) -> Self {
+        self.destinations = destinations;
+        self
     }
-}
 
-impl TryFrom<&str> for MatrixCrop {
-    type Error = Error;
+    /// Use adaptive bitrate renditions for HLS sinks
+    ///
+    /// The decoded stream is split with a `tee`, then scaled and encoded
+    /// once for each rendition.  Each rendition is written to a numbered
+    /// sub-directory (`r0`, `r1`, ...), and a `master.m3u8` playlist listing
+    /// all of them is written to the HLS directory.
+    ///
+    /// Every rendition is a separate encode, so CPU cost grows with the
+    /// number (and resolution) of renditions.  With hardware acceleration,
+    /// each rendition uses its own encoder session, which some devices
+    /// limit; missing encoders are handled by software fallback.
+    pub fn with_renditions(mut self, renditions: Vec<Rendition>) -> Self {
+        self.renditions = renditions;
+        self
+    }
 
-    fn try_from(v: &str) -> Result<Self, Self::Error> {
-        let p: Vec<_> = v.split(',').collect();
-        if p.len() == 3 {
-            let mut crop = p[0].chars();
-            let x = MatrixCrop::position(crop.next().unwrap_or_default())?;
-            let y = MatrixCrop::position(crop.next().unwrap_or_default())?;
-            let width =
-                MatrixCrop::position(crop.next().unwrap_or_default())? + 1;
-            let height =
-                MatrixCrop::position(crop.next().unwrap_or_default())? + 1;
-            if x < width && y < height {
-                let hgap: u32 = p[1].parse()?;
-                let vgap: u32 = p[2].parse()?;
-                // Don't allow more than 50% gap
-                if hgap <= MatrixCrop::PERCENT / 2
-                    && vgap <= MatrixCrop::PERCENT / 2
-                {
-                    return Ok(MatrixCrop {
-                        aspect: AspectRatio::default(),
-                        x,
-                        y,
-                        width,
-                        height,
-                        hgap,
-                        vgap,
-                    });
-                }
-            }
-        }
-        Err(Error::InvalidCrop())
+    /// Use the specified maximum number of B-frames (h.264 / h.265)
+    ///
+    /// The default, zero, tunes encoders for low latency, which is best for
+    /// live flows.  B-frames reduce bitrate for the same quality, which is
+    /// useful for recording, but add latency of one frame each.
+    pub fn with_bframes(mut self, bframes: u32) -> Self {
+        self.bframes = bframes;
+        self
     }
-}
 
-impl MatrixCrop {
-    /// Percent of window, in hundredths
-    const PERCENT: u32 = 100_00;
+    /// Tune software h.264 / h.265 encoders for low latency
+    ///
+    /// This is enabled by default, which is best for live flows.  Disabling
+    /// it skips `tune=zerolatency`, allowing look-ahead and better
+    /// compression for recording.  B-frames are still set by `with_bframes`.
+    pub fn with_low_latency(mut self, low_latency: bool) -> Self {
+        self.low_latency = low_latency;
+        self
+    }
 
-    /// Get a matrix position from a crop code
-    fn position(c: char) -> Result<u8, Error> {
-        match c {
-            'A'..='H' => Ok(c as u8 - b'A'),
-            _ => Err(Error::InvalidCrop()),
-        }
+    /// Use the specified number of software encoder threads
+    ///
+    /// By default, x264 / x265 pick a thread count from the number of CPU
+    /// cores, which can oversubscribe a machine running many flows.  Applies
+    /// to software `H264` and `H265` encoders only.
+    pub fn with_encoder_threads(mut self, threads: Option<u32>) -> Self {
+        self.encoder_threads = threads;
+        self
     }
 
-    /// Check if window is cropped
-    fn is_cropped(&self) -> bool {
-        self.width > 1 || self.height > 1
+    /// Use sliced threads for the software h.264 encoder
+    ///
+    /// Sliced threads split each frame between threads, which reduces encode
+    /// latency.  Frame-based threading (the default) has better throughput
+    /// and quality, but adds a frame of latency for each thread.
+    pub fn with_sliced_threads(mut self, sliced: bool) -> Self {
+        self.sliced_threads = sliced;
+        self
     }
 
-    /// Get number of pixels to crop from top edge
-    fn top(&self, height: u32) -> u32 {
-        let num = u32::from(self.y);
-        let den = u32::from(self.height);
-        let pix = height * num / den;
-        let gap = height * self.vgap / (den * MatrixCrop::PERCENT * 2);
-        trace!("crop top: {} + {} = {}", pix, gap, pix + gap);
-        pix + gap
+    /// Limit h.264 encoding to the constrained-baseline profile
+    ///
+    /// Many mobile browsers and embedded decoders (including most WebRTC
+    /// clients) can only decode constrained-baseline.  This disables B-frames
+    /// and CABAC, overriding `with_bframes`.
+    pub fn with_constrained_baseline(mut self, enable: bool) -> Self {
+        self.constrained_baseline = enable;
+        self
     }
 
-    /// Get number of pixels to crop from bottom edge
-    fn bottom(&self, height: u32) -> u32 {
-        let num = u32::from(self.height - self.y - 1);
-        let den = u32::from(self.height);
-        let pix = height * num / den;
-        let gap = height * self.vgap / (den * MatrixCrop::PERCENT * 2);
-        trace!("crop bottom: {} + {} = {}", pix, gap, pix + gap);
-        pix + gap
+    /// Request a key frame when packet loss reaches a threshold
+    ///
+    /// When the jitter buffer reports at least `lost` packets lost since the
+    /// previous stats update, an upstream force-key-unit event is sent from
+    /// the sinks.  On transcoding flows, the encoder produces a key frame
+    /// immediately; on RTSP sources, the RTP session requests one from the
+    /// sender with RTCP PLI / FIR.  This shortens artifacts after loss.
+    pub fn with_keyframe_loss(mut self, lost: Option<u64>) -> Self {
+        self.keyframe_loss = lost;
+        self
     }
 
-    /// Get number of pixels to crop from left edge
-    fn left(&self, width: u32) -> u32 {
-        let num = u32::from(self.x);
-        let den = u32::from(self.width);
-        let pix = width * num / den;
-        let gap = width * self.hgap / (den * MatrixCrop::PERCENT * 2);
-        trace!("crop left: {} + {} = {}", pix, gap, pix + gap);
-        pix + gap
+    /// Receive RTCP feedback from receivers of an RTP sink
+    ///
+    /// An RTP session is added before the `udpsink`, which sends RTCP to
+    /// the sink port plus one and listens on that port for receiver RTCP.
+    /// Picture loss indications (PLI) and full intra requests (FIR) are
+    /// turned into upstream force-key-unit events, answered by the encoder
+    /// or, when passing RTP through, by the RTSP source.  This only applies
+    /// to an RTP sink without additional destinations.
+    pub fn with_rtcp_feedback(mut self, rtcp_feedback: bool) -> Self {
+        self.rtcp_feedback = rtcp_feedback;
+        self
     }
 
-    /// Get number of pixels to crop from right edge
-    fn right(&self, width: u32) -> u32 {
-        let num = u32::from(self.width - self.x - 1);
-        let den = u32::from(self.width);
-        let pix = width * num / den;
-        let gap = width * self.hgap / (den * MatrixCrop::PERCENT * 2);
-        trace!("crop right: {} + {} = {}", pix, gap, pix + gap);
-        pix + gap
+    /// Raise an alert when packet loss is above a percentage
+    ///
+    /// Loss is the share of lost packets per stats update, so it is only
+    /// available for RTP sources.
+    pub fn with_loss_alert(mut self, percent: Option<f32>) -> Self {
+        self.loss_alert = percent;
+        self
     }
-}
 
-impl Default for Sink {
-    fn default() -> Self {
-        Sink::FAKE
+    /// Raise an alert when no packets are received (disabled by default)
+    pub fn with_down_alert(mut self, down: bool) -> Self {
+        self.down_alert = down;
+        self
     }
-}
 
-impl Sink {
-    /// Is the sink RTP?
-    fn is_rtp(&self) -> bool {
-        match self {
-            Sink::RTP(_, _, _, _) => true,
-            _ => false,
-        }
+    /// Use the specified number of stats updates to raise or clear alerts
+    ///
+    /// The default is 3; a condition must last this long before an alert
+    /// is raised, and be clear this long before `RECOVERED` is sent.
+    pub fn with_alert_intervals(mut self, intervals: Option<u32>) -> Self {
+        self.alert_intervals = match intervals {
+            Some(n) if n > 0 => n,
+            _ => ALERT_INTERVALS,
+        };
+        self
     }
 
-    /// Get the gstreamer factory name
-    fn factory_name(&self, acceleration: Acceleration) -> &'static str {
-        match (self, acceleration) {
-            (Sink::FAKE, _) => "fakesink",
-            (Sink::RTP(_, _, _, _), _) => "udpsink",
-            (Sink::WINDOW(_), Acceleration::VAAPI) => "vaapisink",
-            (Sink::WINDOW(_), _) => "gtksink",
+    /// Get maximum B-frames for encoder
+    fn max_bframes(&self) -> u32 {
+        if self.constrained_baseline {
+            0
+        } else {
+            self.bframes
         }
     }
 
-    /// Get the matrix crop setting
-    fn crop(&self) -> MatrixCrop {
-        match self {
-            Sink::WINDOW(c) => *c,
-            _ => MatrixCrop::default(),
-        }
+    /// Use the specified resolution (width, height), scaling if needed
+    ///
+    /// A resolution requires transcoding.
+    pub fn with_resolution(mut self, resolution: Option<(i32, i32)>) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Use the specified aspect ratio handling when scaling to a resolution
+    ///
+    /// `PRESERVE` (default) adds black borders (letter / pillar box), while
+    /// `FILL` stretches the frame to the resolution.
+    pub fn with_scale_aspect(mut self, aspect: AspectRatio) -> Self {
+        self.scale_aspect = aspect;
+        self
+    }
+
+    /// Use the specified flow feedback
+    pub fn with_feedback(mut self, feedback: Option<Sender<Feedback>>) -> Self {
+        self.feedback = feedback;
+        self
+    }
+
+    /// Use the specified window sink element
+    ///
+    /// By default, the element is chosen from the video acceleration.
+    pub fn with_window_sink(mut self, window_sink: WindowSink) -> Self {
+        self.window_sink = window_sink;
+        self
+    }
+
+    /// Share one GL display and context between window sinks of flows
+    ///
+    /// This only affects `WindowSink::GL` sinks.  By default, each sink
+    /// opens its own display and GL context, which is expensive for a video
+    /// wall with many windows.
+    pub fn with_gl_share(mut self, gl_share: Option<GlShare>) -> Self {
+        self.gl_share = gl_share;
+        self
+    }
+
+    /// Use the specified streaming thread priority (nice value)
+    ///
+    /// Streaming threads of the pipeline are set to this nice value, from
+    /// -20 (highest priority) to 19 (lowest), when they start.  On a host
+    /// with many flows, raising the value for less important flows protects
+    /// the others from CPU starvation.  Negative values require the
+    /// `CAP_SYS_NICE` capability.  Without a priority, threads get the nice
+    /// value of the main thread.
+    pub fn with_priority(mut self, priority: Option<i32>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Use the specified video overlay window handle
+    pub fn with_handle(mut self, handle: Option<usize>) -> Self {
+        self.handle = handle;
+        self
+    }
+
+    /// Freeze the last frame in the window while the flow is stopped
+    ///
+    /// Instead of going black, the window keeps displaying the last frame
+    /// until the flow is playing again.  This requires a video overlay
+    /// handle (`with_handle`), since the frame is displayed by a separate
+    /// pipeline.  Disabled by default.
+    pub fn with_freeze_frame(mut self, freeze_frame: bool) -> Self {
+        self.freeze_frame = freeze_frame;
+        self
     }
 
-    /// Get the sink encoding
-    fn encoding(&self) -> Encoding {
-        match self {
-            Sink::RTP(_, _, encoding, _) => *encoding,
-            _ => Encoding::RAW,
-        }
+    /// Use the specified window title
+    ///
+    /// With `gtksink`, the title is set on the window it creates.  Other
+    /// window sinks which create their own window (`xvimagesink`,
+    /// `glimagesink`) receive it as a title tag.  Sinks rendering to a
+    /// provided window handle, and `waylandsink`, ignore the title.
+    pub fn with_window_title(mut self, window_title: Option<&str>) -> Self {
+        self.window_title = window_title.map(|t| t.to_string());
+        self
     }
 
-    /// Should config be inserted in-band?
-    fn insert_config(&self) -> bool {
-        match self {
-            Sink::RTP(_, _, _, true) => true,
-            _ => false,
-        }
+    /// Use the specified window geometry (x, y, width, height)
+    ///
+    /// With a video overlay window handle, this is the render rectangle
+    /// within that window.  With `gtksink`, only the size is used, since GTK
+    /// does not allow positioning a window before it is mapped; use the
+    /// window manager for placement.  Other sinks ignore the geometry.
+    pub fn with_window_geometry(
+        mut self,
+        window_geometry: Option<(i32, i32, i32, i32)>,
+    ) -> Self {
+        self.window_geometry = window_geometry;
+        self
     }
-}
 
-impl fmt::Display for FlowBuilder {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Flow{}", self.idx)
+    /// Use the specified display for a window sink
+    ///
+    /// This pins a window flow to a display (for example `:1` for X11 or
+    /// `wayland-1`) on multi-display machines.  It is set as the `display`
+    /// property of `xvimagesink` / `waylandsink`, or `display-name` of
+    /// `vaapisink`.  For `vaapisink`, a DRM device path (such as
+    /// `/dev/dri/renderD129`) selects that GPU instead.  Other window sinks
+    /// (`gtksink`, `glimagesink`) use the process display, so the option is
+    /// ignored with a warning.  Title and geometry apply on that display.
+    pub fn with_window_display(mut self, display: Option<&str>) -> Self {
+        self.window_display = display.map(|d| d.to_string());
+        self
     }
-}
 
-impl FlowBuilder {
-    /// Create a new flow builder
-    pub fn new(idx: usize) -> Self {
-        FlowBuilder {
-            idx,
-            ..Default::default()
-        }
+    /// Use the specified STUN server for a WebRTC sink
+    ///
+    /// The server is specified as `stun://host:port`.
+    pub fn with_stun_server(mut self, stun_server: Option<&str>) -> Self {
+        self.stun_server = stun_server.map(|s| s.to_string());
+        self
     }
 
-    /// Use the specified source
-    pub fn with_source(mut self, source: Source) -> Self {
-        self.source = source;
+    /// Use automatic restart (enabled by default)
+    ///
+    /// When disabled, a flow which stops or gets stuck stays stopped, which
+    /// can help when debugging a flapping source.
+    pub fn with_restart(mut self, restart: bool) -> Self {
+        self.no_restart = !restart;
         self
     }
 
-    /// Use the specified sink
-    pub fn with_sink(mut self, sink: Sink) -> Self {
-        self.sink = sink;
+    /// Use the specified action when the flow is stuck
+    ///
+    /// A flow is stuck when the sink has not received a new frame since
+    /// the last check.  The default is `RESTART`.
+    pub fn with_stuck_action(mut self, action: StuckAction) -> Self {
+        self.stuck_action = action;
         self
     }
 
-    /// Use the specified video acceleration
-    pub fn with_acceleration(mut self, acceleration: Acceleration) -> Self {
-        self.acceleration = acceleration;
+    /// Use the stuck check (enabled by default)
+    ///
+    /// When disabled, a flow is never stopped for a sink which is not
+    /// receiving new frames.  This is useful for low-framerate sources, but
+    /// a frozen source will not recover automatically.
+    pub fn with_stuck_check(mut self, check: bool) -> Self {
+        self.no_stuck_check = !check;
         self
     }
 
-    /// Use the specified overlay text
-    pub fn with_overlay_text(mut self, overlay_text: Option<&str>) -> Self {
-        self.overlay_text = overlay_text.map(|t| t.to_string());
+    /// Fill gaps in a decoded stream with black video or silent audio
+    ///
+    /// When a transcoding flow's source stops delivering data for half a
+    /// second, an `input-selector` switches to a test source, switching
+    /// back when the source resumes.  This keeps receivers and recordings
+    /// continuous, but the stuck check can no longer detect a frozen source.
+    /// Disabled by default.
+    pub fn with_gap_fill(mut self, gap_fill: bool) -> Self {
+        self.gap_fill = gap_fill;
         self
     }
 
-    /// Use the specified flow feedback
-    pub fn with_feedback(mut self, feedback: Option<Sender<Feedback>>) -> Self {
-        self.feedback = feedback;
+    /// Fall back to SMPTE color bars when the source fails repeatedly
+    ///
+    /// After the flow fails to play `failures` times in a row, the source
+    /// branch (up to the decoder) is stopped, and the gap fill selector
+    /// shows color bars, so "no feed" is easy to recognize.  The source is
+    /// retried every 10 seconds, switching back when data arrives.  From
+    /// then on, source errors return to the bars instead of restarting the
+    /// whole flow.  Like gap fill, this requires transcoding.  Disabled by
+    /// default.
+    pub fn with_fallback_source(mut self, failures: Option<u32>) -> Self {
+        self.fallback_failures = failures.filter(|f| *f > 0);
         self
     }
 
-    /// Use the specified video overlay window handle
-    pub fn with_handle(mut self, handle: Option<usize>) -> Self {
-        self.handle = handle;
+    /// Wait for the flow to reach playing state when building
+    ///
+    /// By default, `build` returns as soon as the state change is started.
+    /// With a timeout (seconds), `build` fails if the pipeline has not
+    /// reached playing state in time.
+    pub fn with_play_timeout(mut self, timeout: Option<u16>) -> Self {
+        self.play_timeout = timeout;
+        self
+    }
+
+    /// Use the specified audio sink encoding (audio-only flows)
+    ///
+    /// Defaults to the source audio encoding (no transcoding).
+    pub fn with_audio_sink_encoding(
+        mut self,
+        audio_sink: Option<AudioEncoding>,
+    ) -> Self {
+        self.audio_sink = audio_sink;
         self
     }
 
@@ -701,70 +3451,540 @@ impl FlowBuilder {
         let name = format!("m{}", self.idx);
         let pipeline = Pipeline::new(Some(&name));
         self.pipeline = pipeline.downgrade();
-        self.add_elements()?;
+        if let Some(clock) = self.pipeline_clock()? {
+            pipeline.use_clock(Some(&clock));
+        }
+        self.add_elements()
+            .map_err(|e| Error::Build(idx, self.stage, Box::new(e)))?;
+        let banner = self.banner.take();
+        let overlay_file = self.overlay_file.clone();
+        let overlay_file_ms = self.overlay_file_ms;
+        let overlay_text = self.overlay_file_text();
+        let ticker = self.ticker.clone();
+        let ticker_speed = self.ticker_speed;
+        let font = self.font;
+        let state_change = Arc::clone(&self.state_change);
+        let feedback = self.feedback.clone();
+        let paused = Arc::clone(&self.paused);
+        let mut checker = FlowChecker::new(&self, pipeline.downgrade());
         let timeout_ms = self.source.timeout_ms();
+        let refresh_ms = self.source.refresh_ms();
+        let name = self.name.clone();
         let bus = pipeline.get_bus().unwrap();
+        let gl_share = self.gl_share.clone();
+        let priority = self.priority;
+        bus.set_sync_handler(move |_bus, m| {
+            if let Some(gl_share) = &gl_share {
+                gl_share.handle_sync(m);
+            }
+            set_thread_priority(idx, m, priority);
+            BusSyncReply::Pass
+        });
         if let Err(_) = bus.add_watch(move |_bus, m| self.handle_message(m)) {
             return Err(Error::ConnectSignal("watch"));
         }
-        let mut checker = FlowChecker::new(idx, pipeline.downgrade());
+        if let Some(banner) = banner {
+            let weak = pipeline.downgrade();
+            glib::source::timeout_add(BANNER_POLL_MS, move || {
+                update_banner(idx, &weak, &banner)
+            });
+        }
+        if let Some(path) = overlay_file {
+            let weak = pipeline.downgrade();
+            let mut last = overlay_text;
+            let mut failed = false;
+            glib::source::timeout_add(overlay_file_ms, move || {
+                update_overlay_file(idx, &weak, &path, &mut last, &mut failed)
+            });
+        }
+        if let Some(refresh_ms) = refresh_ms {
+            let weak = pipeline.downgrade();
+            glib::source::timeout_add(refresh_ms, move || {
+                refresh_png(idx, &weak)
+            });
+        }
+        let ticker = ticker.map(|text| {
+            let (tx, rx) = channel();
+            let mut scroller = TickerScroller::new(
+                idx,
+                pipeline.downgrade(),
+                rx,
+                &text,
+                ticker_speed,
+                font,
+            );
+            glib::source::timeout_add(TICKER_INTERVAL_MS, move || {
+                scroller.do_scroll()
+            });
+            tx
+        });
         glib::source::timeout_add(timeout_ms, move || checker.do_check());
-        pipeline.set_state(State::Playing).unwrap();
-        Ok(Flow { idx, pipeline, bus })
+        let flow = Flow {
+            idx,
+            name,
+            pipeline,
+            bus,
+            state_change,
+            ticker,
+            feedback,
+            paused,
+        };
+        if let Err(e) = flow.pipeline.set_state(State::Playing) {
+            // Dropping the flow removes the bus watch
+            drop(flow);
+            return Err(e.into());
+        }
+        Ok(flow)
     }
 
     /// Build the flow
+    ///
+    /// Errors building the pipeline or setting it to playing are returned,
+    /// with the flow index and pipeline stage where known.
     pub fn build(self) -> Result<Flow, Error> {
-        let idx = self.idx;
-        self.try_build().or_else(|e| {
-            error!("{}: building flow", e);
-            FlowBuilder::new(idx).try_build()
-        })
+        let play_timeout = self.play_timeout;
+        self.check_sink_address()?;
+        let flow = self.try_build()?;
+        if let Some(timeout) = play_timeout {
+            flow.wait_playing(timeout)?;
+        }
+        Ok(flow)
+    }
+
+    /// Check that the sink address is an IP address or host name
+    fn check_sink_address(&self) -> Result<(), Error> {
+        let addrs = self.sinks.iter().filter_map(|sink| match sink {
+            Sink::RTP(addr, _, _, _) => Some(addr),
+            _ => None,
+        });
+        let dests = self.destinations.iter().map(|dest| &dest.address);
+        for addr in addrs.chain(dests) {
+            if let Err(e) = addr.parse::<IpAddr>() {
+                if !is_hostname(addr) {
+                    flow_log!(
+                        error,
+                        self,
+                        "{}: invalid sink address {:?}",
+                        self,
+                        addr
+                    );
+                    return Err(Error::SinkAddress(self.idx, addr.clone(), e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the pipeline clock, if not the default
+    fn pipeline_clock(&self) -> Result<Option<Clock>, Error> {
+        match (&self.clock, self.timestamp) {
+            (Some(clock), _) => Ok(Some(clock.clone())),
+            (None, true) => {
+                // Default system clock is monotonic, not wall-clock time
+                let clock = glib::Object::new(
+                    SystemClock::static_type(),
+                    &[("clock-type", &ClockType::Realtime)],
+                )?;
+                Ok(clock.downcast::<Clock>().ok())
+            },
+            (None, false) => Ok(None),
+        }
     }
 
     /// Check if pipeline should have a text overlay
     fn has_text(&self) -> bool {
         self.overlay_text.is_some()
+            || self.overlay_file.is_some()
+            || self.stats_format.is_some()
+            || self.banner.is_some()
+            || self.ticker.is_some()
+            || self.timestamp
+    }
+
+    /// Get initial overlay text, from file if readable
+    ///
+    /// Read errors are logged when the file is polled.
+    fn overlay_file_text(&self) -> String {
+        self.overlay_file
+            .as_ref()
+            .and_then(|path| read_overlay_file(path).ok())
+            .or_else(|| self.overlay_text.clone())
+            .unwrap_or_default()
+    }
+
+    /// Get statistics overlay text
+    fn stats_text(&self) -> Option<String> {
+        self.stats_format.as_ref().map(|fmt| {
+            fmt.replace("{pushed}", &self.pushed.to_string())
+                .replace("{lost}", &self.lost.to_string())
+                .replace("{late}", &self.late.to_string())
+                .replace("{buffers}", &self.buffers.to_string())
+                .replace("{bytes}", &self.bytes.to_string())
+                .replace("{dropped}", &self.dropped.to_string())
+                .replace("{latency}", &self.latency_ms.to_string())
+                .replace("{received}", &self.received.to_string())
+                .replace("{jitter}", &opt_string(self.jitter_us))
+                .replace("{rtt}", &opt_string(self.rtt_us))
+        })
     }
 
     /// Add all required elements to the pipeline
     ///
     /// Pipeline is built from sink to source.
     fn add_elements(&mut self) -> Result<(), Error> {
-        self.add_element(self.create_sink()?)?;
-        if self.needs_rtp_pay() {
-            self.add_rtp_pay()?;
+        if self.sinks.is_empty() {
+            self.sinks.push(Sink::FAKE);
         }
-        if self.needs_encode() {
-            self.add_encode()?;
-            self.add_queue()?;
+        if let Some(audio) = self.source.audio {
+            return self.add_audio_elements(audio);
         }
-        if self.sink.crop().is_cropped() {
-            self.add_element(make_element("videobox", Some("vbox"))?)?;
+        if self.sinks.len() > 1 {
+            self.add_tee()?;
+        } else {
+            self.add_sink_branch(0)?;
         }
         if self.has_text() {
-            self.add_element(self.create_text()?)?;
+            self.stage = "overlay";
+            if self.overlay_text.is_some() || self.overlay_file.is_some() {
+                let text = self.overlay_file_text();
+                let txt = self.create_text("txt", &text, "right", "top")?;
+                self.add_element(txt)?;
+            }
+            if let Some(text) = self.stats_text() {
+                let txt = self.create_text("stats", &text, "left", "bottom")?;
+                self.add_element(txt)?;
+            }
+            if self.banner.is_some() {
+                let txt = self.create_text("banner", "", "center", "bottom")?;
+                txt.set_property_from_str("wrap-mode", &"word-char");
+                self.add_element(txt)?;
+            }
+            if let Some(text) = &self.ticker {
+                let txt = self.create_text("ticker", text, "left", "bottom")?;
+                self.add_element(txt)?;
+            }
+            if self.timestamp {
+                let txt = self.create_text("clock", "", "left", "top")?;
+                self.add_timestamp_probe(&txt)?;
+                self.add_element(txt)?;
+            }
             self.add_queue()?;
         }
+        if let Some((width, height)) = self.resolution {
+            self.stage = "scale";
+            self.add_scale(width, height)?;
+        }
         if self.needs_decode() {
+            if self.fallback_failures.is_some() {
+                self.stage = "fill";
+                let fill = self.create_test_src("fill", "smpte75")?;
+                self.add_gap_fill(fill)?;
+                self.adding_live = true;
+            } else if self.gap_fill {
+                self.stage = "fill";
+                let fill = self.create_test_src("fill", "black")?;
+                self.add_gap_fill(fill)?;
+            }
+            self.stage = "decode";
             self.add_decode()?;
             self.add_queue()?;
         }
         if self.needs_rtp_depay() {
-            let depay = make_element(self.source.encoding.rtp_depay()?, None)?;
+            self.stage = "depay";
+            let depay =
+                self.make_element(self.source.encoding.rtp_depay()?, "depay")?;
             self.add_element(depay)?;
         }
         if self.is_rtp_passthru() {
             self.add_queue()?;
         }
+        self.stage = "source";
+        self.add_ingress()?;
         self.add_source()?;
+        self.adding_live = false;
+        self.head = None;
+        Ok(())
+    }
+
+    /// Add all elements for an audio-only flow
+    fn add_audio_elements(
+        &mut self,
+        audio: AudioEncoding,
+    ) -> Result<(), Error> {
+        let sink = match &self.sinks[..] {
+            [sink @ Sink::RTP(_, _, _, _)] | [sink @ Sink::FAKE] => {
+                sink.clone()
+            },
+            _ => return Err(Error::Other("invalid sink for audio")),
+        };
+        let audio_sink = self.audio_sink.unwrap_or(audio);
+        self.stage = "sink";
+        self.add_element(self.create_sink("sink", &sink)?)?;
+        if sink.is_rtp() {
+            self.stage = "pay";
+            self.add_element(self.make_element(audio_sink.rtp_pay(), "pay")?)?;
+        }
+        if sink.is_rtp() && audio_sink != audio {
+            self.stage = "transcode";
+            self.add_element(self.make_element(audio_sink.encoder(), "enc")?)?;
+            self.add_element(self.make_element("audioresample", "resample")?)?;
+            self.add_element(self.make_element("audioconvert", "convert")?)?;
+            if self.gap_fill {
+                let fill = self.make_element("audiotestsrc", "fill")?;
+                fill.set_property_from_str("wave", "silence");
+                self.add_gap_fill(fill)?;
+            }
+            self.add_element(self.make_element(audio.decoder(), "dec")?)?;
+            self.add_queue()?;
+        }
+        self.stage = "depay";
+        self.add_element(self.make_element(audio.rtp_depay(), "depay")?)?;
+        self.stage = "source";
+        self.add_ingress()?;
+        if self.source.is_rtp() {
+            self.add_source_rtp()?;
+        } else if self.source.is_rtsp() {
+            self.add_source_rtsp()?;
+        } else {
+            return Err(Error::Other("invalid source for audio"));
+        }
         self.head = None;
         Ok(())
     }
 
-    /// Check if pipeline needs RTP payloader
-    fn needs_rtp_pay(&self) -> bool {
-        self.sink.is_rtp() && !self.is_rtp_passthru()
+    /// Add elements to scale video to a resolution
+    fn add_scale(&mut self, width: i32, height: i32) -> Result<(), Error> {
+        let caps = Caps::new_simple(
+            "video/x-raw",
+            &[
+                ("width", &width),
+                ("height", &height),
+                ("pixel-aspect-ratio", &gstreamer::Fraction::new(1, 1)),
+            ],
+        );
+        let fltr = self.make_element("capsfilter", "scalecaps")?;
+        self.set_property(&fltr, "caps", &caps)?;
+        self.add_element(fltr)?;
+        let scale = self.make_element("videoscale", "scale")?;
+        self.set_property(&scale, "add-borders", &self.scale_aspect.as_bool())?;
+        self.add_element(scale)
+    }
+
+    /// Add a tee with one branch for each sink
+    fn add_tee(&mut self) -> Result<(), Error> {
+        let mut branches = vec![];
+        for i in 0..self.sinks.len() {
+            self.add_sink_branch(i)?;
+            self.add_queue()?;
+            branches.extend(self.head.take());
+        }
+        self.branch = 0;
+        let tee = self.make_element("tee", "tee")?;
+        self.add_element(tee.clone())?;
+        for branch in branches {
+            self.link_src_sink(&tee, branch)?;
+        }
+        Ok(())
+    }
+
+    /// Add elements for one sink branch
+    fn add_sink_branch(&mut self, i: usize) -> Result<(), Error> {
+        self.branch = i;
+        self.stage = "sink";
+        let sink = self.sinks[i].clone();
+        if let Sink::HLS(dir) = &sink {
+            if !self.renditions.is_empty() {
+                self.stage = "renditions";
+                return self.add_renditions(dir);
+            }
+        }
+        if sink.is_rtp() && !self.destinations.is_empty() {
+            self.add_destinations(&sink)?;
+        } else {
+            self.add_element(self.create_sink("sink", &sink)?)?;
+            if sink.is_rtp() && self.rtcp_feedback {
+                self.stage = "session";
+                self.add_rtp_session(&sink)?;
+            }
+        }
+        if sink.is_pipe() {
+            // leak (drop) frames -- when the reader cannot keep up
+            let que = self.make_element("queue", "pipequeue")?;
+            self.set_property(&que, "max-size-time", &SEC_NS)?;
+            que.set_property_from_str("leaky", &"downstream");
+            self.add_element(que)?;
+            self.stage = "parse";
+            if self.needs_h264_parse(&sink) {
+                self.add_h264_parse()?;
+            } else {
+                self.add_byte_stream_parse(sink.encoding())?;
+            }
+        }
+        if sink.is_hls() {
+            self.stage = "parse";
+            self.add_element(self.make_element("h264parse", "parse")?)?;
+        }
+        if sink.is_http() {
+            self.stage = "mux";
+            let mux = self.make_element("multipartmux", "mux")?;
+            self.set_property(&mux, "boundary", &http::MJPEG_BOUNDARY)?;
+            self.add_element(mux)?;
+        }
+        if self.needs_rtp_pay(&sink) {
+            self.stage = "pay";
+            self.add_rtp_pay(&sink)?;
+            if self.needs_h264_parse(&sink) {
+                self.add_h264_parse()?;
+            }
+        }
+        if self.needs_encode(&sink) {
+            self.stage = "encode";
+            self.add_encode(&sink)?;
+            self.add_queue()?;
+        }
+        if sink.crop().is_cropped() {
+            self.stage = "crop";
+            let vbox = self.make_element("videobox", "vbox")?;
+            self.add_element(vbox)?;
+        }
+        Ok(())
+    }
+
+    /// Add an RTP session with RTCP feedback, before an RTP sink
+    fn add_rtp_session(&mut self, sink: &Sink) -> Result<(), Error> {
+        let dest = match sink {
+            Sink::RTP(addr, port, _, _) => Destination::new(addr, port + 1),
+            _ => return Err(Error::Other("invalid sink for RTP session")),
+        };
+        let pipeline = self
+            .pipeline
+            .upgrade()
+            .ok_or(Error::Other("pipeline gone"))?;
+        let rtp_sink = self.head.take().ok_or(Error::Other("no RTP sink"))?;
+        let input = self.make_element("identity", "sessionin")?;
+        let session = self.make_element("rtpsession", "session")?;
+        let rtcp_sink = self.make_element("udpsink", "rtcpsink")?;
+        self.config_udpsink(&rtcp_sink, &dest)?;
+        self.set_property(&rtcp_sink, "sync", &false)?;
+        self.set_property(&rtcp_sink, "async", &false)?;
+        let rtcp_src = self.make_element("udpsrc", "rtcpsrc")?;
+        self.set_property(&rtcp_src, "port", &dest.port)?;
+        if dest.is_multicast() {
+            self.set_property(&rtcp_src, "address", &dest.address)?;
+        }
+        let caps = Caps::new_simple("application/x-rtcp", &[]);
+        self.set_property(&rtcp_src, "caps", &caps)?;
+        if pipeline
+            .add_many(&[&input, &session, &rtcp_sink, &rtcp_src])
+            .is_err()
+        {
+            return Err(Error::PipelineAdd());
+        }
+        // Requesting send_rtp_sink creates the send_rtp_src pad
+        input.link_pads(Some("src"), &session, Some("send_rtp_sink"))?;
+        session.link_pads(Some("send_rtp_src"), &rtp_sink, Some("sink"))?;
+        session.link_pads(Some("send_rtcp_src"), &rtcp_sink, Some("sink"))?;
+        rtcp_src.link_pads(Some("src"), &session, Some("recv_rtcp_sink"))?;
+        self.head = Some(input);
+        Ok(())
+    }
+
+    /// Add a tee with an RTP sink for each destination
+    fn add_destinations(&mut self, sink: &Sink) -> Result<(), Error> {
+        let mut branches = vec![];
+        self.add_element(self.create_sink("sink", sink)?)?;
+        self.add_queue()?;
+        branches.extend(self.head.take());
+        for (j, dest) in self.destinations.clone().iter().enumerate() {
+            let udp =
+                self.make_element("udpsink", &format!("sink_dest{}", j))?;
+            self.config_udpsink(&udp, dest)?;
+            self.config_sync(&udp)?;
+            self.add_element(udp)?;
+            self.add_queue()?;
+            branches.extend(self.head.take());
+        }
+        let tee = self.make_element("tee", "desttee")?;
+        self.add_element(tee.clone())?;
+        for branch in branches {
+            self.link_src_sink(&tee, branch)?;
+        }
+        Ok(())
+    }
+
+    /// Add a tee with an encoded HLS sink for each rendition
+    fn add_renditions(&mut self, dir: &str) -> Result<(), Error> {
+        let dir = Path::new(dir);
+        self.write_master_playlist(dir)?;
+        let mut branches = vec![];
+        for (k, rend) in self.renditions.clone().iter().enumerate() {
+            // First rendition sink keeps the plain name, for stuck checks
+            self.rendition = if k > 0 { Some(k) } else { None };
+            let sink = self.make_element("hlssink2", "sink")?;
+            self.rendition = Some(k);
+            self.config_hlssink(&sink, &dir.join(Rendition::dir(k)))?;
+            self.add_element(sink)?;
+            self.add_element(self.make_element("h264parse", "parse")?)?;
+            self.add_encode(&Sink::HLS(String::new()))?;
+            self.add_scale(rend.width, rend.height)?;
+            self.add_queue()?;
+            branches.extend(self.head.take());
+        }
+        self.rendition = None;
+        let tee = self.make_element("tee", "rendtee")?;
+        self.add_element(tee.clone())?;
+        for branch in branches {
+            self.link_src_sink(&tee, branch)?;
+        }
+        Ok(())
+    }
+
+    /// Write HLS master playlist listing all renditions
+    fn write_master_playlist(&self, dir: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dir)?;
+        let mut m3u8 = String::from("#EXTM3U\n");
+        for (k, rend) in self.renditions.iter().enumerate() {
+            m3u8.push_str(&rend.playlist_entry(k));
+        }
+        std::fs::write(dir.join("master.m3u8"), m3u8)?;
+        Ok(())
+    }
+
+    /// Configure an HLS sink to write to a directory
+    fn config_hlssink(&self, sink: &Element, dir: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dir)?;
+        let location = dir.join("segment%05d.ts");
+        let playlist = dir.join("index.m3u8");
+        self.set_property(
+            sink,
+            "location",
+            &location.to_string_lossy().as_ref(),
+        )?;
+        self.set_property(
+            sink,
+            "playlist-location",
+            &playlist.to_string_lossy().as_ref(),
+        )?;
+        self.set_property(sink, "target-duration", &HLS_TARGET_DURATION_SEC)?;
+        self.set_property(sink, "playlist-length", &HLS_PLAYLIST_LENGTH)?;
+        self.set_property(sink, "max-files", &HLS_MAX_FILES)
+    }
+
+    /// Get the target bitrate of the rendition being added (kbps)
+    fn rendition_bitrate(&self) -> Option<u32> {
+        self.rendition
+            .and_then(|k| self.renditions.get(k))
+            .map(|rend| rend.bitrate)
+    }
+
+    /// Check if any HLS sink has adaptive bitrate renditions
+    fn has_renditions(&self) -> bool {
+        !self.renditions.is_empty() && self.sinks.iter().any(Sink::is_hls)
+    }
+
+    /// Check if a sink branch needs RTP payloader
+    fn needs_rtp_pay(&self, sink: &Sink) -> bool {
+        (sink.is_rtp() || sink.is_webrtc()) && !self.is_rtp_passthru()
     }
 
     /// Check if pipeline needs RTP depayloader
@@ -774,20 +3994,31 @@ impl FlowBuilder {
 
     /// Check if RTP can pass unchanged from source to sink
     fn is_rtp_passthru(&self) -> bool {
-        self.source.is_rtp_or_rtsp()
-            && self.sink.is_rtp()
-            && !self.sink.insert_config()
-            && !self.needs_transcode()
+        match &self.sinks[..] {
+            [sink] => {
+                self.source.is_rtp_or_rtsp()
+                    && sink.is_rtp()
+                    && !sink.insert_config()
+                    && !self.needs_transcode()
+                    && !self.needs_h264_parse(sink)
+            },
+            _ => false,
+        }
     }
 
     /// Check if pipeline needs transcoding
     fn needs_transcode(&self) -> bool {
-        self.source.encoding != self.sink.encoding() || self.has_text()
+        self.sinks
+            .iter()
+            .any(|sink| self.source.encoding != sink.encoding())
+            || self.has_text()
+            || self.resolution.is_some()
+            || self.has_renditions()
     }
 
-    /// Check if pipeline needs encoding
-    fn needs_encode(&self) -> bool {
-        self.sink.encoding() != Encoding::RAW && self.needs_transcode()
+    /// Check if a sink branch needs encoding
+    fn needs_encode(&self, sink: &Sink) -> bool {
+        sink.encoding() != Encoding::RAW && self.needs_transcode()
     }
 
     /// Check if pipeline needs decoding
@@ -795,18 +4026,99 @@ impl FlowBuilder {
         self.source.encoding != Encoding::RAW && self.needs_transcode()
     }
 
+    /// Check if a sink branch needs H.264 stream format conversion
+    fn needs_h264_parse(&self, sink: &Sink) -> bool {
+        sink.encoding() == Encoding::H264
+            && (self.h264_stream_format.is_some()
+                || self.h264_alignment.is_some())
+    }
+
+    /// Add H.264 parser with stream format / alignment caps
+    fn add_h264_parse(&mut self) -> Result<(), Error> {
+        let mut values: Vec<(&str, &dyn ToSendValue)> = vec![];
+        if let Some(stream_format) = &self.h264_stream_format {
+            match stream_format.as_str() {
+                "byte-stream" | "avc" | "avc3" => (),
+                _ => {
+                    flow_log!(
+                        error,
+                        self,
+                        "{}: invalid stream format {:?}",
+                        self,
+                        stream_format
+                    );
+                    return Err(Error::Other("invalid stream format"));
+                },
+            }
+            values.push(("stream-format", stream_format));
+        }
+        if let Some(alignment) = &self.h264_alignment {
+            match alignment.as_str() {
+                "au" | "nal" => (),
+                _ => {
+                    flow_log!(
+                        error,
+                        self,
+                        "{}: invalid alignment {:?}",
+                        self,
+                        alignment
+                    );
+                    return Err(Error::Other("invalid alignment"));
+                },
+            }
+            values.push(("alignment", alignment));
+        }
+        let caps = Caps::new_simple("video/x-h264", &values);
+        let fltr = self.make_element("capsfilter", "parsecaps")?;
+        self.set_property(&fltr, "caps", &caps)?;
+        self.add_element(fltr)?;
+        self.add_element(self.make_element("h264parse", "parse")?)
+    }
+
+    /// Add parser to convert H.264 / H.265 to byte-stream format
+    ///
+    /// Without a container, a stream written to a pipe must be Annex-B, with
+    /// parameter sets in-band.  Depayloaders and encoders may otherwise
+    /// negotiate length-prefixed (`avc` / `hvc1`) format.
+    fn add_byte_stream_parse(
+        &mut self,
+        encoding: Encoding,
+    ) -> Result<(), Error> {
+        let (media, parser) = match encoding {
+            Encoding::H264 => ("video/x-h264", "h264parse"),
+            Encoding::H265 => ("video/x-h265", "h265parse"),
+            _ => return Ok(()),
+        };
+        let caps = Caps::new_simple(
+            media,
+            &[("stream-format", &"byte-stream"), ("alignment", &"au")],
+        );
+        let fltr = self.make_element("capsfilter", "parsecaps")?;
+        self.set_property(&fltr, "caps", &caps)?;
+        self.add_element(fltr)?;
+        let parse = self.make_element(parser, "parse")?;
+        // send parameter sets with every IDR frame
+        self.set_property(&parse, "config-interval", &-1)?;
+        self.add_element(parse)
+    }
+
     /// Add RTP payload element
-    fn add_rtp_pay(&mut self) -> Result<(), Error> {
-        let pay = make_element(self.sink.encoding().rtp_pay()?, None)?;
-        if self.sink.insert_config() {
-            match self.sink.encoding() {
+    fn add_rtp_pay(&mut self, sink: &Sink) -> Result<(), Error> {
+        let pay = self.make_element(sink.encoding().rtp_pay()?, "pay")?;
+        if sink.insert_config() {
+            match sink.encoding() {
                 Encoding::MPEG4 => {
-                    // send configuration headers once per second
-                    set_property(&pay, "config-interval", &1u32)?;
+                    // send configuration headers once per second by default
+                    let interval = self
+                        .config_interval
+                        .and_then(|i| u32::try_from(i).ok())
+                        .unwrap_or(1);
+                    self.set_property(&pay, "config-interval", &interval)?;
                 },
                 Encoding::H264 | Encoding::H265 => {
-                    // send sprop parameter sets every IDR frame (-1)
-                    set_property(&pay, "config-interval", &(-1))?;
+                    // send sprop parameter sets every IDR frame by default
+                    let interval = self.config_interval.unwrap_or(-1);
+                    self.set_property(&pay, "config-interval", &interval)?;
                 },
                 _ => (),
             }
@@ -815,66 +4127,194 @@ impl FlowBuilder {
     }
 
     /// Add encode elements
-    fn add_encode(&mut self) -> Result<(), Error> {
-        match self.sink.encoding() {
+    fn add_encode(&mut self, sink: &Sink) -> Result<(), Error> {
+        match sink.encoding() {
             Encoding::RAW => Ok(()),
-            Encoding::MJPEG => self.add_element(make_element("jpegenc", None)?),
+            Encoding::MJPEG => {
+                self.add_element(self.make_element("jpegenc", "enc")?)
+            },
             Encoding::MPEG2 => {
-                self.add_element(make_element("mpegtsmux", None)?)?;
-                self.add_element(make_element("mpeg2enc", None)?)
+                self.add_element(self.make_element("mpegtsmux", "mux")?)?;
+                self.add_element(self.make_element("mpeg2enc", "enc")?)
             },
             Encoding::MPEG4 => self.add_element(self.create_mpeg4enc()?),
-            Encoding::H264 => self.add_element(self.create_h264enc()?),
-            Encoding::H265 => self.add_element(self.create_h265enc()?),
-            Encoding::VP8 => self.add_element(self.create_vp8enc()?),
-            Encoding::VP9 => self.add_element(self.create_vp9enc()?),
-            Encoding::AV1 => self.add_element(make_element("av1enc", None)?),
+            Encoding::H264 => {
+                if self.constrained_baseline {
+                    self.add_baseline_caps()?;
+                }
+                self.add_encoder(self.create_h264enc()?)
+            },
+            Encoding::H265 => self.add_encoder(self.create_h265enc()?),
+            Encoding::VP8 => self.add_encoder(self.create_vp8enc()?),
+            Encoding::VP9 => self.add_encoder(self.create_vp9enc()?),
+            Encoding::AV1 => {
+                self.add_element(self.make_element("av1enc", "enc")?)
+            },
             _ => Err(Error::Other("invalid encoding")),
         }
     }
 
+    /// Add an encoder element
+    ///
+    /// VAAPI encoders are preceded by a `vaapipostproc` and capsfilter, to
+    /// upload software-decoded frames as NV12 surfaces.  Otherwise, they can
+    /// fail to negotiate with a decoder producing I420.
+    fn add_encoder(&mut self, enc: Element) -> Result<(), Error> {
+        let vaapi = match enc.get_factory() {
+            Some(factory) => factory.get_name().starts_with("vaapi"),
+            None => false,
+        };
+        self.add_element(enc)?;
+        if vaapi {
+            let caps = Caps::from_str(VAAPI_ENCODE_CAPS)
+                .map_err(|_| Error::InvalidProperty("caps"))?;
+            let fltr = self.make_element("capsfilter", "vaapicaps")?;
+            self.set_property(&fltr, "caps", &caps)?;
+            self.add_element(fltr)?;
+            self.add_element(self.make_element("vaapipostproc", "postproc")?)?;
+        }
+        Ok(())
+    }
+
+    /// Check if a hardware element is available
+    ///
+    /// When missing, returns `false` if software fallback is allowed.
+    fn has_hw_element(
+        &self,
+        factory_name: &'static str,
+    ) -> Result<bool, Error> {
+        if self.elements.has_element(factory_name) {
+            return Ok(true);
+        }
+        if !self.software_fallback {
+            flow_log!(
+                error,
+                self,
+                "{}: missing element {}",
+                self,
+                factory_name
+            );
+            return Err(Error::MissingElement(factory_name));
+        }
+        flow_log!(
+            warn,
+            self,
+            "{}: missing {}, using software fallback",
+            self,
+            factory_name
+        );
+        if let Some(fb) = &self.feedback {
+            let fallback = Feedback::Fallback(self.idx, factory_name.into());
+            if let Err(e) = fb.send(fallback) {
+                flow_log!(error, self, "{}: send {}", self, e);
+            }
+        }
+        Ok(false)
+    }
+
     /// Create MPEG-4 encode element
     fn create_mpeg4enc(&self) -> Result<Element, Error> {
-        make_element("avenc_mpeg4", None)
+        self.make_element("avenc_mpeg4", "enc")
     }
 
     /// Create h.264 encode element
     fn create_h264enc(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => {
-                let enc = make_element("vaapih264enc", None)?;
+            Acceleration::VAAPI if self.has_hw_element("vaapih264enc")? => {
+                let enc = self.make_element("vaapih264enc", "enc")?;
                 // Quality-level ranges to 1 (best) to 7 (worst)
-                set_property(&enc, "quality-level", &6u32)?;
+                self.set_property(&enc, "quality-level", &6u32)?;
                 enc.set_property_from_str("tune", &"low-power");
+                self.set_property(&enc, "max-bframes", &self.max_bframes())?;
+                if self.constrained_baseline {
+                    self.set_property(&enc, "cabac", &false)?;
+                }
+                if let Some(bitrate) = self.rendition_bitrate() {
+                    enc.set_property_from_str("rate-control", &"cbr");
+                    self.set_property(&enc, "bitrate", &bitrate)?;
+                }
+                Ok(enc)
+            },
+            Acceleration::OMX if self.has_hw_element("omxh264enc")? => {
+                let enc = self.make_element("omxh264enc", "enc")?;
+                if let Some(bitrate) = self.rendition_bitrate() {
+                    // OMX target bitrate is in bits per second
+                    let bitrate = bitrate.saturating_mul(1000);
+                    self.set_property(&enc, "target-bitrate", &bitrate)?;
+                }
                 Ok(enc)
             },
-            Acceleration::OMX => make_element("omxh264enc", None),
             _ => {
-                let enc = make_element("x264enc", None)?;
-                enc.set_property_from_str("tune", &"zerolatency");
+                let enc = self.make_element("x264enc", "enc")?;
+                if self.max_bframes() > 0 {
+                    self.set_property(&enc, "bframes", &self.max_bframes())?;
+                } else if self.low_latency {
+                    enc.set_property_from_str("tune", &"zerolatency");
+                }
+                if self.constrained_baseline {
+                    self.set_property(&enc, "cabac", &false)?;
+                }
                 // With the default "medium" speed-preset, the pipeline can't
                 // run live.  With "superfast", the quality is still very good.
                 // ultrafast (1), superfast (2), veryfast (3), faster (4),
                 // fast (5), medium (6), etc.
                 enc.set_property_from_str("speed-preset", &"superfast");
+                if let Some(threads) = self.encoder_threads {
+                    self.set_property(&enc, "threads", &threads)?;
+                }
+                if self.sliced_threads {
+                    self.set_property(&enc, "sliced-threads", &true)?;
+                }
+                if let Some(bitrate) = self.rendition_bitrate() {
+                    self.set_property(&enc, "bitrate", &bitrate)?;
+                }
                 Ok(enc)
             },
         }
     }
 
+    /// Add caps filter for constrained-baseline h.264 profile
+    ///
+    /// The encoder picks its profile from downstream caps.
+    fn add_baseline_caps(&mut self) -> Result<(), Error> {
+        let caps = Caps::new_simple(
+            "video/x-h264",
+            &[("profile", &"constrained-baseline")],
+        );
+        let fltr = self.make_element("capsfilter", "profilecaps")?;
+        self.set_property(&fltr, "caps", &caps)?;
+        self.add_element(fltr)
+    }
+
     /// Create h.265 encode element
     fn create_h265enc(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => {
-                let enc = make_element("vaapih265enc", None)?;
+            Acceleration::VAAPI if self.has_hw_element("vaapih265enc")? => {
+                let enc = self.make_element("vaapih265enc", "enc")?;
                 // Quality-level ranges to 1 (best) to 7 (worst)
-                set_property(&enc, "quality-level", &6u32)?;
+                self.set_property(&enc, "quality-level", &6u32)?;
                 enc.set_property_from_str("tune", &"low-power");
+                self.set_property(&enc, "max-bframes", &self.bframes)?;
                 Ok(enc)
             },
             _ => {
-                let enc = make_element("x265enc", None)?;
-                enc.set_property_from_str("tune", &"zerolatency");
+                let enc = self.make_element("x265enc", "enc")?;
+                let mut options = vec![];
+                if self.bframes > 0 {
+                    options.push(format!("bframes={}", self.bframes));
+                } else if self.low_latency {
+                    enc.set_property_from_str("tune", &"zerolatency");
+                }
+                if let Some(threads) = self.encoder_threads {
+                    options.push(format!("pools={}", threads));
+                }
+                if !options.is_empty() {
+                    self.set_property(
+                        &enc,
+                        "option-string",
+                        &options.join(":"),
+                    )?;
+                }
                 enc.set_property_from_str("speed-preset", &"superfast");
                 Ok(enc)
             },
@@ -884,69 +4324,224 @@ impl FlowBuilder {
     /// Create VP8 encode element
     fn create_vp8enc(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapivp8enc", None),
-            _ => make_element("vp8enc", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapivp8enc")? => {
+                self.make_element("vaapivp8enc", "enc")
+            },
+            _ => self.make_element("vp8enc", "enc"),
         }
     }
 
     /// Create VP9 encode element
     fn create_vp9enc(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapivp9enc", None),
-            _ => make_element("vp9enc", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapivp9enc")? => {
+                self.make_element("vaapivp9enc", "enc")
+            },
+            _ => self.make_element("vp9enc", "enc"),
         }
     }
 
     /// Add source elements
     fn add_source(&mut self) -> Result<(), Error> {
-        if self.source.is_rtp() {
-            self.add_source_rtp()
-        } else if self.source.is_rtsp() {
-            self.add_source_rtsp()
-        } else if self.source.is_http() {
-            self.add_source_http()
-        } else {
-            self.add_source_test()
+        if self.source.is_app() {
+            return self.add_source_app();
+        }
+        match self.source.scheme() {
+            Some(Scheme::Test) => self.add_source_test(),
+            Some(Scheme::Udp) => self.add_source_rtp(),
+            Some(Scheme::Rtsp) | Some(Scheme::Rtsps) => self.add_source_rtsp(),
+            Some(Scheme::Http) | Some(Scheme::Https) => self.add_source_http(),
+            Some(Scheme::File) => self.add_source_file(),
+            Some(Scheme::V4l2) => self.add_source_v4l2(),
+            None => {
+                flow_log!(
+                    error,
+                    self,
+                    "{}: unsupported location {:?}",
+                    self,
+                    self.source.location
+                );
+                Err(Error::Other("unsupported source scheme"))
+            },
+        }
+    }
+
+    /// Add source elements for a local file
+    ///
+    /// Files contain an elementary stream (H.264, H.265, MPEG-4), IVF
+    /// (VP8, VP9, AV1), MPEG-2 TS, PNG or JPEG images.  Other containers
+    /// are not supported.
+    fn add_source_file(&mut self) -> Result<(), Error> {
+        let parser = match self.source.encoding {
+            Encoding::H264 => Some("h264parse"),
+            Encoding::H265 => Some("h265parse"),
+            Encoding::MPEG4 => Some("mpeg4videoparse"),
+            Encoding::VP8 | Encoding::VP9 | Encoding::AV1 => Some("ivfparse"),
+            Encoding::PNG | Encoding::MJPEG | Encoding::MPEG2 => None,
+            _ => return Err(Error::Other("invalid encoding for file")),
+        };
+        if let Some(parser) = parser {
+            self.add_element(self.make_element(parser, "parse")?)?;
+        }
+        let path = self.source.location.trim_start_matches("file://");
+        let src = self.make_element("filesrc", "filesrc")?;
+        self.set_property(&src, "location", &path)?;
+        self.add_element(src)
+    }
+
+    /// Add source elements for a V4L2 capture device
+    ///
+    /// The device path follows the scheme (`v4l2:///dev/video0`), or the
+    /// default device is used if it is empty.
+    fn add_source_v4l2(&mut self) -> Result<(), Error> {
+        if self.source.encoding == Encoding::RAW {
+            let convert = self.make_element("videoconvert", "convert")?;
+            self.add_element(convert)?;
+        }
+        let device = self.source.location.trim_start_matches("v4l2://");
+        let src = self.make_element("v4l2src", "v4l2src")?;
+        if !device.is_empty() {
+            self.set_property(&src, "device", &device)?;
         }
+        self.add_element(src)
     }
 
     /// Add source elements for an RTP flow
     fn add_source_rtp(&mut self) -> Result<(), Error> {
         if !self.source.is_rtsp() {
-            let jtr = make_element("rtpjitterbuffer", Some("jitter"))?;
-            set_property(&jtr, "latency", &self.source.latency)?;
-            set_property(&jtr, "max-dropout-time", &self.source.timeout_ms())?;
+            let jtr = self.make_element("rtpjitterbuffer", "jitter")?;
+            self.set_property(&jtr, "latency", &self.source.latency_ms())?;
+            self.set_property(
+                &jtr,
+                "max-dropout-time",
+                &self.source.max_dropout,
+            )?;
             self.add_element(jtr)?;
-            let fltr = make_element("capsfilter", None)?;
+            let fltr = self.make_element("capsfilter", "rtpcaps")?;
             let caps = self.create_rtp_caps()?;
-            set_property(&fltr, "caps", &caps)?;
+            self.set_property(&fltr, "caps", &caps)?;
+            if self.needs_sprops() {
+                self.add_sprops_probe(&fltr, caps)?;
+            }
             self.add_element(fltr)?;
         }
-        let src = make_element("udpsrc", None)?;
-        set_property(&src, "uri", &self.source.location)?;
+        let src = self.make_element("udpsrc", "src")?;
+        self.set_property(&src, "uri", &self.source.location)?;
+        if self.source.skip_first_bytes > 0 {
+            let skip = i32::from(self.source.skip_first_bytes);
+            self.set_property(&src, "skip-first-bytes", &skip)?;
+        }
         // Post GstUDPSrcTimeout messages after timeout (0 for disabled)
-        set_property(&src, "timeout", &self.source.timeout_ns())?;
+        self.set_property(&src, "timeout", &self.source.timeout_ns())?;
         self.add_element(src)
     }
 
+    /// Check if sprops should be learned from in-band parameter sets
+    fn needs_sprops(&self) -> bool {
+        self.source.encoding == Encoding::H264
+            && self.source.sprops.is_none()
+            && self.source.rtp_caps.is_none()
+    }
+
+    /// Add a probe to learn sprops from in-band SPS / PPS
+    ///
+    /// Once both are seen, they are added to the RTP caps.  The depayloader
+    /// can then decode after a restart, even if the sender stops sending
+    /// them in-band (some only send them when a stream starts).
+    fn add_sprops_probe(
+        &self,
+        fltr: &Element,
+        caps: Caps,
+    ) -> Result<(), Error> {
+        let pad = fltr
+            .get_static_pad("sink")
+            .ok_or(Error::Other("no sink pad"))?;
+        let idx = self.idx;
+        let weak = fltr.downgrade();
+        let param_sets = Mutex::new(ParamSets::default());
+        pad.add_probe(PadProbeType::BUFFER, move |_pad, info| {
+            if let Some(PadProbeData::Buffer(buffer)) = &info.data {
+                if let Ok(map) = buffer.map_readable() {
+                    let mut param_sets = param_sets.lock().unwrap();
+                    for nal in rtp_h264_nals(map.as_slice()) {
+                        param_sets.update(nal);
+                    }
+                    if let (Some(sprops), Some(fltr)) =
+                        (param_sets.sprops(), weak.upgrade())
+                    {
+                        info!(
+                            flow = idx;
+                            "Flow{}: learned sprops {}",
+                            idx, sprops
+                        );
+                        let mut caps = caps.copy();
+                        if let Some(caps) = caps.get_mut() {
+                            caps.set_simple(&[(
+                                "sprop-parameter-sets",
+                                &sprops,
+                            )]);
+                        }
+                        if let Err(e) = set_property(&fltr, "caps", &caps) {
+                            warn!(
+                                flow = idx;
+                                "Flow{}: sprops caps -- {}",
+                                idx, e
+                            );
+                        }
+                        return PadProbeReturn::Remove;
+                    }
+                }
+            }
+            PadProbeReturn::Ok
+        });
+        Ok(())
+    }
+
     /// Create RTP caps for filter element
+    ///
+    /// The `encoding-name` is derived from the source encoding, so that
+    /// depayloaders link without an SDP (pure `udp://` sources).
     fn create_rtp_caps(&self) -> Result<Caps, Error> {
+        if let Some(caps) = &self.source.rtp_caps {
+            return Caps::from_str(caps)
+                .map_err(|_| Error::Other("invalid RTP caps"));
+        }
+        if let Some(audio) = &self.source.audio {
+            return Ok(Caps::new_simple(
+                "application/x-rtp",
+                &[
+                    ("media", &"audio"),
+                    ("clock-rate", &audio.rtp_clock_rate()),
+                    ("encoding-name", &audio.rtp_encoding_name()),
+                ],
+            ));
+        }
+        if self.source.clock_rate <= 0 {
+            flow_log!(
+                error,
+                self,
+                "{}: invalid clock rate {}",
+                self,
+                self.source.clock_rate
+            );
+            return Err(Error::Other("invalid clock rate"));
+        }
         let mut values: Vec<(&str, &dyn ToSendValue)> =
-            vec![("clock-rate", &RTP_VIDEO_CLOCK_RATE)];
-        if let Encoding::MPEG2 = self.source.encoding {
-            values.push(("encoding-name", &"MP2T"));
+            vec![("media", &"video"), ("clock-rate", &self.source.clock_rate)];
+        let encoding_name = self.source.encoding.rtp_encoding_name();
+        if let Some(encoding_name) = &encoding_name {
+            values.push(("encoding-name", encoding_name));
         }
         if let Some(sprops) = &self.source.sprops {
-            values.push(("sprop-parameter-sets", &sprops));
-            return Ok(Caps::new_simple("application/x-rtp", &values[..]));
+            values.push(("sprop-parameter-sets", sprops));
         }
         Ok(Caps::new_simple("application/x-rtp", &values[..]))
     }
 
     /// Add source elements for an RTSP flow
     fn add_source_rtsp(&mut self) -> Result<(), Error> {
-        let src = make_element("rtspsrc", None)?;
-        set_property(&src, "location", &self.source.location)?;
+        let src = self.make_element("rtspsrc", "src")?;
+        self.set_property(&src, "location", &self.source.location)?;
         match &self.source.rtsp_transport {
             Transport::ANY => (),
             Transport::UDP => src.set_property_from_str("protocols", &"udp"),
@@ -955,17 +4550,29 @@ impl FlowBuilder {
             },
             Transport::TCP => src.set_property_from_str("protocols", &"tcp"),
         }
-        set_property(&src, "tcp-timeout", &self.source.timeout_us())?;
+        if self.source.is_rtsps() && !self.source.tls_validation {
+            // Allow unknown CA and mismatched identity (self-signed)
+            src.set_property_from_str(
+                "tls-validation-flags",
+                &"expired+revoked+insecure+not-activated+generic-error",
+            );
+        }
+        // Initial connect timeout is replaced once connected
+        self.set_property(
+            &src,
+            "tcp-timeout",
+            &self.source.connect_timeout_us(),
+        )?;
         // Retry TCP after UDP timeout (0 for disabled)
-        set_property(&src, "timeout", &self.source.timeout_us())?;
-        set_property(&src, "latency", &self.source.latency)?;
-        set_property(&src, "do-retransmission", &false)?;
-        set_property(&src, "user-agent", &AGENT)?;
-        match src.connect("select-stream", false, |values| {
-            match values[1].get::<u32>() {
-                Ok(Some(num)) => Some((num == STREAM_NUM_VIDEO).to_value()),
-                _ => None,
-            }
+        self.set_property(&src, "timeout", &self.source.timeout_us())?;
+        self.set_property(&src, "latency", &self.source.latency_ms())?;
+        self.set_property(&src, "do-retransmission", &false)?;
+        self.set_property(&src, "user-agent", &AGENT)?;
+        let audio = self.source.audio.is_some();
+        let idx = self.idx;
+        let selected = AtomicBool::new(false);
+        match src.connect("select-stream", false, move |values| {
+            Some(select_stream(idx, audio, &selected, values).to_value())
         }) {
             Ok(_) => self.add_element(src),
             Err(_) => Err(Error::ConnectSignal("select-stream")),
@@ -974,11 +4581,26 @@ impl FlowBuilder {
 
     /// Add source elements for an HTTP flow
     fn add_source_http(&mut self) -> Result<(), Error> {
-        let src = make_element("souphttpsrc", None)?;
-        set_property(&src, "location", &self.location_http()?)?;
+        let src = self.make_element("souphttpsrc", "httpsrc")?;
+        self.set_property(&src, "location", &self.location_http()?)?;
         // Blocking request timeout (0 for no timeout)
-        set_property(&src, "timeout", &self.source.timeout_s())?;
-        set_property(&src, "retries", &0)?;
+        self.set_property(&src, "timeout", &self.source.timeout_s())?;
+        self.set_property(&src, "retries", &0)?;
+        if let Some(proxy) = &self.source.proxy {
+            if !is_proxy_uri(proxy) {
+                flow_log!(error, self, "{}: invalid proxy {:?}", self, proxy);
+                return Err(Error::Other("invalid proxy"));
+            }
+            self.set_property(&src, "proxy", proxy)?;
+        }
+        self.set_property(&src, "ssl-strict", &self.source.ssl_strict)?;
+        if !self.source.headers.is_empty() {
+            let mut headers = Structure::new_empty("extra-headers");
+            for (name, value) in &self.source.headers {
+                headers.set(name, value);
+            }
+            self.set_property(&src, "extra-headers", &headers)?;
+        }
         self.add_element(src)
     }
 
@@ -990,43 +4612,150 @@ impl FlowBuilder {
         }
     }
 
+    /// Add source element for an application flow
+    fn add_source_app(&mut self) -> Result<(), Error> {
+        let src = self.make_element("appsrc", "appsrc")?;
+        if let Some(caps) = &self.source.app_caps {
+            let caps = Caps::from_str(caps).map_err(|_| {
+                flow_log!(error, self, "{}: invalid app caps {:?}", self, caps);
+                Error::InvalidProperty("caps")
+            })?;
+            self.set_property(&src, "caps", &caps)?;
+        }
+        src.set_property_from_str("format", "time");
+        self.set_property(&src, "is-live", &true)?;
+        self.set_property(&src, "do-timestamp", &true)?;
+        self.add_element(src)
+    }
+
     /// Add source element for a test flow
     fn add_source_test(&mut self) -> Result<(), Error> {
-        let src = make_element("videotestsrc", None)?;
-        src.set_property_from_str("pattern", "smpte75");
-        set_property(&src, "is-live", &true)?;
-        self.add_element(src)
+        self.add_element(self.create_test_src("src", "smpte75")?)
+    }
+
+    /// Create a live video test source
+    fn create_test_src(
+        &self,
+        name: &str,
+        pattern: &str,
+    ) -> Result<Element, Error> {
+        let src = self.make_element("videotestsrc", name)?;
+        src.set_property_from_str("pattern", pattern);
+        self.set_property(&src, "is-live", &true)?;
+        Ok(src)
     }
 
     /// Add decode elements
     fn add_decode(&mut self) -> Result<(), Error> {
         match self.source.encoding {
             Encoding::PNG => {
-                self.add_element(make_element("imagefreeze", None)?)?;
-                self.add_element(make_element("videoconvert", None)?)?;
-                self.add_element(make_element("pngdec", None)?)
+                let freeze = self.make_element("imagefreeze", "freeze")?;
+                if freeze.find_property("allow-replace").is_some() {
+                    // Replace frozen frame when refreshed
+                    self.set_property(&freeze, "allow-replace", &true)?;
+                }
+                self.add_element(freeze)?;
+                self.add_element(
+                    self.make_element("videoconvert", "convert")?,
+                )?;
+                self.add_element(self.make_element("pngdec", "pngdec")?)
+            },
+            Encoding::MJPEG => {
+                self.add_element(self.make_element("jpegdec", "dec")?)
             },
-            Encoding::MJPEG => self.add_element(make_element("jpegdec", None)?),
             Encoding::MPEG2 => {
-                self.add_element(make_element("mpeg2dec", None)?)?;
-                self.add_element(make_element("tsdemux", None)?)
+                self.add_element(self.make_element("mpeg2dec", "dec")?)?;
+                self.add_element(self.make_element("tsdemux", "demux")?)
             },
             Encoding::MPEG4 => self.add_element(self.create_mpeg4dec()?),
             Encoding::H264 => self.add_element(self.create_h264dec()?),
             Encoding::H265 => self.add_element(self.create_h265dec()?),
             Encoding::VP8 => self.add_element(self.create_vp8dec()?),
             Encoding::VP9 => self.add_element(self.create_vp9dec()?),
-            Encoding::AV1 => self.add_element(make_element("av1dec", None)?),
+            Encoding::AV1 => {
+                self.add_element(self.make_element("av1dec", "dec")?)
+            },
             _ => Err(Error::Other("invalid encoding")),
         }
     }
 
+    /// Add an input selector to fill gaps from a test source
+    ///
+    /// The fill source is linked first, through a capsfilter; the live
+    /// branch links to the next requested pad, which is made active.  A
+    /// probe on that pad records the time of the latest data, and copies
+    /// the live format to the fill capsfilter.
+    fn add_gap_fill(&mut self, fill: Element) -> Result<(), Error> {
+        let pipeline = self
+            .pipeline
+            .upgrade()
+            .ok_or(Error::Other("pipeline gone"))?;
+        self.set_property(&fill, "is-live", &true)?;
+        let sel = self.make_element("input-selector", "selector")?;
+        self.add_element(sel.clone())?;
+        let fltr = self.make_element("capsfilter", "fillcaps")?;
+        if let Err(_) = pipeline.add_many(&[&fill, &fltr]) {
+            return Err(Error::PipelineAdd());
+        }
+        if let Err(_) = fill.link(&fltr).and_then(|_| fltr.link(&sel)) {
+            return Err(Error::Other("gap fill link failed"));
+        }
+        let start = Instant::now();
+        let last_ms = Arc::new(AtomicU64::new(0));
+        let live_ms = Arc::clone(&last_ms);
+        let fltr = fltr.downgrade();
+        let idx = self.idx;
+        sel.connect_pad_added(move |sel, pad| {
+            let live_ms = Arc::clone(&live_ms);
+            let fltr = fltr.clone();
+            let mask = PadProbeType::BUFFER | PadProbeType::EVENT_DOWNSTREAM;
+            pad.add_probe(mask, move |_pad, info| {
+                match &info.data {
+                    Some(PadProbeData::Buffer(_)) => {
+                        live_ms.store(elapsed_ms(start), Ordering::Relaxed);
+                    },
+                    Some(PadProbeData::Event(ev)) => {
+                        if let EventView::Caps(c) = ev.view() {
+                            let caps = fill_caps(c.get_caps());
+                            if let (Some(fltr), Some(caps)) =
+                                (fltr.upgrade(), caps)
+                            {
+                                if let Err(e) = fltr.set_property("caps", &caps)
+                                {
+                                    warn!(
+                                        flow = idx;
+                                        "Flow{}: fill caps -- {}",
+                                        idx, e
+                                    );
+                                }
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+                PadProbeReturn::Ok
+            });
+            if let Err(e) = sel.set_property("active-pad", pad) {
+                warn!(flow = idx; "Flow{}: gap fill -- {}", idx, e);
+            }
+        });
+        let weak = sel.downgrade();
+        glib::source::timeout_add(GAP_CHECK_MS, move || {
+            check_gap(idx, &weak, start, &last_ms)
+        });
+        Ok(())
+    }
+
     /// Add queue element
     fn add_queue(&mut self) -> Result<(), Error> {
-        let que = make_element("queue", None)?;
-        set_property(&que, "max-size-time", &SEC_NS)?;
-        set_property(&que, "flush-on-eos", &true)?;
-        if self.needs_encode() {
+        let name = match &self.head {
+            Some(head) => format!("{}_queue", head.get_name()),
+            None => elem_name(self.idx, "queue"),
+        };
+        let que = self.elements.make_element("queue", &name)?;
+        self.set_property(&que, "max-size-time", &SEC_NS)?;
+        self.set_property(&que, "flush-on-eos", &true)?;
+        if self.sinks.iter().any(|sink| self.needs_encode(sink)) {
             // leak (drop) packets -- when encoding cannot keep up
             que.set_property_from_str("leaky", &"downstream");
         }
@@ -1035,19 +4764,23 @@ impl FlowBuilder {
 
     /// Create MPEG-4 decode element
     fn create_mpeg4dec(&self) -> Result<Element, Error> {
-        let dec = make_element("avdec_mpeg4", None)?;
-        set_property(&dec, "output-corrupt", &false)?;
+        let dec = self.make_element("avdec_mpeg4", "dec")?;
+        self.set_property(&dec, "output-corrupt", &false)?;
         Ok(dec)
     }
 
     /// Create h.264 decode element
     fn create_h264dec(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapih264dec", None),
-            Acceleration::OMX => make_element("omxh264dec", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapih264dec")? => {
+                self.make_element("vaapih264dec", "dec")
+            },
+            Acceleration::OMX if self.has_hw_element("omxh264dec")? => {
+                self.make_element("omxh264dec", "dec")
+            },
             _ => {
-                let dec = make_element("avdec_h264", None)?;
-                set_property(&dec, "output-corrupt", &false)?;
+                let dec = self.make_element("avdec_h264", "dec")?;
+                self.set_property(&dec, "output-corrupt", &false)?;
                 Ok(dec)
             },
         }
@@ -1056,76 +4789,434 @@ impl FlowBuilder {
     /// Create h.265 decode element
     fn create_h265dec(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapih265dec", None),
-            _ => make_element("libde265dec", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapih265dec")? => {
+                self.make_element("vaapih265dec", "dec")
+            },
+            _ => self.make_element("libde265dec", "dec"),
         }
     }
 
     /// Create VP8 decode element
     fn create_vp8dec(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapivp8dec", None),
-            Acceleration::OMX => make_element("omxvp8dec", None),
-            _ => make_element("vp8dec", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapivp8dec")? => {
+                self.make_element("vaapivp8dec", "dec")
+            },
+            Acceleration::OMX if self.has_hw_element("omxvp8dec")? => {
+                self.make_element("omxvp8dec", "dec")
+            },
+            _ => self.make_element("vp8dec", "dec"),
         }
     }
 
     /// Create VP9 decode element
     fn create_vp9dec(&self) -> Result<Element, Error> {
         match self.acceleration {
-            Acceleration::VAAPI => make_element("vaapivp9dec", None),
-            _ => make_element("vp9dec", None),
+            Acceleration::VAAPI if self.has_hw_element("vaapivp9dec")? => {
+                self.make_element("vaapivp9dec", "dec")
+            },
+            _ => self.make_element("vp9dec", "dec"),
+        }
+    }
+
+    /// Configure a UDP sink element for a destination
+    fn config_udpsink(
+        &self,
+        sink: &Element,
+        dest: &Destination,
+    ) -> Result<(), Error> {
+        self.set_property(sink, "host", &dest.address)?;
+        self.set_property(sink, "port", &dest.port)?;
+        let ttl = dest.ttl.or(self.ttl).unwrap_or(TTL_MULTICAST);
+        self.set_property(sink, "ttl-mc", &ttl)?;
+        if let Some(dscp) = self.dscp {
+            self.set_property(sink, "qos-dscp", &dscp)?;
         }
+        if let Some(iface) = &dest.iface {
+            self.set_property(sink, "multicast-iface", iface)?;
+        }
+        if dest.is_multicast() {
+            self.set_property(
+                sink,
+                "auto-multicast",
+                &!self.no_auto_multicast,
+            )?;
+            self.set_property(sink, "loop", &self.multicast_loop)?;
+        }
+        if let Some(max_bitrate) = self.max_bitrate {
+            // Throttle rate -- 0 means unlimited
+            self.set_property(sink, "max-bitrate", &max_bitrate)?;
+        }
+        if let Some(buffer_size) = self.sink_buffer_size {
+            let buffer_size = i32::try_from(buffer_size)?;
+            self.set_property(sink, "buffer-size", &buffer_size)?;
+        }
+        Ok(())
     }
 
     /// Create a sink element
-    fn create_sink(&self) -> Result<Element, Error> {
-        let sink = make_element(
-            self.sink.factory_name(self.acceleration),
-            Some("sink"),
+    fn create_sink(&self, name: &str, config: &Sink) -> Result<Element, Error> {
+        let sink = self.make_element(
+            config.factory_name(self.acceleration, self.window_sink),
+            name,
         )?;
-        match &self.sink {
+        match config {
             Sink::RTP(addr, port, _, _) => {
-                set_property(&sink, "host", addr)?;
-                set_property(&sink, "port", port)?;
-                set_property(&sink, "ttl-mc", &TTL_MULTICAST)?;
+                self.config_udpsink(&sink, &Destination::new(addr, *port))?;
             },
             Sink::WINDOW(crop) => {
-                set_property(
-                    &sink,
-                    "force-aspect-ratio",
-                    &crop.aspect.as_bool(),
-                )?;
+                if sink.find_property("force-aspect-ratio").is_some() {
+                    self.set_property(
+                        &sink,
+                        "force-aspect-ratio",
+                        &crop.aspect.as_bool(),
+                    )?;
+                }
+                if let Some(display) = &self.window_display {
+                    self.config_display(&sink, display)?;
+                }
                 if let Some(handle) = self.handle {
                     match sink.clone().dynamic_cast::<VideoOverlay>() {
-                        Ok(overlay) => unsafe {
-                            overlay.set_window_handle(handle);
+                        Ok(overlay) => {
+                            unsafe {
+                                overlay.set_window_handle(handle);
+                            }
+                            if let Some((x, y, w, h)) = self.window_geometry {
+                                overlay.set_render_rectangle(x, y, w, h)?;
+                            }
                         },
-                        Err(_) => error!("{}: invalid video overlay", self),
+                        Err(_) => flow_log!(
+                            error,
+                            self,
+                            "{}: invalid video overlay",
+                            self
+                        ),
                     }
                 }
             },
+            Sink::WEBRTC(_, signal) => {
+                sink.set_property_from_str("bundle-policy", &"max-bundle");
+                if let Some(stun_server) = &self.stun_server {
+                    self.set_property(&sink, "stun-server", stun_server)?;
+                }
+                self.connect_webrtc(&sink, signal.clone())?;
+            },
+            Sink::HTTP(port, path) => {
+                self.set_property(&sink, "sync", &false)?;
+                // Slow clients skip to the next frame header
+                sink.set_property_from_str("sync-method", &"latest-keyframe");
+                sink.set_property_from_str("recover-policy", &"keyframe");
+                sink.set_property_from_str("unit-format", &"buffers");
+                self.set_property(&sink, "units-soft-max", &MJPEG_SOFT_MAX)?;
+                if let Err(_) =
+                    sink.connect("client-fd-removed", false, |values| {
+                        if let Ok(Some(fd)) = values[1].get::<i32>() {
+                            http::close_fd(fd);
+                        }
+                        None
+                    })
+                {
+                    return Err(Error::ConnectSignal("client-fd-removed"));
+                }
+                http::spawn_server(self.idx, *port, path.clone(), &sink);
+            },
+            Sink::HLS(dir) => self.config_hlssink(&sink, Path::new(dir))?,
+            Sink::PIPE(target, _) => self.config_fdsink(&sink, target)?,
             _ => (),
         }
+        self.config_sync(&sink)?;
+        // Count egress on the first sink only
+        if name == "sink" && self.branch == 0 {
+            self.add_egress_probe(&sink);
+        }
         Ok(sink)
     }
 
+    /// Configure an `fdsink` to write to a file descriptor or named pipe
+    fn config_fdsink(&self, sink: &Element, target: &str) -> Result<(), Error> {
+        if let Some(fd) = target.strip_prefix("fd:") {
+            let fd: i32 = fd.parse()?;
+            return self.set_property(sink, "fd", &fd);
+        }
+        // Open without blocking, so a missing reader is an error (ENXIO)
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(target)
+            .map_err(|e| {
+                flow_log!(
+                    error,
+                    self,
+                    "{}: opening pipe {:?}: {}",
+                    self,
+                    target,
+                    e
+                );
+                e
+            })?;
+        // Check the opened file, so it can't be swapped after checking
+        if !file.metadata()?.file_type().is_fifo() {
+            flow_log!(error, self, "{}: not a named pipe {:?}", self, target);
+            return Err(Error::Other("pipe is not a FIFO"));
+        }
+        let fd = file.as_raw_fd();
+        let res = unsafe { libc::fcntl(fd, libc::F_SETFL, 0) };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        self.set_property(sink, "fd", &fd)?;
+        // File is closed when the sink is finalized
+        unsafe {
+            sink.set_data("pipe-file", file);
+        }
+        Ok(())
+    }
+
+    /// Configure clock sync and maximum lateness of a sink
+    ///
+    /// Sinks without these properties (bins, such as `webrtcbin`) are left
+    /// unchanged.
+    fn config_sync(&self, sink: &Element) -> Result<(), Error> {
+        if let Some(sync) = self.sync {
+            if sink.find_property("sync").is_some() {
+                self.set_property(sink, "sync", &sync)?;
+            }
+        }
+        if let Some(max_lateness) = self.max_lateness {
+            if sink.find_property("max-lateness").is_some() {
+                let ns = if max_lateness < 0 {
+                    -1
+                } else {
+                    max_lateness.saturating_mul(1_000_000)
+                };
+                self.set_property(sink, "max-lateness", &ns)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Configure the display of a window sink
+    fn config_display(
+        &self,
+        sink: &Element,
+        display: &str,
+    ) -> Result<(), Error> {
+        let factory = sink.get_factory().map(|f| f.get_name());
+        if sink.find_property("display-name").is_some() {
+            // vaapisink: display type must match a DRM device path
+            if display.starts_with("/dev/dri/") {
+                sink.set_property_from_str("display", &"drm");
+            }
+            self.set_property(sink, "display-name", &display)?;
+        } else if let Some(true) = sink
+            .find_property("display")
+            .map(|p| p.get_value_type() == String::static_type())
+        {
+            self.set_property(sink, "display", &display)?;
+        } else {
+            flow_log!(
+                warn,
+                self,
+                "{}: display not supported by {:?}",
+                self,
+                factory
+            );
+            return Ok(());
+        }
+        flow_log!(info, self, "{}: window display {}", self, display);
+        Ok(())
+    }
+
+    /// Connect signals for WebRTC negotiation
+    fn connect_webrtc(
+        &self,
+        webrtc: &Element,
+        signal: Sender<Signal>,
+    ) -> Result<(), Error> {
+        let signal = Arc::new(Mutex::new(signal));
+        let tx = Arc::clone(&signal);
+        let idx = self.idx;
+        if let Err(_) =
+            webrtc.connect("on-negotiation-needed", false, move |values| {
+                match values[0].get::<Element>() {
+                    Ok(Some(webrtc)) => create_offer(idx, &webrtc, &tx),
+                    _ => error!(flow = idx; "Flow{}: invalid webrtcbin", idx),
+                }
+                None
+            })
+        {
+            return Err(Error::ConnectSignal("on-negotiation-needed"));
+        }
+        match webrtc.connect("on-ice-candidate", false, move |values| {
+            match (values[1].get::<u32>(), values[2].get::<String>()) {
+                (Ok(Some(mline)), Ok(Some(candidate))) => {
+                    let msg = Signal::IceCandidate(mline, candidate);
+                    if let Err(e) = signal.lock().unwrap().send(msg) {
+                        error!(flow = idx; "Flow{}: send {}", idx, e);
+                    }
+                },
+                _ => error!(flow = idx; "Flow{}: invalid ICE candidate", idx),
+            }
+            None
+        }) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::ConnectSignal("on-ice-candidate")),
+        }
+    }
+
     /// Create a text overlay element
-    fn create_text(&self) -> Result<Element, Error> {
-        let txt = make_element("textoverlay", Some("txt"))?;
-        set_property(&txt, "auto-resize", &false)?;
-        set_property(&txt, "text", &self.overlay_text.as_ref().unwrap())?;
-        set_property(&txt, "shaded-background", &false)?;
-        set_property(&txt, "color", &OVERLAY_COLOR)?;
+    fn create_text(
+        &self,
+        name: &str,
+        text: &str,
+        halignment: &str,
+        valignment: &str,
+    ) -> Result<Element, Error> {
+        let txt = self.make_element("textoverlay", name)?;
+        self.set_property(&txt, "auto-resize", &false)?;
+        self.set_property(&txt, "text", &text)?;
+        match self.overlay_shading {
+            Some(shading) => {
+                self.set_property(&txt, "shaded-background", &true)?;
+                self.set_property(&txt, "shading-value", &u32::from(shading))?;
+            },
+            None => self.set_property(&txt, "shaded-background", &false)?,
+        }
+        let color = self.overlay_color.unwrap_or(OVERLAY_COLOR);
+        self.set_property(&txt, "color", &color)?;
         txt.set_property_from_str("wrap-mode", &"none");
-        txt.set_property_from_str("halignment", &"right");
-        txt.set_property_from_str("valignment", &"top");
+        txt.set_property_from_str("halignment", halignment);
+        txt.set_property_from_str("valignment", valignment);
         Ok(txt)
     }
 
+    /// Add a probe to update timestamp overlay text for each frame
+    fn add_timestamp_probe(&self, txt: &Element) -> Result<(), Error> {
+        let pad = txt
+            .get_static_pad("video_sink")
+            .ok_or(Error::Other("no video_sink pad"))?;
+        let weak = txt.downgrade();
+        let offset = self.clock.is_some();
+        pad.add_probe(PadProbeType::BUFFER, move |pad, info| {
+            if let (Some(txt), Some(PadProbeData::Buffer(buffer))) =
+                (weak.upgrade(), &info.data)
+            {
+                let text = timestamp_text(pad, &txt, buffer.get_pts(), offset);
+                if let Some(text) = text {
+                    let _ = txt.set_property("text", &text);
+                }
+            }
+            PadProbeReturn::Ok
+        });
+        Ok(())
+    }
+
+    /// Add an identity element to count ingress buffers and bytes
+    ///
+    /// This works for all sources, including `rtspsrc` with dynamic pads.
+    fn add_ingress(&mut self) -> Result<(), Error> {
+        let ingress = self.make_element("identity", "ingress")?;
+        if let Some(pad) = ingress.get_static_pad("sink") {
+            self.add_media_probe(&pad);
+            let buffers = Arc::clone(&self.ingress_buffers);
+            let bytes = Arc::clone(&self.ingress_bytes);
+            pad.add_probe(PadProbeType::BUFFER, move |_pad, info| {
+                if let Some(PadProbeData::Buffer(buffer)) = &info.data {
+                    let size = buffer.get_size() as u64;
+                    buffers.fetch_add(1, Ordering::Relaxed);
+                    bytes.fetch_add(size, Ordering::Relaxed);
+                }
+                PadProbeReturn::Ok
+            });
+        }
+        self.add_element(ingress)
+    }
+
+    /// Add a probe to drop streams with the wrong media type
+    ///
+    /// This guards against sources which send audio to a video flow (or
+    /// video to an audio flow), such as an RTSP camera with a misleading
+    /// SDP, or an MPEG-2 TS with audio.  Caps and buffers are dropped until
+    /// caps with a matching media type are received, rather than causing
+    /// not-negotiated errors downstream.
+    fn add_media_probe(&self, pad: &Pad) {
+        let idx = self.idx;
+        let audio = self.source.audio.is_some();
+        let wrong = AtomicBool::new(false);
+        let mask = PadProbeType::BUFFER | PadProbeType::EVENT_DOWNSTREAM;
+        pad.add_probe(mask, move |_pad, info| match &info.data {
+            Some(PadProbeData::Event(ev)) => match ev.view() {
+                EventView::Caps(c) => {
+                    let caps = c.get_caps();
+                    let is_wrong = is_wrong_media(caps, audio);
+                    if is_wrong {
+                        warn!(
+                            flow = idx;
+                            "Flow{}: dropping stream {}",
+                            idx, caps
+                        );
+                    }
+                    wrong.store(is_wrong, Ordering::Relaxed);
+                    if is_wrong {
+                        PadProbeReturn::Drop
+                    } else {
+                        PadProbeReturn::Ok
+                    }
+                },
+                _ => PadProbeReturn::Ok,
+            },
+            Some(PadProbeData::Buffer(_)) if wrong.load(Ordering::Relaxed) => {
+                PadProbeReturn::Drop
+            },
+            _ => PadProbeReturn::Ok,
+        });
+    }
+
+    /// Add a probe to count egress buffers and bytes on a sink
+    ///
+    /// Sinks without a static sink pad (webrtcbin) are not counted.
+    fn add_egress_probe(&self, sink: &Element) {
+        if let Some(pad) = sink.get_static_pad("sink") {
+            let buffers = Arc::clone(&self.egress_buffers);
+            let bytes = Arc::clone(&self.egress_bytes);
+            pad.add_probe(PadProbeType::BUFFER, move |_pad, info| {
+                if let Some(PadProbeData::Buffer(buffer)) = &info.data {
+                    let size = buffer.get_size() as u64;
+                    buffers.fetch_add(1, Ordering::Relaxed);
+                    bytes.fetch_add(size, Ordering::Relaxed);
+                }
+                PadProbeReturn::Ok
+            });
+        }
+    }
+
+    /// Make an element named for the flow, sink branch and rendition
+    fn make_element(
+        &self,
+        factory_name: &'static str,
+        name: &str,
+    ) -> Result<Element, Error> {
+        let name = match self.rendition {
+            Some(k) => format!("{}_r{}", name, k),
+            None => name.to_string(),
+        };
+        let name = elem_name(self.idx, &branch_name(&name, self.branch));
+        self.elements.make_element(factory_name, &name)
+    }
+
+    /// Set a property of an element
+    fn set_property(
+        &self,
+        elem: &Element,
+        name: &'static str,
+        value: &dyn ToValue,
+    ) -> Result<(), Error> {
+        self.elements.set_property(elem, name, value)
+    }
+
     /// Add an element to pipeline
     fn add_element(&mut self, elem: Element) -> Result<(), Error> {
-        trace!("{}: add_element {}", self, elem.get_name());
+        flow_log!(trace, self, "{}: add_element {}", self, elem.get_name());
         match self.pipeline.upgrade() {
             Some(pipeline) => {
                 if let Err(_) = pipeline.add(&elem) {
@@ -1135,6 +5226,9 @@ impl FlowBuilder {
                     Some(head) => self.link_src_sink(&elem, head)?,
                     None => (),
                 }
+                if self.adding_live {
+                    self.live_branch.push(elem.downgrade());
+                }
                 self.head = Some(elem);
                 Ok(())
             },
@@ -1144,22 +5238,81 @@ impl FlowBuilder {
 
     /// Link a source element with a sink
     fn link_src_sink(&self, src: &Element, sink: Element) -> Result<(), Error> {
-        trace!("{}: {} => {}", self, src.get_name(), sink.get_name());
+        flow_log!(
+            trace,
+            self,
+            "{}: {} => {}",
+            self,
+            src.get_name(),
+            sink.get_name()
+        );
         match src.link(&sink) {
             Ok(()) => {
                 let p0 = src.get_name();
                 let p1 = sink.get_name();
-                trace!("{}: pad linked (static) {} => {}", self, p0, p1);
+                flow_log!(
+                    trace,
+                    self,
+                    "{}: pad linked (static) {} => {}",
+                    self,
+                    p0,
+                    p1
+                );
             },
             Err(_) => {
                 let sink = sink.downgrade(); // weak ref
                 let idx = self.idx;
+                let weak = sink.clone();
                 src.connect_pad_added(move |src, src_pad| {
-                    match sink.upgrade() {
+                    match weak.upgrade() {
                         Some(sink) => link_ghost_pad(idx, src, src_pad, sink),
                         None => error!("sink gone"),
                     }
                 });
+                let weak = sink.clone();
+                let pipeline = self.pipeline.clone();
+                let state_changes = Arc::clone(&self.state_changes);
+                src.connect_pad_removed(move |src, pad| {
+                    let pn = pad.get_name();
+                    debug!(
+                        flow = idx;
+                        "Flow{} pad {} removed: {}",
+                        idx,
+                        pn,
+                        src.get_name()
+                    );
+                    // Pads are also removed on every stop and restart
+                    if !is_pipeline_playing(&pipeline) {
+                        return;
+                    }
+                    let changes = state_changes.load(Ordering::Relaxed);
+                    let state_changes = Arc::clone(&state_changes);
+                    let pipeline = pipeline.clone();
+                    let src = src.downgrade();
+                    let sink = weak.clone();
+                    glib::source::timeout_add(PAD_RELINK_MS, move || {
+                        // Drop the check if the state changed meanwhile
+                        if state_changes.load(Ordering::Relaxed) == changes
+                            && is_pipeline_playing(&pipeline)
+                        {
+                            if let Some(src) = src.upgrade() {
+                                check_dynamic_link(
+                                    idx,
+                                    &src,
+                                    &sink,
+                                    "pad removed",
+                                );
+                            }
+                        }
+                        glib::Continue(false)
+                    });
+                });
+                let pipeline = self.pipeline.clone();
+                src.connect_no_more_pads(move |src| {
+                    if is_pipeline_playing(&pipeline) {
+                        check_dynamic_link(idx, src, &sink, "no pad linked");
+                    }
+                });
             },
         }
         Ok(())
@@ -1169,18 +5322,33 @@ impl FlowBuilder {
     fn handle_message(&mut self, msg: &Message) -> glib::Continue {
         match msg.view() {
             MessageView::AsyncDone(_) => {
-                trace!("{}: playing", self);
+                flow_log!(trace, self, "{}: playing", self);
+                self.failures = 0;
+                self.end_freeze();
+                self.end_connect_timeout();
+                self.record_state_change(true);
                 if let Some(fb) = &self.feedback {
                     if let Err(e) = fb.send(Feedback::Playing(self.idx)) {
-                        error!("{}: send {}", self, e);
+                        flow_log!(error, self, "{}: send {}", self, e);
                     }
                 }
             },
             MessageView::Eos(_) => {
-                trace!("{}: end of stream", self);
-                self.stop();
+                flow_log!(trace, self, "{}: end of stream", self);
+                if self.source.is_looping() {
+                    self.seek_start(SeekFlags::FLUSH | SeekFlags::SEGMENT);
+                } else {
+                    self.stop();
+                }
+            },
+            MessageView::SegmentDone(_) => {
+                flow_log!(trace, self, "{}: segment done", self);
+                self.seek_start(SeekFlags::SEGMENT);
             },
             MessageView::StateChanged(chg) => {
+                if let Some(true) = chg.get_src().map(|s| s.is::<Pipeline>()) {
+                    self.state_changes.fetch_add(1, Ordering::Relaxed);
+                }
                 match (chg.get_current(), &chg.get_src()) {
                     (State::Playing, Some(src)) => {
                         if src.is::<Pipeline>() {
@@ -1192,36 +5360,315 @@ impl FlowBuilder {
                 }
             },
             MessageView::Error(err) => {
-                debug!("{}: error {}", self, err.get_error());
+                let elem = msg_src_name(msg);
+                let error = err.get_error();
+                match err.get_debug() {
+                    Some(dbg) => {
+                        flow_log!(
+                            error,
+                            self,
+                            "{}: {} error {} ({})",
+                            self,
+                            elem,
+                            error,
+                            dbg
+                        )
+                    },
+                    None => flow_log!(
+                        error,
+                        self,
+                        "{}: {} error {}",
+                        self,
+                        elem,
+                        error
+                    ),
+                }
+                let live = msg.get_src().iter().any(|s| self.is_live(s));
+                if let Some(fb) = &self.feedback {
+                    let fb_err =
+                        Feedback::Error(self.idx, elem, error.to_string());
+                    if let Err(e) = fb.send(fb_err) {
+                        flow_log!(error, self, "{}: send {}", self, e);
+                    }
+                }
+                if live && self.fallback_retry_at > 0 {
+                    self.start_fallback();
+                    return glib::Continue(true);
+                }
+                if self.source.is_rtsp()
+                    && is_unavailable(&error.to_string(), err.get_debug())
+                {
+                    self.retry_after(RTSP_RETRY_AFTER_SEC);
+                }
+                self.failures += 1;
+                if let Some(failures) = self.fallback_failures {
+                    if self.failures >= failures {
+                        self.start_fallback();
+                    }
+                }
                 self.stop();
             },
             MessageView::Warning(wrn) => {
-                debug!("{}: warning {}", self, wrn.get_error());
+                let elem = msg_src_name(msg);
+                match wrn.get_debug() {
+                    Some(dbg) => flow_log!(
+                        warn,
+                        self,
+                        "{}: {} warning {} ({})",
+                        self,
+                        elem,
+                        wrn.get_error(),
+                        dbg
+                    ),
+                    None => {
+                        flow_log!(
+                            warn,
+                            self,
+                            "{}: {} warning {}",
+                            self,
+                            elem,
+                            wrn.get_error()
+                        )
+                    },
+                }
                 self.stop();
             },
             MessageView::Element(elem) => {
                 if let Some(obj) = elem.get_src() {
                     if obj.get_name() == "GstUDPSrcTimeout" {
-                        debug!("{}: udpsrc timeout", self);
+                        flow_log!(debug, self, "{}: udpsrc timeout", self);
                         self.stop();
                     }
                 }
+                if let Some(structure) = elem.get_structure() {
+                    if structure.get_name() == "redirect" {
+                        self.follow_redirect(structure);
+                    }
+                }
+            },
+            MessageView::Buffering(buf) => {
+                self.handle_buffering(buf.get_percent())
+            },
+            MessageView::Latency(_) => self.update_latency(),
+            MessageView::Qos(qos) => {
+                let (_processed, dropped) = qos.get_stats();
+                self.update_dropped(msg_src_name(msg), dropped.get_value());
+            },
+            MessageView::Application(_app) => {
+                self.update_packet_stats();
+                self.retry_live();
             },
-            MessageView::Application(_app) => self.update_packet_stats(),
             _ => (),
         };
         glib::Continue(true)
     }
 
+    /// Pause while a non-live source is buffering
+    ///
+    /// Live sources (RTP, RTSP, app) never pause, since buffering messages
+    /// there only reflect jitter.
+    fn handle_buffering(&mut self, percent: i32) {
+        if !self.source.is_buffered() || self.paused.load(Ordering::Relaxed) {
+            return;
+        }
+        let pipeline = match self.pipeline.upgrade() {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let buffering = self.buffering.load(Ordering::Relaxed);
+        if percent < 100 && !buffering {
+            flow_log!(debug, self, "{}: buffering {}%", self, percent);
+            self.buffering.store(true, Ordering::Relaxed);
+            set_state(self, &pipeline, State::Paused);
+        } else if percent >= 100 && buffering {
+            flow_log!(debug, self, "{}: buffering done", self);
+            self.buffering.store(false, Ordering::Relaxed);
+            set_state(self, &pipeline, State::Playing);
+        }
+    }
+
+    /// Check if an object is in the live source branch
+    fn is_live(&self, obj: &gstreamer::Object) -> bool {
+        let mut obj = Some(obj.clone());
+        while let Some(o) = obj {
+            let found = self
+                .live_branch
+                .iter()
+                .filter_map(|e| e.upgrade())
+                .any(|elem| elem.upcast_ref::<gstreamer::Object>() == &o);
+            if found {
+                return true;
+            }
+            obj = o.get_parent();
+        }
+        false
+    }
+
+    /// Switch from the initial connect timeout to the normal timeout
+    fn end_connect_timeout(&self) {
+        if self.connected.swap(true, Ordering::Relaxed)
+            || self.source.connect_timeout.is_none()
+        {
+            return;
+        }
+        let src = self
+            .pipeline
+            .upgrade()
+            .and_then(|p| p.get_by_name(&elem_name(self.idx, "src")));
+        if let Some(src) = src {
+            if src.find_property("tcp-timeout").is_some() {
+                let timeout = self.source.timeout_us();
+                if let Err(e) = self.set_property(&src, "tcp-timeout", &timeout)
+                {
+                    flow_log!(warn, self, "{}: connect timeout -- {}", self, e);
+                }
+            }
+        }
+        flow_log!(debug, self, "{}: connected, using normal timeout", self);
+    }
+
+    /// Stop the live source branch, showing fallback test source
+    ///
+    /// Branch elements are locked in null state, so the rest of the
+    /// pipeline can play without them.
+    fn start_fallback(&mut self) {
+        if self.live_branch.is_empty() {
+            return;
+        }
+        if self.fallback_retry_at == 0 {
+            flow_log!(warn, self, "{}: source failed, showing fallback", self);
+        }
+        for elem in self.live_branch.iter().filter_map(|e| e.upgrade()) {
+            elem.set_locked_state(true);
+            if let Err(e) = elem.set_state(State::Null) {
+                flow_log!(
+                    warn,
+                    self,
+                    "{}: fallback {} -- {}",
+                    self,
+                    elem.get_name(),
+                    e
+                );
+            }
+        }
+        self.fallback_retry_at = epoch_ms() + FALLBACK_RETRY_SEC * 1000;
+    }
+
+    /// Retry the live source branch while showing fallback
+    fn retry_live(&mut self) {
+        let retry_at = self.fallback_retry_at;
+        if retry_at == 0 || epoch_ms() < retry_at {
+            return;
+        }
+        flow_log!(debug, self, "{}: retrying source", self);
+        for elem in self.live_branch.iter().rev().filter_map(|e| e.upgrade()) {
+            elem.set_locked_state(false);
+            if let Err(e) = elem.sync_state_with_parent() {
+                flow_log!(
+                    warn,
+                    self,
+                    "{}: fallback retry {} -- {}",
+                    self,
+                    elem.get_name(),
+                    e
+                );
+            }
+        }
+        // Stay in fallback mode; errors return to fallback until retried
+        self.fallback_retry_at = epoch_ms() + FALLBACK_RETRY_SEC * 1000;
+    }
+
+    /// Follow a source redirect (only once)
+    ///
+    /// `rtspsrc` posts a `redirect` message when the server redirects to a
+    /// different URL.  The new location is set on the source, and the flow
+    /// is restarted.
+    fn follow_redirect(&mut self, structure: &StructureRef) {
+        let location = match structure.get::<String>("new-location") {
+            Ok(Some(location)) => location,
+            _ => return,
+        };
+        if self.redirected {
+            flow_log!(
+                warn,
+                self,
+                "{}: ignoring redirect to {}",
+                self,
+                location
+            );
+            return;
+        }
+        self.redirected = true;
+        flow_log!(info, self, "{}: redirect to {}", self, location);
+        if let Some(pipeline) = self.pipeline.upgrade() {
+            if let Some(src) = pipeline.get_by_name(&elem_name(self.idx, "src"))
+            {
+                set_state(self, &pipeline, State::Null);
+                if let Err(e) = self.set_property(&src, "location", &location) {
+                    flow_log!(warn, self, "{}: redirect -- {}", self, e);
+                    return;
+                }
+                self.source.location = location.clone();
+                if let Some(fb) = &self.feedback {
+                    let redirect = Feedback::Redirect(self.idx, location);
+                    if let Err(e) = fb.send(redirect) {
+                        flow_log!(error, self, "{}: send {}", self, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hold off restarting for a number of seconds
+    fn retry_after(&self, sec: u64) {
+        flow_log!(
+            info,
+            self,
+            "{}: server unavailable, retry after {} s",
+            self,
+            sec
+        );
+        let retry_at = epoch_ms() + sec * 1000;
+        self.retry_at.store(retry_at, Ordering::Relaxed);
+    }
+
+    /// Seek to start of stream, for looping
+    ///
+    /// A segment seek posts `SegmentDone` instead of EOS at the end, so the
+    /// next loop can seek without flushing.
+    fn seek_start(&mut self, flags: SeekFlags) {
+        if let Some(pipeline) = self.pipeline.upgrade() {
+            flow_log!(debug, self, "{}: looping", self);
+            if pipeline
+                .seek_simple(flags, ClockTime::from_seconds(0))
+                .is_err()
+            {
+                flow_log!(warn, self, "{}: loop seek failed", self);
+                self.stop();
+            }
+        }
+    }
+
     /// Stop the flow
     fn stop(&mut self) {
+        if self.paused.load(Ordering::Relaxed) {
+            flow_log!(debug, self, "{}: paused, not stopping", self);
+            return;
+        }
         if let Some(pipeline) = self.pipeline.upgrade() {
             if self.is_playing(&pipeline) {
-                debug!("{}: stopping", self);
-                pipeline.set_state(State::Null).unwrap();
+                flow_log!(debug, self, "{}: stopping", self);
+                if self.freeze_frame {
+                    self.start_freeze(&pipeline);
+                }
+                set_state(self, &pipeline, State::Null);
+            } else if self.no_restart {
+                flow_log!(info, self, "{}: stopping (not playing)", self);
+                set_state(self, &pipeline, State::Null);
             } else {
-                debug!("{}: restarting (not playing)", self);
-                pipeline.set_state(State::Playing).unwrap();
+                let n = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+                flow_log!(info, self, "{}: restart #{} (not playing)", self, n);
+                set_state(self, &pipeline, State::Playing);
             }
         }
     }
@@ -1234,17 +5681,106 @@ impl FlowBuilder {
         }
     }
 
+    /// Start displaying the last frame of a window sink
+    fn start_freeze(&mut self, pipeline: &Pipeline) {
+        if self.handle.is_none() || self.freeze.is_some() {
+            return;
+        }
+        let window =
+            self.sinks
+                .iter()
+                .enumerate()
+                .find_map(|(i, sink)| match sink {
+                    Sink::WINDOW(_) => {
+                        Some((branch_name("sink", i), sink.clone()))
+                    },
+                    _ => None,
+                });
+        if let Some((name, sink)) = window {
+            let sample = pipeline
+                .get_by_name(&elem_name(self.idx, &name))
+                .and_then(|elem| elem.get_property("last-sample").ok())
+                .and_then(|value| value.get::<Sample>().ok())
+                .flatten();
+            if let Some(sample) = sample {
+                match self.create_freeze(&sink, &sample) {
+                    Ok(freeze) => {
+                        flow_log!(debug, self, "{}: freezing last frame", self);
+                        self.freeze = Some(freeze);
+                    },
+                    Err(e) => {
+                        flow_log!(warn, self, "{}: freeze frame -- {}", self, e)
+                    },
+                }
+            }
+        }
+    }
+
+    /// Create a pipeline to display a frozen frame
+    fn create_freeze(
+        &self,
+        sink: &Sink,
+        sample: &Sample,
+    ) -> Result<FreezeFrame, Error> {
+        let freeze = Pipeline::new(Some(&format!("f{}", self.idx)));
+        let src = self.make_element("appsrc", "freezesrc")?;
+        if let Some(caps) = sample.get_caps() {
+            self.set_property(&src, "caps", &caps.to_owned())?;
+        }
+        src.set_property_from_str("format", "time");
+        let elems = [
+            src.clone(),
+            self.make_element("imagefreeze", "freezeimage")?,
+            self.make_element("videoconvert", "freezeconvert")?,
+            self.create_sink("freeze", sink)?,
+        ];
+        if freeze.add_many(&elems.iter().collect::<Vec<_>>()).is_err() {
+            return Err(Error::PipelineAdd());
+        }
+        if Element::link_many(&elems.iter().collect::<Vec<_>>()).is_err() {
+            return Err(Error::Other("freeze link failed"));
+        }
+        let freeze = FreezeFrame(freeze);
+        freeze.0.set_state(State::Playing)?;
+        src.emit("push-sample", &[sample])?;
+        src.emit("end-of-stream", &[])?;
+        Ok(freeze)
+    }
+
+    /// Stop displaying a frozen frame
+    fn end_freeze(&mut self) {
+        if self.freeze.take().is_some() {
+            flow_log!(debug, self, "{}: ending freeze frame", self);
+        }
+    }
+
     /// Provide feedback for stopped state
     fn stopped(&mut self) {
-        trace!("{}: stopped", self);
+        flow_log!(trace, self, "{}: stopped", self);
+        self.buffering.store(false, Ordering::Relaxed);
+        self.record_state_change(false);
         if let Some(fb) = &self.feedback {
             if let Err(e) = fb.send(Feedback::Stopped(self.idx)) {
-                error!("{}: send {}", self, e);
+                flow_log!(error, self, "{}: send {}", self, e);
             }
         }
-        if let Some(pipeline) = self.pipeline.upgrade() {
-            debug!("{}: restarting (stopped)", self);
-            pipeline.set_state(State::Playing).unwrap();
+        if self.no_restart {
+            flow_log!(info, self, "{}: stopped (restart disabled)", self);
+        } else if epoch_ms() < self.retry_at.load(Ordering::Relaxed) {
+            flow_log!(info, self, "{}: stopped (waiting to retry)", self);
+        } else if let Some(pipeline) = self.pipeline.upgrade() {
+            let n = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+            flow_log!(info, self, "{}: restart #{} (stopped)", self, n);
+            set_state(self, &pipeline, State::Playing);
+        }
+    }
+
+    /// Record the time of a change between playing and stopped
+    fn record_state_change(&self, playing: bool) {
+        let mut state_change = self.state_change.lock().unwrap();
+        match *state_change {
+            Some((p, _)) if p == playing => (),
+            _ => *state_change = Some((playing, SystemTime::now())),
         }
     }
 
@@ -1252,30 +5788,97 @@ impl FlowBuilder {
     fn configure_playing(&self) {
         match self.pipeline.upgrade() {
             Some(pipeline) => {
-                if self.has_text() {
-                    self.configure_text(&pipeline);
-                }
-                let crop = self.sink.crop();
-                if crop.is_cropped() {
-                    self.configure_vbox(&pipeline, crop);
+                self.configure_text(&pipeline, "txt");
+                self.configure_text(&pipeline, "stats");
+                self.configure_text(&pipeline, "banner");
+                self.configure_text(&pipeline, "ticker");
+                self.configure_text(&pipeline, "clock");
+                for (i, sink) in self.sinks.iter().enumerate() {
+                    if let Sink::WINDOW(_) = sink {
+                        if self.handle.is_none() {
+                            let name = branch_name("sink", i);
+                            self.configure_window(&pipeline, &name);
+                        }
+                    }
+                    let crop = sink.crop();
+                    if crop.is_cropped() {
+                        let name = branch_name("vbox", i);
+                        self.configure_vbox(&pipeline, &name, crop);
+                    }
                 }
             },
-            None => error!("{}: pipeline gone", self),
+            None => flow_log!(error, self, "{}: pipeline gone", self),
+        }
+    }
+
+    /// Configure window title and geometry
+    fn configure_window(&self, pipeline: &Pipeline, name: &str) {
+        if let Some(sink) = pipeline.get_by_name(&elem_name(self.idx, name)) {
+            let res = if sink.find_property("widget").is_some() {
+                self.config_gtk_window(&sink)
+            } else {
+                self.send_title_tag(&sink)
+            };
+            if let Err(e) = res {
+                flow_log!(warn, self, "{}: window -- {}", self, e);
+            }
+        }
+    }
+
+    /// Configure the window created by a GTK sink
+    fn config_gtk_window(&self, sink: &Element) -> Result<(), Error> {
+        let widget = sink
+            .get_property("widget")?
+            .get::<glib::Object>()?
+            .ok_or(Error::Other("gtksink widget missing"))?;
+        if let Some((_x, _y, width, height)) = self.window_geometry {
+            widget.set_property("width-request", &width)?;
+            widget.set_property("height-request", &height)?;
+        }
+        if let Some(title) = &self.window_title {
+            let window = widget
+                .get_property("parent")?
+                .get::<glib::Object>()?
+                .ok_or(Error::Other("gtksink window missing"))?;
+            window.set_property("title", title)?;
+        }
+        Ok(())
+    }
+
+    /// Send a title tag to a window sink
+    fn send_title_tag(&self, sink: &Element) -> Result<(), Error> {
+        if let Some(title) = &self.window_title {
+            let pad = sink
+                .get_static_pad("sink")
+                .ok_or(Error::Other("no sink pad"))?;
+            let mut tags = TagList::new();
+            if let Some(tags) = tags.get_mut() {
+                tags.add::<tags::Title>(&title.as_str(), TagMergeMode::Replace);
+            }
+            if !pad.send_event(event::Tag::new(tags)) {
+                return Err(Error::Other("title tag"));
+            }
         }
+        Ok(())
     }
 
     /// Configure text overlay element
-    fn configure_text(&self, pipeline: &Pipeline) {
-        if let Some(txt) = pipeline.get_by_name("txt") {
+    fn configure_text(&self, pipeline: &Pipeline, name: &str) {
+        if let Some(txt) = pipeline.get_by_name(&elem_name(self.idx, name)) {
             match txt.get_static_pad("src") {
                 Some(src_pad) => match src_pad.get_current_caps() {
                     Some(caps) => match self.config_txt_props(txt, caps) {
-                        Err(_) => error!("{}: txt props", self),
+                        Err(_) => flow_log!(error, self, "{}: txt props", self),
                         _ => (),
                     },
-                    None => error!("{}: no caps on txt src pad", self),
+                    None => flow_log!(
+                        error,
+                        self,
+                        "{}: no caps on txt src pad",
+                        self
+                    ),
                 },
-                None => error!("{}: no txt src pad", self),
+                None => flow_log!(error, self, "{}: no txt src pad", self),
             }
         }
     }
@@ -1284,32 +5887,51 @@ impl FlowBuilder {
     fn config_txt_props(&self, txt: Element, caps: Caps) -> Result<(), Error> {
         for s in caps.iter() {
             if let Ok(Some(height)) = s.get::<i32>("height") {
-                let sz = FONT_SZ * u32::try_from(height)? / DEFAULT_HEIGHT;
+                let sz = self.font.scaled(u32::try_from(height)?);
                 let margin = i32::try_from(sz / 2)?;
-                trace!("{}: font sz {}, height: {}", self, sz, height);
+                flow_log!(
+                    trace,
+                    self,
+                    "{}: font sz {}, height: {}",
+                    self,
+                    sz,
+                    height
+                );
                 let font = format!("Overpass, Bold {}", sz);
-                set_property(&txt, "font-desc", &font)?;
-                set_property(&txt, "ypad", &margin)?; // from top edge
-                set_property(&txt, "xpad", &margin)?; // from right edge
+                self.set_property(&txt, "font-desc", &font)?;
+                self.set_property(&txt, "ypad", &margin)?; // from top edge
+                self.set_property(&txt, "xpad", &margin)?; // from right edge
             }
         }
         Ok(())
     }
 
     /// Configure videobox element
-    fn configure_vbox(&self, pipeline: &Pipeline, crop: MatrixCrop) {
-        if let Some(vbx) = pipeline.get_by_name("vbox") {
+    fn configure_vbox(
+        &self,
+        pipeline: &Pipeline,
+        name: &str,
+        crop: MatrixCrop,
+    ) {
+        if let Some(vbx) = pipeline.get_by_name(&elem_name(self.idx, name)) {
             match vbx.get_static_pad("src") {
                 Some(src_pad) => match src_pad.get_current_caps() {
                     Some(caps) => {
                         match self.config_vbox_props(vbx, caps, crop) {
-                            Err(_) => error!("{}: vbox props", self),
+                            Err(_) => {
+                                flow_log!(error, self, "{}: vbox props", self)
+                            },
                             _ => (),
                         }
                     },
-                    None => error!("{}: no caps on vbox src pad", self),
+                    None => flow_log!(
+                        error,
+                        self,
+                        "{}: no caps on vbox src pad",
+                        self
+                    ),
                 },
-                None => error!("{}: no vbox src pad", self),
+                None => flow_log!(error, self, "{}: no vbox src pad", self),
             }
         }
     }
@@ -1324,10 +5946,10 @@ impl FlowBuilder {
         for s in caps.iter() {
             match (s.get("width"), s.get("height")) {
                 (Ok(Some(width)), Ok(Some(height))) => {
-                    set_property(&vbx, "top", &crop.top(height))?;
-                    set_property(&vbx, "bottom", &crop.bottom(height))?;
-                    set_property(&vbx, "left", &crop.left(width))?;
-                    set_property(&vbx, "right", &crop.right(width))?;
+                    self.set_property(&vbx, "top", &crop.top(height))?;
+                    self.set_property(&vbx, "bottom", &crop.bottom(height))?;
+                    self.set_property(&vbx, "left", &crop.left(width))?;
+                    self.set_property(&vbx, "right", &crop.right(width))?;
                 },
                 _ => (),
             }
@@ -1340,25 +5962,188 @@ impl FlowBuilder {
         let pushed = self.pushed;
         let lost = self.lost;
         let late = self.late;
+        let buffers = self.buffers;
+        let bytes = self.bytes;
+        let dropped = self.dropped;
+        let received = self.received;
+        self.buffers = self.egress_buffers.load(Ordering::Relaxed);
+        self.bytes = self.egress_bytes.load(Ordering::Relaxed);
+        self.received = self.ingress_bytes.load(Ordering::Relaxed);
         match self.pipeline.upgrade() {
             Some(pipeline) => {
-                if let Some(jitter) = pipeline.get_by_name("jitter") {
-                    if let Err(e) = self.update_jitter_stats(jitter) {
-                        warn!("{}: jitter stats -- {}", self, e);
-                    }
+                match pipeline.get_by_name(&elem_name(self.idx, "jitter")) {
+                    Some(jitter) => {
+                        if let Err(e) = self.update_jitter_stats(jitter) {
+                            flow_log!(
+                                warn,
+                                self,
+                                "{}: jitter stats -- {}",
+                                self,
+                                e
+                            );
+                        }
+                    },
+                    None => {
+                        self.pushed =
+                            self.ingress_buffers.load(Ordering::Relaxed);
+                        self.update_rtsp_stats(&pipeline);
+                    },
                 }
             },
-            None => error!("{}: pipeline gone", self),
+            None => flow_log!(error, self, "{}: pipeline gone", self),
         }
+        self.update_stats_text();
         if self.pushed >= pushed && self.lost >= lost && self.late >= late {
+            let pushed = self.pushed - pushed;
+            let lost = self.lost - lost;
+            let late = self.late - late;
+            let buffers = self.buffers - buffers;
+            let bytes = self.bytes - bytes;
+            let dropped = self.dropped - dropped;
+            let received = self.received - received;
+            if let Some(threshold) = self.keyframe_loss {
+                if lost > 0 && lost >= threshold {
+                    self.request_keyframe(lost);
+                }
+            }
+            if !self.paused.load(Ordering::Relaxed) {
+                self.check_alert(pushed, lost);
+            }
             if let Some(fb) = &self.feedback {
-                let pushed = self.pushed - pushed;
-                let lost = self.lost - lost;
-                let late = self.late - late;
-                if let Err(e) =
-                    fb.send(Feedback::Stats(self.idx, pushed, lost, late))
-                {
-                    error!("{}: send {}", self, e);
+                let stats = Feedback::Stats(
+                    self.idx,
+                    Stats {
+                        pushed,
+                        lost,
+                        late,
+                        buffers,
+                        bytes,
+                        dropped,
+                        latency: self.latency_ms,
+                        received,
+                        jitter: self.jitter_us,
+                        rtt: self.rtt_us,
+                    },
+                );
+                if let Err(e) = fb.send(stats) {
+                    flow_log!(error, self, "{}: send {}", self, e);
+                }
+            }
+        }
+    }
+
+    /// Get the alert condition for one stats update
+    fn alert_condition(&self, pushed: u64, lost: u64) -> Option<Alert> {
+        if self.down_alert && pushed == 0 {
+            return Some(Alert::DOWN);
+        }
+        match self.loss_alert {
+            Some(percent) if pushed + lost > 0 => {
+                let loss = lost as f32 * 100.0 / (pushed + lost) as f32;
+                if loss > percent {
+                    Some(Alert::LOSS)
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Check alert conditions, sending feedback when raised or cleared
+    fn check_alert(&mut self, pushed: u64, lost: u64) {
+        let cond = self.alert_condition(pushed, lost);
+        if cond == self.alert_cond {
+            self.alert_count = self.alert_count.saturating_add(1);
+        } else {
+            self.alert_cond = cond;
+            self.alert_count = 1;
+        }
+        if self.alert_count < self.alert_intervals || cond == self.alert {
+            return;
+        }
+        self.alert = cond;
+        let alert = cond.unwrap_or(Alert::RECOVERED);
+        flow_log!(debug, self, "{}: alert {:?}", self, alert);
+        if let Some(fb) = &self.feedback {
+            if let Err(e) = fb.send(Feedback::Alert(self.idx, alert)) {
+                flow_log!(error, self, "{}: send {}", self, e);
+            }
+        }
+    }
+
+    /// Update pipeline latency after an element's latency changed
+    fn update_latency(&mut self) {
+        if let Some(pipeline) = self.pipeline.upgrade() {
+            if pipeline.recalculate_latency().is_err() {
+                flow_log!(warn, self, "{}: recalculate latency failed", self);
+            }
+            let mut query = query::Latency::new();
+            if pipeline.query(&mut query) {
+                let (live, min, _max) = query.get_result();
+                let latency_ms = min.mseconds().unwrap_or(0);
+                if latency_ms != self.latency_ms {
+                    flow_log!(
+                        info,
+                        self,
+                        "{}: latency {} ms (live {})",
+                        self,
+                        latency_ms,
+                        live
+                    );
+                }
+                self.latency_ms = latency_ms;
+            }
+        }
+    }
+
+    /// Update dropped frames from an element QoS message
+    ///
+    /// QoS dropped counts are totals for each element, which reset when the
+    /// element restarts.
+    fn update_dropped(&mut self, elem: String, dropped: i64) {
+        let dropped = u64::try_from(dropped).unwrap_or(0);
+        let prev = self.qos_dropped.insert(elem, dropped).unwrap_or(0);
+        if dropped > prev {
+            flow_log!(trace, self, "{}: QoS {} dropped", self, dropped - prev);
+            self.dropped += dropped - prev;
+        }
+    }
+
+    /// Request a key frame after packet loss
+    fn request_keyframe(&self, lost: u64) {
+        if let Some(pipeline) = self.pipeline.upgrade() {
+            flow_log!(
+                debug,
+                self,
+                "{}: {} lost, requesting key frame",
+                self,
+                lost
+            );
+            let event = UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build();
+            if !pipeline.send_event(event) {
+                flow_log!(
+                    debug,
+                    self,
+                    "{}: key frame request not handled",
+                    self
+                );
+            }
+        }
+    }
+
+    /// Update statistics overlay text
+    fn update_stats_text(&self) {
+        if let (Some(text), Some(pipeline)) =
+            (self.stats_text(), self.pipeline.upgrade())
+        {
+            if let Some(txt) =
+                pipeline.get_by_name(&elem_name(self.idx, "stats"))
+            {
+                if let Err(e) = self.set_property(&txt, "text", &text) {
+                    flow_log!(warn, self, "{}: stats text -- {}", self, e);
                 }
             }
         }
@@ -1366,10 +6151,7 @@ impl FlowBuilder {
 
     /// Get statistics from jitter buffer element
     fn update_jitter_stats(&mut self, jitter: Element) -> Result<(), Error> {
-        let prop = jitter.get_property("stats")?;
-        let stats = prop
-            .get::<Structure>()?
-            .ok_or(Error::Other("empty stats"))?;
+        let stats = jitter_stats(&jitter)?;
         let pushed = stats
             .get::<u64>("num-pushed")?
             .ok_or(Error::Other("missing num-pushed"))?;
@@ -1382,20 +6164,83 @@ impl FlowBuilder {
         self.pushed = pushed;
         self.lost = lost;
         self.late = late;
+        self.update_network_stats(&stats);
         Ok(())
     }
+
+    /// Get network statistics from the jitter buffer inside `rtspsrc`
+    ///
+    /// Packet counts for RTSP sources still come from the ingress probe.
+    fn update_rtsp_stats(&mut self, pipeline: &Pipeline) {
+        self.jitter_us = None;
+        self.rtt_us = None;
+        if !self.source.is_rtsp() {
+            return;
+        }
+        let jitter = pipeline
+            .get_by_name(&elem_name(self.idx, "src"))
+            .and_then(|src| src.downcast::<Bin>().ok())
+            .and_then(|bin| {
+                bin.iterate_recurse().find(|e| is_jitter_buffer(&e))
+            });
+        if let Some(jitter) = jitter {
+            match jitter_stats(&jitter) {
+                Ok(stats) => self.update_network_stats(&stats),
+                Err(e) => flow_log!(
+                    debug,
+                    self,
+                    "{}: RTSP jitter stats -- {}",
+                    self,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Update jitter and round-trip time from jitter buffer stats
+    fn update_network_stats(&mut self, stats: &StructureRef) {
+        self.jitter_us = stats_us(stats, "avg-jitter");
+        self.rtt_us = stats_us(stats, "rtx-rtt");
+    }
+}
+
+impl Drop for FreezeFrame {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.set_state(State::Null) {
+            error!("freeze frame: {}", e);
+        }
+    }
 }
 
 impl Drop for Flow {
     fn drop(&mut self) {
-        self.bus.remove_watch().unwrap();
-        self.pipeline.set_state(State::Null).unwrap();
+        // Stop the pipeline before removing the bus watch, so that sources
+        // release their sockets and threads before the next flow starts
+        set_state(self, &self.pipeline, State::Null);
+        let timeout = ClockTime::from_seconds(TEARDOWN_TIMEOUT_SEC);
+        match self.pipeline.get_state(timeout) {
+            (Ok(_), State::Null, _) => (),
+            (res, current, pending) => {
+                flow_log!(
+                    warn,
+                    self,
+                    "{}: teardown {:?} current: {:?} pending: {:?}",
+                    self,
+                    res,
+                    current,
+                    pending
+                );
+            },
+        }
+        if let Err(e) = self.bus.remove_watch() {
+            flow_log!(error, self, "{}: remove watch -- {}", self, e);
+        }
     }
 }
 
 impl fmt::Display for Flow {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Flow{}", self.idx)
+        fmt_flow(f, self.idx, &self.name)
     }
 }
 
@@ -1407,22 +6252,208 @@ impl Flow {
             _ => false,
         }
     }
+
+    /// Wait for the flow to reach playing state
+    fn wait_playing(&self, timeout: u16) -> Result<(), Error> {
+        let timeout = ClockTime::from_seconds(timeout.into());
+        match self.pipeline.get_state(timeout) {
+            (Ok(_), State::Playing, _) => Ok(()),
+            (Ok(_), current, pending) => {
+                flow_log!(
+                    error,
+                    self,
+                    "{}: not playing after timeout ({:?}, pending {:?})",
+                    self,
+                    current,
+                    pending
+                );
+                Err(Error::StateChange())
+            },
+            (Err(e), _, _) => {
+                flow_log!(error, self, "{}: state change -- {}", self, e);
+                Err(Error::StateChange())
+            },
+        }
+    }
+
+    /// Check if the flow is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pause the flow, keeping the source connection
+    ///
+    /// A paused flow is not checked or restarted until resumed.
+    pub fn pause(&self) -> Result<(), Error> {
+        if self.paused.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        flow_log!(debug, self, "{}: pausing", self);
+        self.pipeline.set_state(State::Paused)?;
+        self.send_feedback(Feedback::Paused(self.idx));
+        Ok(())
+    }
+
+    /// Resume a paused flow
+    pub fn resume(&self) -> Result<(), Error> {
+        if !self.paused.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        flow_log!(debug, self, "{}: resuming", self);
+        self.pipeline.set_state(State::Playing)?;
+        self.send_feedback(Feedback::Playing(self.idx));
+        Ok(())
+    }
+
+    /// Send flow feedback
+    fn send_feedback(&self, feedback: Feedback) {
+        if let Some(fb) = &self.feedback {
+            if let Err(e) = fb.send(feedback) {
+                flow_log!(error, self, "{}: send {}", self, e);
+            }
+        }
+    }
+
+    /// Get the time of the last change between playing and stopped
+    ///
+    /// Returns `None` if the flow has not started playing yet.
+    pub fn last_state_change(&self) -> Option<SystemTime> {
+        self.state_change.lock().unwrap().map(|(_, time)| time)
+    }
+
+    /// Get the negotiated video caps (width, height, framerate)
+    ///
+    /// Caps are read from the decoder, parser or sink, whichever has them.
+    /// Returns `None` until negotiated, and for RTP passthrough flows, which
+    /// never parse the stream.
+    pub fn source_caps(&self) -> Option<(u32, u32, Fraction)> {
+        [("dec", "src"), ("parse", "src"), ("sink", "sink")]
+            .iter()
+            .find_map(|(name, pad)| {
+                let elem =
+                    self.pipeline.get_by_name(&elem_name(self.idx, name))?;
+                let caps = elem.get_static_pad(pad)?.get_current_caps()?;
+                video_caps(&caps)
+            })
+    }
+
+    /// Get the time since the flow started playing (`None` if stopped)
+    pub fn uptime(&self) -> Option<Duration> {
+        match *self.state_change.lock().unwrap() {
+            Some((true, time)) => time.elapsed().ok(),
+            _ => None,
+        }
+    }
+
+    /// Push a sample to an application source flow
+    ///
+    /// This never blocks; if the `appsrc` queue is full (`max-bytes`), the
+    /// sample is dropped and an error returned.
+    pub fn push_sample(&self, sample: &Sample) -> Result<(), Error> {
+        let src = self
+            .pipeline
+            .get_by_name(&elem_name(self.idx, "appsrc"))
+            .ok_or(Error::Other("not an app source flow"))?;
+        let level = src.get_property("current-level-bytes")?.get::<u64>();
+        let max = src.get_property("max-bytes")?.get::<u64>();
+        if let (Ok(Some(level)), Ok(Some(max))) = (level, max) {
+            if max > 0 && level >= max {
+                flow_log!(
+                    debug,
+                    self,
+                    "{}: appsrc queue full, dropping sample",
+                    self
+                );
+                return Err(Error::Other("appsrc queue full"));
+            }
+        }
+        match src.emit("push-sample", &[sample])? {
+            Some(ret) => match ret.get::<FlowReturn>() {
+                Ok(Some(FlowReturn::Ok)) => Ok(()),
+                _ => Err(Error::Other("push-sample failed")),
+            },
+            None => Err(Error::Other("push-sample failed")),
+        }
+    }
+
+    /// Update the ticker overlay text or speed
+    pub fn update_ticker(&self, update: Ticker) -> Result<(), Error> {
+        match &self.ticker {
+            Some(ticker) => ticker
+                .send(update)
+                .map_err(|_| Error::Other("ticker channel closed")),
+            None => Err(Error::Other("no ticker overlay")),
+        }
+    }
+
+    /// Get the webrtcbin element of a WebRTC flow
+    fn webrtc(&self) -> Result<Element, Error> {
+        let mut i = 0;
+        while let Some(sink) = self
+            .pipeline
+            .get_by_name(&elem_name(self.idx, &branch_name("sink", i)))
+        {
+            match sink.get_factory() {
+                Some(factory) if factory.get_name() == "webrtcbin" => {
+                    return Ok(sink)
+                },
+                _ => i += 1,
+            }
+        }
+        Err(Error::Other("not a WebRTC flow"))
+    }
+
+    /// Set the remote WebRTC session description (SDP answer)
+    pub fn set_webrtc_answer(&self, sdp: &str) -> Result<(), Error> {
+        let webrtc = self.webrtc()?;
+        let sdp = SDPMessage::parse_buffer(sdp.as_bytes())
+            .map_err(|_| Error::Other("invalid SDP"))?;
+        let answer = WebRTCSessionDescription::new(WebRTCSDPType::Answer, sdp);
+        webrtc.emit("set-remote-description", &[&answer, &None::<Promise>])?;
+        Ok(())
+    }
+
+    /// Add a remote WebRTC ICE candidate
+    pub fn add_ice_candidate(
+        &self,
+        mline: u32,
+        candidate: &str,
+    ) -> Result<(), Error> {
+        let webrtc = self.webrtc()?;
+        webrtc.emit("add-ice-candidate", &[&mline, &candidate])?;
+        Ok(())
+    }
 }
 
 impl fmt::Display for FlowChecker {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Flow{}", self.idx)
+        fmt_flow(f, self.idx, &self.name)
     }
 }
 
 impl FlowChecker {
     /// Create a new periodic flow checker
-    fn new(idx: usize, pipeline: WeakRef<Pipeline>) -> Self {
+    fn new(builder: &FlowBuilder, pipeline: WeakRef<Pipeline>) -> Self {
+        let stuck_action = if builder.no_stuck_check {
+            None
+        } else {
+            Some(builder.stuck_action)
+        };
         FlowChecker {
-            idx,
+            idx: builder.idx,
+            name: builder.name.clone(),
             pipeline,
             count: 0,
             last_pts: ClockTime::none(),
+            no_restart: builder.no_restart,
+            restarts: Arc::clone(&builder.restarts),
+            stuck_action,
+            feedback: builder.feedback.clone(),
+            paused: Arc::clone(&builder.paused),
+            buffering: Arc::clone(&builder.buffering),
+            retry_at: Arc::clone(&builder.retry_at),
+            connected: Arc::clone(&builder.connected),
+            connect_checks: builder.source.connect_checks(),
         }
     }
 
@@ -1433,12 +6464,12 @@ impl FlowChecker {
             Some(pipeline) => match self.check_flow(&pipeline) {
                 Ok(()) => glib::Continue(true),
                 Err(e) => {
-                    error!("{}: {:?}", self, e);
+                    flow_log!(error, self, "{}: {:?}", self, e);
                     glib::Continue(false)
                 },
             },
             None => {
-                debug!("{}: do_check pipeline gone", self);
+                flow_log!(debug, self, "{}: do_check pipeline gone", self);
                 glib::Continue(false)
             },
         }
@@ -1446,12 +6477,26 @@ impl FlowChecker {
 
     /// Check pipeline flow
     fn check_flow(&mut self, pipeline: &Pipeline) -> Result<(), Error> {
+        if self.paused.load(Ordering::Relaxed)
+            || self.buffering.load(Ordering::Relaxed)
+        {
+            // Paused flows are neither stuck nor stopped
+            self.count = 0;
+            return Ok(());
+        }
         if !self.is_playing(&pipeline) {
-            self.restart_pipeline(&pipeline);
+            let retry_at = self.retry_at.load(Ordering::Relaxed);
+            let connecting = !self.connected.load(Ordering::Relaxed)
+                && self.count < self.connect_checks;
+            if !self.no_restart && !connecting && epoch_ms() >= retry_at {
+                self.restart_pipeline(&pipeline);
+            }
             return Ok(());
         }
-        if self.count > PTS_CHECK_TRIES && self.is_stuck(&pipeline)? {
-            self.post_eos(&pipeline)?;
+        if let Some(action) = self.stuck_action {
+            if self.count > PTS_CHECK_TRIES && self.is_stuck(&pipeline)? {
+                self.handle_stuck(&pipeline, action)?;
+            }
         }
         self.post_stats(&pipeline)
     }
@@ -1466,28 +6511,67 @@ impl FlowChecker {
 
     /// Restart the pipeline
     fn restart_pipeline(&mut self, pipeline: &Pipeline) {
-        debug!("{}: restarting", self);
-        pipeline.set_state(State::Null).unwrap();
-        pipeline.set_state(State::Playing).unwrap();
+        let n = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+        flow_log!(info, self, "{}: restart #{} (check)", self, n);
+        set_state(self, pipeline, State::Null);
+        set_state(self, pipeline, State::Playing);
         self.count = 0;
     }
 
     /// Check if pipeline is stuck
     fn is_stuck(&mut self, pipeline: &Pipeline) -> Result<bool, Error> {
         let sink = pipeline
-            .get_by_name("sink")
+            .get_by_name(&elem_name(self.idx, "sink"))
             .ok_or(Error::Other("sink gone"))?;
+        // Some sinks (webrtcbin) are bins without last-sample
+        if sink.find_property("last-sample").is_none() {
+            return Ok(false);
+        }
         self.is_sink_stuck(&sink)
     }
 
+    /// Handle a stuck pipeline
+    fn handle_stuck(
+        &self,
+        pipeline: &Pipeline,
+        action: StuckAction,
+    ) -> Result<(), Error> {
+        match action {
+            StuckAction::RESTART => self.post_eos(pipeline)?,
+            StuckAction::WARN => {
+                flow_log!(warn, self, "{}: stuck, waiting", self)
+            },
+            StuckAction::FLUSH => self.flush(pipeline)?,
+        }
+        if let Some(fb) = &self.feedback {
+            let stuck = Feedback::Stuck(self.idx, action);
+            if let Err(e) = fb.send(stuck) {
+                flow_log!(error, self, "{}: send {}", self, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the pipeline without changing state
+    fn flush(&self, pipeline: &Pipeline) -> Result<(), Error> {
+        flow_log!(debug, self, "{}: flushing", self);
+        if pipeline.send_event(event::FlushStart::new())
+            && pipeline.send_event(event::FlushStop::new(false))
+        {
+            Ok(())
+        } else {
+            Err(Error::Other("flush failed"))
+        }
+    }
+
     /// Post an EOS message on the pipeline bus
     fn post_eos(&self, pipeline: &Pipeline) -> Result<(), Error> {
         let sink = pipeline
-            .get_by_name("sink")
+            .get_by_name(&elem_name(self.idx, "sink"))
             .ok_or(Error::Other("sink gone"))?;
         let msg = message::Eos::builder().src(&sink).build();
         let bus = pipeline.get_bus().unwrap();
-        debug!("{}: posting EOS", self);
+        flow_log!(debug, self, "{}: posting EOS", self);
         match bus.post(&msg) {
             Ok(_) => Ok(()),
             Err(_) => Err(Error::Other("post_eos failed")),
@@ -1501,10 +6585,16 @@ impl FlowChecker {
                 Ok(Some(sample)) => match sample.get_buffer() {
                     Some(buffer) => {
                         let pts = buffer.get_pts();
-                        trace!("{}: PTS {}", self, pts);
+                        flow_log!(trace, self, "{}: PTS {}", self, pts);
                         let stuck = pts == self.last_pts;
                         if stuck {
-                            debug!("{}: PTS stuck @ {}", self, pts);
+                            flow_log!(
+                                debug,
+                                self,
+                                "{}: PTS stuck @ {}",
+                                self,
+                                pts
+                            );
                         } else {
                             self.last_pts = pts;
                         }
@@ -1512,7 +6602,13 @@ impl FlowChecker {
                     },
                     None => return Err(Error::Other("sample buffer missing")),
                 },
-                _ => debug!("{}: last-sample missing {}", self, self.count),
+                _ => flow_log!(
+                    debug,
+                    self,
+                    "{}: last-sample missing {}",
+                    self,
+                    self.count
+                ),
             },
             Err(_) => return Err(Error::Other("get last-sample failed")),
         };
@@ -1528,9 +6624,621 @@ impl FlowChecker {
         match bus.post(&msg) {
             Ok(_) => Ok(()),
             Err(_) => {
-                trace!("{}: post_stats failed", self);
+                flow_log!(trace, self, "{}: post_stats failed", self);
                 Ok(())
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Element factory which records elements instead of making them
+    ///
+    /// Each element is an empty bin, so no plugins are needed.
+    #[derive(Clone, Default)]
+    struct RecordElements {
+        /// Available hardware element factories
+        hardware: Vec<&'static str>,
+        /// Factory names of made elements, in order
+        made: Arc<Mutex<Vec<&'static str>>>,
+        /// Properties set (`element.property`)
+        props: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Elements for RecordElements {
+        fn has_element(&self, factory_name: &'static str) -> bool {
+            self.hardware.contains(&factory_name)
+        }
+
+        fn make_element(
+            &self,
+            factory_name: &'static str,
+            name: &str,
+        ) -> Result<Element, Error> {
+            self.made.lock().unwrap().push(factory_name);
+            Ok(gstreamer::Bin::new(Some(name)).upcast())
+        }
+
+        fn set_property(
+            &self,
+            elem: &Element,
+            name: &'static str,
+            _value: &dyn ToValue,
+        ) -> Result<(), Error> {
+            let prop = format!("{}.{}", elem.get_name(), name);
+            self.props.lock().unwrap().push(prop);
+            Ok(())
+        }
+    }
+
+    /// Add elements to a pipeline, recording the factories chosen
+    fn record_elements(
+        builder: FlowBuilder,
+        elements: &RecordElements,
+    ) -> Result<Vec<&'static str>, Error> {
+        gstreamer::init().unwrap();
+        let mut builder = builder.with_elements(Box::new(elements.clone()));
+        let pipeline = Pipeline::new(None);
+        builder.pipeline = pipeline.downgrade();
+        builder.add_elements()?;
+        Ok(elements.made.lock().unwrap().clone())
+    }
+
+    /// Create an RTP source
+    fn rtp_source(encoding: Encoding) -> Source {
+        Source::default()
+            .with_location("udp://239.0.0.1:5000")
+            .with_encoding(encoding)
+    }
+
+    /// Create an RTP sink
+    fn rtp_sink(encoding: Encoding) -> Sink {
+        Sink::RTP("239.0.0.2".to_string(), 5000, encoding, false)
+    }
+
+    /// Create a flow builder with one source and sink
+    fn flow(source: Source, sink: Sink) -> FlowBuilder {
+        FlowBuilder::new(0).with_source(source).with_sink(sink)
+    }
+
+    #[test]
+    fn decide_passthru() {
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H264));
+        assert!(b.is_rtp_passthru());
+        assert!(!b.needs_transcode());
+        assert!(!b.needs_decode());
+        assert!(!b.needs_encode(&b.sinks[0]));
+        assert!(!b.needs_rtp_depay());
+        assert!(!b.needs_rtp_pay(&b.sinks[0]));
+        let sink =
+            Sink::RTP("239.0.0.2".to_string(), 5000, Encoding::H264, true);
+        let b = flow(rtp_source(Encoding::H264), sink);
+        assert!(!b.is_rtp_passthru());
+        assert!(!b.needs_transcode());
+        assert!(b.needs_rtp_depay());
+        assert!(b.needs_rtp_pay(&b.sinks[0]));
+    }
+
+    #[test]
+    fn decide_transcode() {
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H265));
+        assert!(!b.is_rtp_passthru());
+        assert!(b.needs_transcode());
+        assert!(b.needs_decode());
+        assert!(b.needs_encode(&b.sinks[0]));
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H264))
+            .with_overlay_text(Some("text"));
+        assert!(!b.is_rtp_passthru());
+        assert!(b.needs_decode());
+        assert!(b.needs_encode(&b.sinks[0]));
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H264))
+            .with_resolution(Some((640, 360)));
+        assert!(!b.is_rtp_passthru());
+        assert!(b.needs_decode());
+        assert!(b.needs_encode(&b.sinks[0]));
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H264))
+            .with_sink(Sink::FAKE);
+        assert!(!b.is_rtp_passthru());
+        assert!(b.needs_transcode());
+    }
+
+    #[test]
+    fn decide_encode_only() {
+        let b = flow(Source::default(), rtp_sink(Encoding::H264));
+        assert!(b.needs_transcode());
+        assert!(!b.needs_decode());
+        assert!(b.needs_encode(&b.sinks[0]));
+        assert!(!b.needs_rtp_depay());
+        assert!(b.needs_rtp_pay(&b.sinks[0]));
+    }
+
+    #[test]
+    fn decide_decode_only() {
+        let b = flow(rtp_source(Encoding::H264), Sink::FAKE);
+        assert!(b.needs_transcode());
+        assert!(b.needs_decode());
+        assert!(!b.needs_encode(&b.sinks[0]));
+        assert!(b.needs_rtp_depay());
+        assert!(!b.needs_rtp_pay(&b.sinks[0]));
+    }
+
+    #[test]
+    fn elements_passthru() {
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H265), rtp_sink(Encoding::H265));
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "udpsink",
+                "queue",
+                "identity",
+                "rtpjitterbuffer",
+                "capsfilter",
+                "udpsrc",
+            ]
+        );
+        let props = elements.props.lock().unwrap();
+        assert!(props.contains(&"m0_sink.host".to_string()));
+        assert!(props.contains(&"m0_sink.port".to_string()));
+        assert!(props.contains(&"m0_src.uri".to_string()));
+    }
+
+    #[test]
+    fn elements_destinations() {
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H264), rtp_sink(Encoding::H264))
+            .with_destinations(vec![Destination::new("239.0.0.3", 5002)]);
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(made.iter().filter(|f| **f == "udpsink").count(), 2);
+        assert!(made.contains(&"tee"));
+        let props = elements.props.lock().unwrap();
+        assert!(props.contains(&"m0_sink.host".to_string()));
+        assert!(props.contains(&"m0_sink_dest0.host".to_string()));
+        assert!(props.contains(&"m0_sink_dest0.port".to_string()));
+    }
+
+    #[test]
+    fn elements_transcode() {
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H265), rtp_sink(Encoding::H264));
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "udpsink",
+                "rtph264pay",
+                "x264enc",
+                "queue",
+                "libde265dec",
+                "queue",
+                "rtph265depay",
+                "identity",
+                "rtpjitterbuffer",
+                "capsfilter",
+                "udpsrc",
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_encode_only() {
+        let elements = RecordElements::default();
+        let b = flow(Source::default(), rtp_sink(Encoding::H264))
+            .with_encoder_threads(Some(2));
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "udpsink",
+                "rtph264pay",
+                "x264enc",
+                "queue",
+                "identity",
+                "videotestsrc"
+            ]
+        );
+        let props = elements.props.lock().unwrap();
+        assert!(props.contains(&"m0_enc.threads".to_string()));
+    }
+
+    #[test]
+    fn elements_decode_only() {
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H265), Sink::FAKE);
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "fakesink",
+                "libde265dec",
+                "queue",
+                "rtph265depay",
+                "identity",
+                "rtpjitterbuffer",
+                "capsfilter",
+                "udpsrc",
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_vaapi() {
+        let elements = RecordElements {
+            hardware: vec!["vaapih264enc", "vaapih265dec"],
+            ..Default::default()
+        };
+        let b = flow(rtp_source(Encoding::H265), rtp_sink(Encoding::H264))
+            .with_acceleration(Acceleration::VAAPI);
+        let made = record_elements(b, &elements).unwrap();
+        assert!(made.contains(&"vaapih264enc"));
+        assert!(made.contains(&"vaapih265dec"));
+        assert!(!made.contains(&"x264enc"));
+    }
+
+    #[test]
+    fn elements_vaapi_missing() {
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H265), rtp_sink(Encoding::H264))
+            .with_acceleration(Acceleration::VAAPI);
+        match record_elements(b, &elements) {
+            Err(Error::MissingElement(name)) => {
+                assert_eq!(name, "vaapih264enc")
+            },
+            _ => panic!("missing hardware element not reported"),
+        }
+        let elements = RecordElements::default();
+        let b = flow(rtp_source(Encoding::H265), rtp_sink(Encoding::H264))
+            .with_acceleration(Acceleration::VAAPI)
+            .with_software_fallback(true);
+        let made = record_elements(b, &elements).unwrap();
+        assert!(made.contains(&"x264enc"));
+        assert!(made.contains(&"libde265dec"));
+    }
+
+    #[test]
+    fn media_of_caps() {
+        gstreamer::init().unwrap();
+        let caps = |s: &str| Caps::from_str(s).unwrap();
+        let rtp = caps("application/x-rtp, media=audio, encoding-name=OPUS");
+        assert_eq!(caps_media(&rtp), Some("audio"));
+        let rtp = caps("application/x-rtp, media=video, encoding-name=H264");
+        assert_eq!(caps_media(&rtp), Some("video"));
+        assert_eq!(caps_media(&caps("application/x-rtp")), None);
+        assert_eq!(caps_media(&caps("video/x-h264")), Some("video"));
+        assert_eq!(caps_media(&caps("audio/mpeg")), Some("audio"));
+        assert_eq!(caps_media(&Caps::new_empty()), None);
+    }
+
+    #[test]
+    fn wrong_media() {
+        gstreamer::init().unwrap();
+        let audio = Caps::from_str("audio/x-raw").unwrap();
+        let video = Caps::from_str("video/x-raw").unwrap();
+        let ts = Caps::from_str("video/mpegts").unwrap();
+        let image = Caps::from_str("image/png").unwrap();
+        assert!(is_wrong_media(&audio, false));
+        assert!(!is_wrong_media(&audio, true));
+        assert!(!is_wrong_media(&video, false));
+        assert!(is_wrong_media(&video, true));
+        assert!(!is_wrong_media(&ts, false));
+        assert!(!is_wrong_media(&image, false));
+        assert!(!is_wrong_media(&image, true));
+    }
+
+    #[test]
+    fn media_probe_multi_stream() {
+        gstreamer::init().unwrap();
+        let src = Pad::new(Some("src"), gstreamer::PadDirection::Src);
+        let sink = Pad::new(Some("sink"), gstreamer::PadDirection::Sink);
+        FlowBuilder::new(0).add_media_probe(&sink);
+        src.set_active(true).unwrap();
+        sink.set_active(true).unwrap();
+        src.link(&sink).unwrap();
+        let segment = gstreamer::FormattedSegment::<ClockTime>::new();
+        src.push_event(event::StreamStart::new("multi"));
+        src.push_event(event::Segment::new(&segment));
+        // Audio stream is dropped by the probe
+        let audio = Caps::from_str("audio/x-raw").unwrap();
+        src.push_event(event::Caps::new(&audio));
+        assert_eq!(
+            src.push(gstreamer::Buffer::new()),
+            Ok(gstreamer::FlowSuccess::Ok)
+        );
+        // Video stream passes, to a sink pad with no chain function
+        let video = Caps::from_str("video/x-raw").unwrap();
+        src.push_event(event::Caps::new(&video));
+        assert!(src.push(gstreamer::Buffer::new()).is_err());
+        // Dropping resumes when audio caps return
+        src.push_event(event::Caps::new(&audio));
+        assert_eq!(
+            src.push(gstreamer::Buffer::new()),
+            Ok(gstreamer::FlowSuccess::Ok)
+        );
+    }
+
+    #[test]
+    fn source_from_uri() {
+        let enc = |uri| Source::from_uri(uri).unwrap().encoding;
+        assert_eq!(enc(""), Encoding::RAW);
+        assert_eq!(enc("test:"), Encoding::RAW);
+        assert_eq!(enc("test"), Encoding::RAW);
+        assert_eq!(enc("udp://239.0.0.1:5000"), Encoding::H264);
+        assert_eq!(enc("rtsp://10.0.0.5/stream"), Encoding::H264);
+        assert_eq!(enc("rtsps://10.0.0.5/stream"), Encoding::H264);
+        assert_eq!(enc("http://10.0.0.5/snap.PNG?x=1"), Encoding::PNG);
+        assert_eq!(enc("http://10.0.0.5/mjpg"), Encoding::MJPEG);
+        assert_eq!(enc("file:///tmp/clip.ts"), Encoding::MPEG2);
+        assert_eq!(enc("file:///tmp/clip.hevc"), Encoding::H265);
+        assert_eq!(enc("file:///tmp/clip.ivf"), Encoding::VP8);
+        assert_eq!(enc("file:///tmp/still.png"), Encoding::PNG);
+        assert_eq!(enc("file:///tmp/v1.0/clip"), Encoding::H264);
+        assert_eq!(enc("v4l2:///dev/video0"), Encoding::RAW);
+        assert!(Source::from_uri("ftp://10.0.0.5/clip").is_err());
+    }
+
+    #[test]
+    fn source_scheme() {
+        let src = Source::from_uri("rtsps://10.0.0.5/").unwrap();
+        assert!(src.is_rtsp() && src.is_rtsps() && !src.is_rtp());
+        let src = Source::from_uri("udp://239.0.0.1:5000").unwrap();
+        assert!(src.is_rtp() && !src.is_rtsp() && !src.is_http());
+        let src = Source::from_uri("https://10.0.0.5/").unwrap();
+        assert!(src.is_http() && src.is_buffered());
+        let src = Source::from_uri("file:///tmp/clip.h264").unwrap();
+        assert!(!src.is_rtp_or_rtsp() && !src.is_http());
+    }
+
+    #[test]
+    fn source_looping() {
+        let src = Source::from_uri("file:///tmp/clip.h264").unwrap();
+        assert!(!src.is_looping());
+        assert!(src.with_loop(true).is_looping());
+        let src = Source::from_uri("").unwrap().with_loop(true);
+        assert!(src.is_looping());
+        let src = Source::from_uri("udp://239.0.0.1:5000").unwrap();
+        assert!(!src.with_loop(true).is_looping());
+        let src = Source::from_uri("rtsp://10.0.0.5/").unwrap();
+        assert!(!src.with_loop(true).is_looping());
+    }
+
+    /// Create a baseline JPEG of one 8x8 gray block
+    fn gray_jpeg() -> Vec<u8> {
+        let mut jpg = vec![0xFF, 0xD8];
+        // quantization table 0, all ones
+        jpg.extend(&[0xFF, 0xDB, 0x00, 0x43, 0x00]);
+        jpg.extend(&[1; 64]);
+        // baseline frame: 8-bit, 8x8, one component
+        jpg.extend(&[0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x08, 0x00, 0x08]);
+        jpg.extend(&[0x01, 0x01, 0x11, 0x00]);
+        // DC and AC tables 0, each with a 1-bit code for symbol 0
+        for class in [0x00, 0x10] {
+            jpg.extend(&[0xFF, 0xC4, 0x00, 0x14, class, 0x01]);
+            jpg.extend(&[0; 16]);
+        }
+        // scan: DC difference 0 and end of block, padded with ones
+        jpg.extend(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F]);
+        jpg.extend(&[0x00, 0x3F, 0xFF, 0xD9]);
+        jpg
+    }
+
+    /// Run a flow for a while, and check if it is still playing
+    fn run_flow(builder: FlowBuilder) -> bool {
+        let flow = builder.build().unwrap();
+        let main_loop = glib::MainLoop::new(None, false);
+        let ml = main_loop.clone();
+        glib::source::timeout_add(1500, move || {
+            ml.quit();
+            glib::Continue(false)
+        });
+        main_loop.run();
+        flow.is_playing()
+    }
+
+    #[test]
+    fn loop_short_file() {
+        gstreamer::init().unwrap();
+        let factories = ["filesrc", "jpegdec", "queue", "identity", "fakesink"];
+        if factories.iter().any(|f| ElementFactory::find(f).is_none()) {
+            return;
+        }
+        let path = std::env::temp_dir()
+            .join(format!("streambed-loop-{}.jpg", std::process::id()));
+        std::fs::write(&path, gray_jpeg()).unwrap();
+        let uri = format!("file://{}", path.display());
+        let flow = |looping| {
+            let source = Source::from_uri(&uri).unwrap().with_loop(looping);
+            FlowBuilder::new(0)
+                .with_source(source)
+                .with_restart(false)
+                .with_stuck_check(false)
+        };
+        let looped = run_flow(flow(true));
+        let stopped = !run_flow(flow(false));
+        std::fs::remove_file(&path).unwrap();
+        assert!(looped);
+        assert!(stopped);
+    }
+
+    #[test]
+    fn elements_file() {
+        let elements = RecordElements::default();
+        let source = Source::from_uri("file:///tmp/clip.h264").unwrap();
+        let b = flow(source, rtp_sink(Encoding::H264));
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            ["udpsink", "rtph264pay", "identity", "h264parse", "filesrc"]
+        );
+        let props = elements.props.lock().unwrap();
+        assert!(props.contains(&"m0_filesrc.location".to_string()));
+    }
+
+    #[test]
+    fn elements_file_ts() {
+        let elements = RecordElements::default();
+        let source = Source::from_uri("file:///tmp/clip.ts").unwrap();
+        let b = flow(source, Sink::FAKE);
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "fakesink", "mpeg2dec", "tsdemux", "queue", "identity",
+                "filesrc",
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_v4l2() {
+        let elements = RecordElements::default();
+        let source = Source::from_uri("v4l2:///dev/video1").unwrap();
+        let b = flow(source, rtp_sink(Encoding::H264));
+        let made = record_elements(b, &elements).unwrap();
+        assert_eq!(
+            made,
+            [
+                "udpsink",
+                "rtph264pay",
+                "x264enc",
+                "queue",
+                "identity",
+                "videoconvert",
+                "v4l2src",
+            ]
+        );
+        let props = elements.props.lock().unwrap();
+        assert!(props.contains(&"m0_v4l2src.device".to_string()));
+    }
+
+    #[test]
+    fn elements_unsupported_scheme() {
+        let elements = RecordElements::default();
+        let source = Source::default().with_location("ftp://10.0.0.5/clip");
+        let b = flow(source, Sink::FAKE);
+        assert!(record_elements(b, &elements).is_err());
+    }
+
+    #[test]
+    fn elements_test_source() {
+        for location in &["", "test", "test:"] {
+            let elements = RecordElements::default();
+            let source = Source::default().with_location(location);
+            let made = record_elements(flow(source, Sink::FAKE), &elements);
+            assert!(made.unwrap().contains(&"videotestsrc"));
+        }
+    }
+
+    #[test]
+    fn matrix_crop_new() {
+        assert!(MatrixCrop::new(0, 0, 1, 1).is_ok());
+        assert!(MatrixCrop::new(7, 7, 8, 8).is_ok());
+        assert!(MatrixCrop::new(1, 0, 1, 1).is_err());
+        assert!(MatrixCrop::new(0, 1, 1, 1).is_err());
+        assert!(MatrixCrop::new(0, 0, 0, 1).is_err());
+        assert!(MatrixCrop::new(0, 0, 9, 1).is_err());
+        assert!(MatrixCrop::new(0, 0, 1, 9).is_err());
+    }
+
+    #[test]
+    fn matrix_crop_gaps() {
+        let crop = MatrixCrop::new(0, 0, 2, 2).unwrap();
+        assert!(crop.with_gaps(0, 0).is_ok());
+        assert!(crop.with_gaps(50_00, 50_00).is_ok());
+        assert!(crop.with_gaps(50_01, 0).is_err());
+        assert!(crop.with_gaps(0, 50_01).is_err());
+    }
+
+    #[test]
+    fn matrix_crop_parse() {
+        assert!(MatrixCrop::try_from("AAAA,0,0").is_ok());
+        assert!(MatrixCrop::try_from("HHHH,0,0").is_ok());
+        assert!(MatrixCrop::try_from("BAAA,0,0").is_err());
+        assert!(MatrixCrop::try_from("ABAA,0,0").is_err());
+        assert!(MatrixCrop::try_from("AAII,0,0").is_err());
+        assert!(MatrixCrop::try_from("AABB,5001,0").is_err());
+        assert!(MatrixCrop::try_from("AABB,0").is_err());
+        assert!(MatrixCrop::try_from("AABB,x,0").is_err());
+        assert!(MatrixCrop::try_from("AA,0,0").is_err());
+    }
+
+    #[test]
+    fn matrix_crop_edges() {
+        let crop = MatrixCrop::new(1, 0, 2, 2).unwrap();
+        assert!(crop.is_cropped());
+        assert_eq!(crop.left(1920), 960);
+        assert_eq!(crop.right(1920), 0);
+        assert_eq!(crop.top(1080), 0);
+        assert_eq!(crop.bottom(1080), 540);
+        let crop = crop.with_gaps(10_00, 0).unwrap();
+        assert_eq!(crop.left(1920), 960 + 48);
+        assert_eq!(crop.right(1920), 48);
+        let crop = MatrixCrop::try_from("BABB,0,0").unwrap();
+        assert_eq!(crop.left(1920), 960);
+        assert_eq!(crop.bottom(1080), 540);
+        assert!(!MatrixCrop::default().is_cropped());
+    }
+
+    /// Create RTP caps for a `udp://` source
+    fn udp_rtp_caps(source: Source) -> Caps {
+        gstreamer::init().unwrap();
+        let source = source.with_location("udp://239.0.0.1:5000");
+        FlowBuilder::new(0)
+            .with_source(source)
+            .create_rtp_caps()
+            .unwrap()
+    }
+
+    #[test]
+    fn rtp_caps_per_encoding() {
+        let names = [
+            (Encoding::RAW, "RAW"),
+            (Encoding::MJPEG, "JPEG"),
+            (Encoding::MPEG2, "MP2T"),
+            (Encoding::MPEG4, "MP4V-ES"),
+            (Encoding::H264, "H264"),
+            (Encoding::H265, "H265"),
+            (Encoding::VP8, "VP8"),
+            (Encoding::VP9, "VP9"),
+            (Encoding::AV1, "AV1"),
+        ];
+        for (encoding, name) in names.iter() {
+            let caps = udp_rtp_caps(Source::default().with_encoding(*encoding));
+            let s = caps.get_structure(0).unwrap();
+            assert_eq!(s.get_name(), "application/x-rtp");
+            assert_eq!(s.get::<&str>("media").unwrap(), Some("video"));
+            assert_eq!(
+                s.get_some::<i32>("clock-rate").unwrap(),
+                RTP_VIDEO_CLOCK_RATE
+            );
+            assert_eq!(s.get::<&str>("encoding-name").unwrap(), Some(*name));
+        }
+    }
+
+    #[test]
+    fn rtp_caps_audio() {
+        let source =
+            Source::default().with_audio_encoding(Some(AudioEncoding::OPUS));
+        let caps = udp_rtp_caps(source);
+        let s = caps.get_structure(0).unwrap();
+        assert_eq!(s.get::<&str>("media").unwrap(), Some("audio"));
+        assert_eq!(s.get_some::<i32>("clock-rate").unwrap(), 48_000);
+        assert_eq!(s.get::<&str>("encoding-name").unwrap(), Some("OPUS"));
+    }
+
+    #[test]
+    fn rtp_caps_sprops() {
+        let source = Source::default()
+            .with_encoding(Encoding::H264)
+            .with_sprops(Some("Z0IAH+KQCgC3YC3AQEBpB4kRUA==,aM48gA=="));
+        let caps = udp_rtp_caps(source);
+        let s = caps.get_structure(0).unwrap();
+        assert_eq!(
+            s.get::<&str>("sprop-parameter-sets").unwrap(),
+            Some("Z0IAH+KQCgC3YC3AQEBpB4kRUA==,aM48gA==")
+        );
+    }
+}
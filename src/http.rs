@@ -0,0 +1,137 @@
+// http.rs
+//
+// Copyright (C) 2019-2020  Minnesota Department of Transportation
+//
+use crate::error::Error;
+use glib::{ObjectExt, WeakRef};
+use gstreamer::Element;
+use log::{debug, info, warn};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::thread;
+use std::time::Duration;
+
+/// Multipart boundary for MJPEG streams
+pub const MJPEG_BOUNDARY: &str = "streambed";
+
+/// Interval to check whether the sink is still alive
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timeout for reading HTTP request headers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spawn a thread to serve an MJPEG stream over HTTP
+///
+/// Accepted clients are handed off to a `multifdsink` element, which pushes
+/// frames to all of them.  The thread exits when the sink is dropped.
+pub fn spawn_server(idx: usize, port: u16, path: String, sink: &Element) {
+    let sink = sink.downgrade();
+    thread::spawn(move || {
+        if let Err(e) = serve(idx, port, &path, sink) {
+            warn!("Flow{}: HTTP server {}", idx, e);
+        }
+    });
+}
+
+/// Close a file descriptor removed from a `multifdsink`
+pub fn close_fd(fd: RawFd) {
+    // Dropping the stream closes the socket
+    drop(unsafe { TcpStream::from_raw_fd(fd) });
+}
+
+/// Serve clients until the sink is dropped
+fn serve(
+    idx: usize,
+    port: u16,
+    path: &str,
+    sink: WeakRef<Element>,
+) -> Result<(), Error> {
+    let listener = match bind(port, &sink)? {
+        Some(listener) => listener,
+        None => return Ok(()),
+    };
+    info!("Flow{}: serving MJPEG on port {} {}", idx, port, path);
+    while sink.upgrade().is_some() {
+        match listener.accept() {
+            Ok((stream, remote)) => {
+                debug!("Flow{}: HTTP client {:?}", idx, remote);
+                // Read each request on its own thread, so a slow client
+                // cannot hold up others
+                let path = path.to_string();
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    if let Err(e) = add_client(stream, &path, &sink) {
+                        debug!("Flow{}: HTTP client {:?} {}", idx, remote, e);
+                    }
+                });
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+    debug!("Flow{}: HTTP server stopped", idx);
+    Ok(())
+}
+
+/// Bind a listener, retrying while a previous flow still holds the port
+fn bind(
+    port: u16,
+    sink: &WeakRef<Element>,
+) -> Result<Option<TcpListener>, Error> {
+    let address: IpAddr = "::".parse()?;
+    while sink.upgrade().is_some() {
+        match TcpListener::bind((address, port)) {
+            Ok(listener) => {
+                listener.set_nonblocking(true)?;
+                return Ok(Some(listener));
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                thread::sleep(POLL_INTERVAL);
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+/// Respond to an HTTP request and add the client to the sink
+fn add_client(
+    mut stream: TcpStream,
+    path: &str,
+    sink: &WeakRef<Element>,
+) -> Result<(), Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip remaining request headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(p)) if p == path => {
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\n\
+                 Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+                 Cache-Control: no-cache\r\n\
+                 Connection: close\r\n\r\n",
+                MJPEG_BOUNDARY
+            )?;
+            let sink = sink.upgrade().ok_or(Error::Other("sink gone"))?;
+            let fd = stream.into_raw_fd();
+            sink.emit("add", &[&fd])?;
+            Ok(())
+        },
+        _ => {
+            write!(stream, "HTTP/1.0 404 Not Found\r\n\r\n")?;
+            Err(Error::Other("invalid HTTP request"))
+        },
+    }
+}
@@ -4,9 +4,14 @@
 //
 mod error;
 mod flow;
+mod http;
 
 pub use crate::error::Error;
 pub use crate::flow::{
-    Acceleration, AspectRatio, Encoding, Feedback, Flow, FlowBuilder,
-    MatrixCrop, Sink, Source, Transport,
+    Acceleration, Alert, AspectRatio, AudioEncoding, Destination, Encoding,
+    Feedback, Flow, FlowBuilder, GlShare, MatrixCrop, Rendition, Signal, Sink,
+    Source, Stats, StuckAction, Ticker, Transport, WindowSink,
+};
+pub use crate::flow::{
+    DEFAULT_MAX_DROPOUT_MS, DEFAULT_REFRESH_MS, RTP_VIDEO_CLOCK_RATE,
 };
@@ -4,20 +4,25 @@
 //
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use env_logger::Env;
+use log::kv::Key;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::fs::{create_dir_all, File};
-use std::io::{BufRead, BufReader, ErrorKind};
+use std::fs::{create_dir_all, metadata, rename, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
 use std::net::{IpAddr, TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use streambed::{
-    Acceleration, Encoding, Error, Feedback, Flow, FlowBuilder, Sink, Source,
-    Transport,
+    Acceleration, Alert, AspectRatio, AudioEncoding, Destination, Encoding,
+    Error, Feedback, Flow, FlowBuilder, Rendition, Sink, Source, Stats,
+    StuckAction, Ticker, Transport,
+};
+use streambed::{
+    DEFAULT_MAX_DROPOUT_MS, DEFAULT_REFRESH_MS, RTP_VIDEO_CLOCK_RATE,
 };
 
 /// Crate version
@@ -26,6 +31,12 @@ const VERSION: &'static str = std::env!("CARGO_PKG_VERSION");
 /// Configuration file name
 const CONFIG_FILE: &'static str = "streambed.muon";
 
+/// Config skeleton template (before flows)
+const INIT_CONFIG: &str = include_str!("init_config.muon");
+
+/// Config skeleton template (flow values)
+const INIT_FLOW: &str = include_str!("init_flow.muon");
+
 /// Possible RTSP transports
 const TRANSPORTS: &[&'static str] = &["", "ANY", "UDP", "MCAST", "TCP"];
 
@@ -33,6 +44,31 @@ const TRANSPORTS: &[&'static str] = &["", "ANY", "UDP", "MCAST", "TCP"];
 const ENCODINGS: &[&'static str] =
     &["", "MJPEG", "MPEG2", "MPEG4", "H264", "H265", "VP8", "VP9"];
 
+/// Interval to check for configuration file changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay after a failed authentication attempt
+const AUTH_DELAY: Duration = Duration::from_secs(2);
+
+/// Possible H.264 stream formats
+const H264_STREAM_FORMATS: &[&str] = &["", "byte-stream", "avc", "avc3"];
+
+/// Possible H.264 alignments
+const H264_ALIGNMENTS: &[&str] = &["", "au", "nal"];
+
+/// Possible audio encodings
+const AUDIO_ENCODINGS: &[&'static str] = &["", "PCMU", "PCMA", "OPUS"];
+
+/// Messages queued for a subscriber before it is dropped as too slow
+const SUBSCRIBER_QUEUE: usize = 256;
+
+/// Optional parameters of event messages
+const EVENT_DETAILS: [&str; 15] = [
+    "pushed", "lost", "late", "buffers", "bytes", "dropped", "latency",
+    "received", "jitter", "rtt", "element", "message", "action", "location",
+    "alert",
+];
+
 /// ASCII group separator
 const SEP_GROUP: u8 = b'\x1D';
 
@@ -46,27 +82,59 @@ const SEP_UNIT: u8 = b'\x1F';
 trait Parameters<'a> {
     /// Get the value of a command parameter
     fn value(&'a self, p: &'a str) -> Option<&'a str>;
+
+    /// Check if a command flag is present
+    fn is_present(&'a self, p: &'a str) -> bool;
 }
 
 impl<'a> Parameters<'a> for ArgMatches<'a> {
     fn value(&'a self, p: &'a str) -> Option<&'a str> {
         self.value_of(p)
     }
+
+    fn is_present(&'a self, p: &'a str) -> bool {
+        ArgMatches::is_present(self, p)
+    }
 }
 
 /// Streambed configuration
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 struct Config {
     /// Control port (TCP)
     control_port: Option<u16>,
+    /// Control authentication token
+    control_token: Option<String>,
+    /// Control connection read timeout in seconds (0 for none)
+    control_timeout: Option<u64>,
+    /// Delay after a control connection error (ms)
+    control_retry_delay: Option<u64>,
     /// Video acceleration method
     acceleration: Option<String>,
+    /// Software fallback when acceleration is unavailable
+    software_fallback: Option<bool>,
+    /// Reload when configuration file changes
+    watch_file: Option<bool>,
+    /// Timeout waiting for flows to play when built (seconds, 0 for none)
+    play_timeout: Option<u16>,
+    /// Default multicast TTL for RTP sinks
+    ttl: Option<u8>,
+    /// Default DSCP for RTP sinks
+    dscp: Option<u8>,
+    /// Total bandwidth budget for all flows (bits per second, 0 for none)
+    bandwidth_budget: Option<u64>,
+    /// Allowed source locations (`scheme` or `scheme://host`) and directories
+    /// (`/path`); empty for any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_sources: Vec<String>,
     /// All flows
     flow: Vec<FlowConfig>,
+    /// Flows removed by reducing the total (restored when increased)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed_flow: Vec<FlowConfig>,
 }
 
 /// Source location (cannot be empty string)
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 struct Location(String);
 
 impl Default for Location {
@@ -77,32 +145,221 @@ impl Default for Location {
     }
 }
 
+/// Split a location URI into scheme and host
+///
+/// A location without `://` is all scheme (`test`).
+fn location_scheme_host(location: &str) -> (&str, Option<&str>) {
+    match location.find("://") {
+        Some(i) => {
+            let rest = &location[i + 3..];
+            let authority =
+                rest.split(&['/', '?', '#'][..]).next().unwrap_or("");
+            let host = authority.rsplit('@').next().unwrap_or(authority);
+            let host = match host.strip_prefix('[') {
+                Some(h) => h.split(']').next(),
+                None => host.split(':').next(),
+            };
+            (&location[..i], host.filter(|h| !h.is_empty()))
+        },
+        // Empty location is a video test source, like `test:`
+        None if location.is_empty() => ("test", None),
+        None => (location.split(':').next().unwrap_or(location), None),
+    }
+}
+
+/// Check if a location matches an allowed source entry
+///
+/// An entry is a scheme (`rtsp`), or a scheme and host (`rtsp://10.0.0.5`),
+/// which matches any port, user or path on that host.
+fn is_source_match(entry: &str, location: &str) -> bool {
+    let (scheme, host) = location_scheme_host(location);
+    let (entry_scheme, entry_host) = location_scheme_host(entry);
+    entry_scheme.eq_ignore_ascii_case(scheme)
+        && match (entry_host, host) {
+            (None, _) => true,
+            (Some(eh), Some(h)) => eh.eq_ignore_ascii_case(h),
+            (Some(_), None) => false,
+        }
+}
+
+/// Check if a filesystem path is under an allowed directory entry
+///
+/// Entries starting with `/` are directories.  Paths must be absolute, with
+/// no `..` components.
+fn is_path_match(entry: &str, path: &str) -> bool {
+    let path = Path::new(path);
+    entry.starts_with('/')
+        && path.is_absolute()
+        && !path.components().any(|c| c == Component::ParentDir)
+        && path.starts_with(entry)
+}
+
+/// Flow parameters checked against allowed sources
+const RESTRICTED_PARAMS: &[&str] =
+    &["location", "hls-dir", "overlay-file", "pipe"];
+
 /// Configuration for one flow
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 struct FlowConfig {
+    /// Flow name
+    name: Option<String>,
+    /// Flow enabled
+    enabled: Option<bool>,
     /// Source location URI
     location: Location,
     /// RTSP transport
     rtsp_transport: Option<String>,
     /// Source encoding
     source_encoding: Option<String>,
+    /// Audio source encoding (audio-only flow)
+    audio_encoding: Option<String>,
+    /// Audio sink encoding
+    audio_sink_encoding: Option<String>,
     /// Source timeout in seconds
     timeout: Option<u16>,
+    /// Initial connect timeout in seconds
+    connect_timeout: Option<u16>,
     /// Buffering latency in milliseconds
     latency: Option<u32>,
+    /// Buffering latency for RTSP over TCP in milliseconds
+    tcp_latency: Option<u32>,
+    /// Maximum RTP dropout time in milliseconds
+    max_dropout: Option<u32>,
+    /// HTTP PNG refresh interval in milliseconds
+    refresh: Option<u32>,
+    /// Loop on end of stream (non-live sources)
+    #[serde(rename = "loop")]
+    looping: Option<bool>,
     /// SDP parameter sets
     sprops: Option<String>,
+    /// RTP source caps
+    rtp_caps: Option<String>,
+    /// RTP video clock rate (Hz)
+    clock_rate: Option<u32>,
+    /// Bytes to skip at the start of each UDP packet
+    skip_first_bytes: Option<u16>,
+    /// Validate TLS certificates
+    tls_validation: Option<bool>,
+    /// HTTP source proxy URI
+    proxy: Option<String>,
+    /// Strict HTTPS certificate checking
+    ssl_strict: Option<bool>,
+    /// Extra HTTP source headers (one `Name: value` per line)
+    http_headers: Option<String>,
     /// Overlay text
     overlay_text: Option<String>,
+    /// Overlay text file path
+    overlay_file: Option<String>,
+    /// Interval to re-read overlay text file (ms)
+    overlay_file_interval: Option<u32>,
+    /// Statistics overlay format
+    stats_overlay: Option<String>,
+    /// Ticker overlay text
+    ticker_text: Option<String>,
+    /// Ticker speed (px per second, using 240 px height)
+    ticker_speed: Option<u32>,
+    /// Overlay font size (pt at reference height)
+    font_size: Option<u32>,
+    /// Overlay font reference height (px; 0 for no scaling)
+    font_height: Option<u32>,
+    /// Overlay text color (hex RGB or ARGB)
+    overlay_color: Option<String>,
+    /// Overlay background shading (1-255)
+    overlay_background: Option<u8>,
+    /// Timestamp overlay
+    timestamp_overlay: Option<bool>,
     /// Sink address
     address: Option<String>,
     /// Sink port
     port: Option<u16>,
     /// Sink encoding
     sink_encoding: Option<String>,
+    /// Maximum sink bitrate (bits per second)
+    max_bitrate: Option<u64>,
+    /// Sink socket send buffer size (bytes)
+    sink_buffer_size: Option<u32>,
+    /// Sink clock sync
+    sync: Option<bool>,
+    /// Sink maximum lateness (ms, -1 for unlimited)
+    max_lateness: Option<i64>,
+    /// In-band parameter set interval (sec, -1 for every IDR)
+    config_interval: Option<i32>,
+    /// H.264 stream format for RTP sink
+    h264_stream_format: Option<String>,
+    /// H.264 alignment for RTP sink
+    h264_alignment: Option<String>,
+    /// Automatic multicast group join
+    auto_multicast: Option<bool>,
+    /// Multicast loopback
+    multicast_loop: Option<bool>,
+    /// Multicast TTL (overrides global default)
+    ttl: Option<u8>,
+    /// DSCP (overrides global default)
+    dscp: Option<u8>,
+    /// Additional sink destinations (`address port [ttl [iface]]` lines)
+    destinations: Option<String>,
+    /// Maximum B-frames (h.264 / h.265)
+    bframes: Option<u32>,
+    /// Software encoder threads
+    encoder_threads: Option<u32>,
+    /// Sliced encoder threads (h.264)
+    sliced_threads: Option<bool>,
+    /// Streaming thread priority (nice value)
+    priority: Option<i32>,
+    /// Tune encoders for low latency
+    low_latency: Option<bool>,
+    /// Constrained-baseline profile (h.264)
+    constrained_baseline: Option<bool>,
+    /// Lost packets to request a key frame
+    keyframe_loss: Option<u64>,
+    /// Receive RTCP feedback (PLI / FIR) on RTP sink
+    rtcp_feedback: Option<bool>,
+    /// Packet loss percentage to raise alert
+    loss_alert: Option<f32>,
+    /// Raise alert when no packets are received
+    down_alert: Option<bool>,
+    /// Stats updates to raise or clear alerts
+    alert_intervals: Option<u32>,
+    /// Scaled resolution (WIDTHxHEIGHT)
+    resolution: Option<String>,
+    /// Aspect ratio handling
+    aspect_ratio: Option<String>,
+    /// Action when flow is stuck
+    stuck_action: Option<String>,
+    /// Check for stuck flow
+    stuck_check: Option<bool>,
+    /// Fill stream gaps with black / silence
+    gap_fill: Option<bool>,
+    /// Source failures before showing fallback color bars
+    fallback_source: Option<u32>,
+    /// Freeze last frame in window while stopped
+    freeze_frame: Option<bool>,
+    /// HTTP sink port (MJPEG)
+    http_port: Option<u16>,
+    /// HTTP sink path
+    http_path: Option<String>,
+    /// HLS sink directory
+    hls_dir: Option<String>,
+    /// Pipe sink (`fd:N` or named pipe path)
+    pipe: Option<String>,
+    /// HLS renditions (`WIDTHxHEIGHT@KBPS`, space separated)
+    renditions: Option<String>,
 }
 
 impl FlowConfig {
+    /// Get flow label for log messages (index, with name if set)
+    fn label(&self, number: usize) -> String {
+        match &self.name {
+            Some(name) => format!("Flow{} ({})", number, name),
+            None => format!("Flow{}", number),
+        }
+    }
+
+    /// Check if flow is enabled
+    fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
     /// Get RTSP transport
     fn rtsp_transport(&self) -> Transport {
         match &self.rtsp_transport {
@@ -125,6 +382,22 @@ impl FlowConfig {
         }
     }
 
+    /// Get audio source encoding
+    fn audio_encoding(&self) -> Option<AudioEncoding> {
+        match &self.audio_encoding {
+            Some(e) => e.parse().ok(),
+            None => None,
+        }
+    }
+
+    /// Get audio sink encoding
+    fn audio_sink_encoding(&self) -> Option<AudioEncoding> {
+        match &self.audio_sink_encoding {
+            Some(e) => e.parse().ok(),
+            None => None,
+        }
+    }
+
     /// Get source timeout
     fn timeout(&self) -> u16 {
         match self.timeout {
@@ -141,14 +414,84 @@ impl FlowConfig {
         }
     }
 
+    /// Get RTP video clock rate
+    fn clock_rate(&self) -> i32 {
+        match self.clock_rate.map(i32::try_from) {
+            Some(Ok(rate)) => rate,
+            Some(Err(_)) => 0,
+            None => RTP_VIDEO_CLOCK_RATE,
+        }
+    }
+
+    /// Get TLS certificate validation
+    fn tls_validation(&self) -> bool {
+        self.tls_validation.unwrap_or(true)
+    }
+
+    /// Get strict HTTPS certificate checking
+    fn ssl_strict(&self) -> bool {
+        self.ssl_strict.unwrap_or(true)
+    }
+
+    /// Get extra HTTP source headers
+    fn http_headers(&self) -> Vec<(&str, &str)> {
+        match &self.http_headers {
+            Some(h) => h
+                .lines()
+                .filter_map(|line| {
+                    let mut nv = line.splitn(2, ':');
+                    match (nv.next(), nv.next()) {
+                        (Some(n), Some(v)) => Some((n.trim(), v.trim())),
+                        _ => None,
+                    }
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Get additional sink destinations
+    fn destinations(&self) -> Vec<Destination> {
+        match &self.destinations {
+            Some(d) => parse_destinations(d).unwrap_or_default(),
+            None => vec![],
+        }
+    }
+
+    /// Get HLS renditions
+    fn renditions(&self) -> Vec<Rendition> {
+        match &self.renditions {
+            Some(r) => parse_renditions(r).unwrap_or_default(),
+            None => vec![],
+        }
+    }
+
     /// Get source
     fn source(&self) -> Source {
-        Source::default()
+        let mut source = Source::default()
             .with_location(&self.location.0)
             .with_rtsp_transport(self.rtsp_transport())
             .with_encoding(self.source_encoding())
+            .with_audio_encoding(self.audio_encoding())
             .with_timeout(self.timeout())
+            .with_connect_timeout(self.connect_timeout)
             .with_latency(self.latency())
+            .with_tcp_latency(self.tcp_latency)
+            .with_max_dropout(
+                self.max_dropout.unwrap_or(DEFAULT_MAX_DROPOUT_MS),
+            )
+            .with_refresh(self.refresh.unwrap_or(DEFAULT_REFRESH_MS))
+            .with_loop(self.looping.unwrap_or(false))
+            .with_rtp_caps(self.rtp_caps.as_deref())
+            .with_clock_rate(self.clock_rate())
+            .with_skip_first_bytes(self.skip_first_bytes.unwrap_or(0))
+            .with_tls_validation(self.tls_validation())
+            .with_proxy(self.proxy.as_deref())
+            .with_ssl_strict(self.ssl_strict());
+        for (name, value) in self.http_headers() {
+            source = source.with_header(name, value);
+        }
+        source
     }
 
     /// Get overlay text
@@ -159,6 +502,77 @@ impl FlowConfig {
         }
     }
 
+    /// Get statistics overlay format
+    fn stats_overlay(&self) -> Option<&str> {
+        match &self.stats_overlay {
+            Some(t) => Some(&t),
+            None => None,
+        }
+    }
+
+    /// Get ticker overlay text
+    fn ticker_text(&self) -> Option<&str> {
+        match &self.ticker_text {
+            Some(t) => Some(&t),
+            None => None,
+        }
+    }
+
+    /// Check if only the ticker changed (can be updated while playing)
+    fn is_ticker_change(&self, other: &Self) -> bool {
+        let mut flow = self.clone();
+        flow.ticker_text = other.ticker_text.clone();
+        flow.ticker_speed = other.ticker_speed;
+        self.ticker_text.is_some()
+            && other.ticker_text.is_some()
+            && flow == *other
+    }
+
+    /// Get lost packets to request a key frame
+    fn keyframe_loss(&self) -> Option<u64> {
+        match self.keyframe_loss.unwrap_or(0) {
+            0 => None,
+            lost => Some(lost),
+        }
+    }
+
+    /// Get overlay text color (ARGB)
+    fn overlay_color(&self) -> Option<u32> {
+        match &self.overlay_color {
+            Some(c) => parse_color(c).ok(),
+            None => None,
+        }
+    }
+
+    /// Get scaled resolution
+    fn resolution(&self) -> Option<(i32, i32)> {
+        match &self.resolution {
+            Some(r) => parse_resolution(r).ok(),
+            None => None,
+        }
+    }
+
+    /// Get action when flow is stuck
+    fn stuck_action(&self) -> StuckAction {
+        match &self.stuck_action {
+            Some(a) => a.parse().unwrap_or_default(),
+            None => StuckAction::default(),
+        }
+    }
+
+    /// Get aspect ratio handling
+    fn aspect_ratio(&self) -> AspectRatio {
+        match &self.aspect_ratio {
+            Some(a) => a.parse().unwrap_or_default(),
+            None => AspectRatio::default(),
+        }
+    }
+
+    /// Get timestamp overlay
+    fn timestamp_overlay(&self) -> bool {
+        self.timestamp_overlay.unwrap_or(false)
+    }
+
     /// Get sink encoding
     fn sink_encoding(&self) -> Encoding {
         match &self.sink_encoding {
@@ -170,16 +584,186 @@ impl FlowConfig {
         }
     }
 
-    /// Get sink
-    fn sink(&self) -> Sink {
-        match (&self.address, &self.port) {
-            (Some(address), Some(port)) => Sink::RTP(
+    /// Get HTTP sink path
+    fn http_path(&self) -> &str {
+        match &self.http_path {
+            Some(p) => &p,
+            None => "/",
+        }
+    }
+
+    /// Check if flow encodes video (so `max-bitrate` can be scaled)
+    ///
+    /// Passthrough flows send the source stream as-is, at its own bitrate.
+    fn is_encoded(&self) -> bool {
+        self.sinks()
+            .iter()
+            .any(|sink| sink.encoding() != self.source_encoding())
+            || self.overlay_text.is_some()
+            || self.overlay_file.is_some()
+            || self.stats_overlay.is_some()
+            || self.ticker_text.is_some()
+            || self.timestamp_overlay()
+            || self.resolution.is_some()
+            || !self.renditions().is_empty()
+    }
+
+    /// Get values of restricted parameters (name, value)
+    fn restricted_params(&self) -> Vec<(&'static str, &str)> {
+        let mut params = vec![("location", &self.location.0[..])];
+        if let Some(hls_dir) = &self.hls_dir {
+            params.push(("hls-dir", hls_dir));
+        }
+        if let Some(overlay_file) = &self.overlay_file {
+            params.push(("overlay-file", overlay_file));
+        }
+        if let Some(pipe) = &self.pipe {
+            params.push(("pipe", pipe));
+        }
+        params
+    }
+
+    /// Get sinks
+    fn sinks(&self) -> Vec<Sink> {
+        let mut sinks = vec![];
+        if let (Some(address), Some(port)) = (&self.address, &self.port) {
+            sinks.push(Sink::RTP(
                 String::from(address),
                 (*port).into(),
                 self.sink_encoding(),
                 true,
-            ),
-            _ => Sink::FAKE,
+            ));
+        }
+        if let Some(http_port) = self.http_port {
+            sinks.push(Sink::HTTP(http_port, self.http_path().to_string()));
+        }
+        if let Some(hls_dir) = &self.hls_dir {
+            sinks.push(Sink::HLS(hls_dir.to_string()));
+        }
+        if let Some(pipe) = &self.pipe {
+            sinks.push(Sink::PIPE(pipe.to_string(), self.sink_encoding()));
+        }
+        if sinks.is_empty() {
+            sinks.push(Sink::FAKE);
+        }
+        sinks
+    }
+
+    /// Validate flow configuration, parsing strictly
+    ///
+    /// Returns a list of problems found.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        check_parse::<Transport>(
+            &mut problems,
+            "rtsp-transport",
+            &self.rtsp_transport,
+        );
+        check_parse::<Encoding>(
+            &mut problems,
+            "source-encoding",
+            &self.source_encoding,
+        );
+        check_parse::<Encoding>(
+            &mut problems,
+            "sink-encoding",
+            &self.sink_encoding,
+        );
+        check_parse::<AudioEncoding>(
+            &mut problems,
+            "audio-encoding",
+            &self.audio_encoding,
+        );
+        check_parse::<AudioEncoding>(
+            &mut problems,
+            "audio-sink-encoding",
+            &self.audio_sink_encoding,
+        );
+        check_parse::<AspectRatio>(
+            &mut problems,
+            "aspect-ratio",
+            &self.aspect_ratio,
+        );
+        check_parse::<StuckAction>(
+            &mut problems,
+            "stuck-action",
+            &self.stuck_action,
+        );
+        if let Some(color) = &self.overlay_color {
+            if parse_color(color).is_err() {
+                problems.push(format!("invalid `overlay-color`: {}", color));
+            }
+        }
+        if let Some(resolution) = &self.resolution {
+            if parse_resolution(resolution).is_err() {
+                problems.push(format!("invalid `resolution`: {}", resolution));
+            }
+        }
+        if let Some(dests) = &self.destinations {
+            if parse_destinations(dests).is_err() {
+                problems.push(format!("invalid `destinations`: {:?}", dests));
+            }
+            if self.address.is_none() {
+                problems.push("`destinations` without RTP sink".to_string());
+            }
+        }
+        if let Some(renditions) = &self.renditions {
+            if parse_renditions(renditions).is_err() {
+                problems.push(format!("invalid `renditions`: {}", renditions));
+            }
+            if self.hls_dir.is_none() {
+                problems.push("`renditions` without HLS sink".to_string());
+            }
+        }
+        match (&self.address, self.port) {
+            (Some(_), None) => {
+                problems.push("RTP sink `address` without `port`".to_string())
+            },
+            (None, Some(_)) => {
+                problems.push("RTP sink `port` without `address`".to_string())
+            },
+            _ => (),
+        }
+        if let Some(format) = &self.h264_stream_format {
+            if !H264_STREAM_FORMATS.contains(&format.as_str()) {
+                problems
+                    .push(format!("invalid `h264-stream-format`: {}", format));
+            }
+        }
+        if let Some(alignment) = &self.h264_alignment {
+            if !H264_ALIGNMENTS.contains(&alignment.as_str()) {
+                problems
+                    .push(format!("invalid `h264-alignment`: {}", alignment));
+            }
+        }
+        if self.clock_rate() <= 0 {
+            problems.push("`clock-rate` must be positive".to_string());
+        }
+        if self.port == Some(0) {
+            problems.push("`port` out of range: 0".to_string());
+        }
+        if self.http_port == Some(0) {
+            problems.push("`http-port` out of range: 0".to_string());
+        }
+        if let Some(path) = &self.http_path {
+            if !path.starts_with('/') {
+                problems
+                    .push(format!("`http-path` must start with /: {}", path));
+            }
+        }
+        problems
+    }
+}
+
+/// Check that an optional value parses strictly
+fn check_parse<T: FromStr>(
+    problems: &mut Vec<String>,
+    name: &str,
+    value: &Option<String>,
+) {
+    if let Some(value) = value {
+        if value.parse::<T>().is_err() {
+            problems.push(format!("invalid `{}`: {}", name, value));
         }
     }
 }
@@ -195,13 +779,112 @@ fn is_parseable<T: FromStr>(value: String) -> Result<(), String> {
     }
 }
 
+/// Check if an argument is a valid clock rate (positive)
+fn is_clock_rate(value: String) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match value.parse::<i32>() {
+        Ok(rate) if rate > 0 => Ok(()),
+        _ => Err(String::from("Invalid clock rate")),
+    }
+}
+
+/// Check if an argument is a valid DSCP (0 to 63)
+fn is_dscp(value: String) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match parse_dscp(&value) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Invalid DSCP")),
+    }
+}
+
+/// Check if an argument is a valid thread priority (-20 to 19)
+fn is_priority(value: String) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match parse_priority(&value) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("Invalid priority")),
+    }
+}
+
+/// Parse a thread priority (nice value, -20 to 19)
+fn parse_priority(priority: &str) -> Result<i32, Error> {
+    match priority.parse()? {
+        p if (-20..=19).contains(&p) => Ok(p),
+        _ => Err(Error::Other("Invalid priority")),
+    }
+}
+
+/// Parse a DSCP (0 to 63)
+fn parse_dscp(dscp: &str) -> Result<u8, Error> {
+    match dscp.parse()? {
+        dscp if dscp < 64 => Ok(dscp),
+        _ => Err(Error::Other("Invalid DSCP")),
+    }
+}
+
+/// Parse a hex RGB or ARGB color (RGB is opaque)
+fn parse_color(color: &str) -> Result<u32, Error> {
+    let argb = u32::from_str_radix(color, 16)?;
+    match color.len() {
+        6 => Ok(0xFF_00_00_00 | argb),
+        8 => Ok(argb),
+        _ => Err(Error::Other("Invalid color")),
+    }
+}
+
+/// Parse sink destinations (`address port [ttl [iface]]` lines)
+fn parse_destinations(destinations: &str) -> Result<Vec<Destination>, Error> {
+    let mut dests = vec![];
+    for line in destinations.lines().filter(|l| !l.trim().is_empty()) {
+        let mut f = line.split_whitespace();
+        match (f.next(), f.next(), f.next(), f.next(), f.next()) {
+            (Some(address), Some(port), ttl, iface, None) => {
+                let port = port.parse::<u16>()?.into();
+                let ttl = match ttl {
+                    Some(ttl) => Some(ttl.parse::<u8>()?.into()),
+                    None => None,
+                };
+                let dest = Destination::new(address, port)
+                    .with_ttl(ttl)
+                    .with_iface(iface);
+                dests.push(dest);
+            },
+            _ => return Err(Error::Other("Invalid destination")),
+        }
+    }
+    Ok(dests)
+}
+
+/// Parse HLS renditions (`WIDTHxHEIGHT@KBPS`, space separated)
+fn parse_renditions(renditions: &str) -> Result<Vec<Rendition>, Error> {
+    renditions.split_whitespace().map(|r| r.parse()).collect()
+}
+
+/// Parse a resolution (WIDTHxHEIGHT)
+fn parse_resolution(resolution: &str) -> Result<(i32, i32), Error> {
+    let mut wh = resolution.splitn(2, 'x');
+    match (wh.next(), wh.next()) {
+        (Some(w), Some(h)) => match (w.parse()?, h.parse()?) {
+            (w, h) if w > 0 && h > 0 => Ok((w, h)),
+            _ => Err(Error::Other("Invalid resolution")),
+        },
+        _ => Err(Error::Other("Invalid resolution")),
+    }
+}
+
 /// Check if flow index is valid
 fn check_flow_idx(n_flows: usize, value: String) -> Result<(), String> {
     if value.is_empty() {
         return Ok(());
     }
     match value.parse::<usize>() {
-        Ok(f) if f < n_flows => Ok(()),
+        Ok(f) if f <= n_flows => Ok(()),
         Ok(_) => Err(String::from("Flow index out of bounds")),
         _ => Err(String::from("Invalid argument")),
     }
@@ -227,6 +910,13 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .value_name("method")
                         .possible_values(&["NONE", "VAAPI", "OMX"]),
                 )
+                .arg(
+                    Arg::with_name("software-fallback")
+                        .long("software-fallback")
+                        .help("use software if acceleration is unavailable")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
                 .arg(
                     Arg::with_name("control-port")
                         .short("c")
@@ -235,6 +925,61 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .takes_value(true)
                         .validator(is_parseable::<u16>),
                 )
+                .arg(
+                    Arg::with_name("control-timeout")
+                        .long("control-timeout")
+                        .help("control read timeout (0 for none)")
+                        .value_name("sec")
+                        .validator(is_parseable::<u64>),
+                )
+                .arg(
+                    Arg::with_name("control-retry-delay")
+                        .long("control-retry-delay")
+                        .help("delay after control connection error")
+                        .value_name("ms")
+                        .validator(is_parseable::<u64>),
+                )
+                .arg(
+                    Arg::with_name("ttl")
+                        .long("ttl")
+                        .help("default multicast TTL for RTP sinks")
+                        .value_name("ttl")
+                        .validator(is_parseable::<u8>),
+                )
+                .arg(
+                    Arg::with_name("dscp")
+                        .long("dscp")
+                        .help("default DSCP for RTP sinks")
+                        .value_name("dscp")
+                        .validator(is_dscp),
+                )
+                .arg(
+                    Arg::with_name("bandwidth-budget")
+                        .long("bandwidth-budget")
+                        .help("total flow bandwidth budget (0 for none)")
+                        .value_name("bps")
+                        .validator(is_parseable::<u64>),
+                )
+                .arg(
+                    Arg::with_name("play-timeout")
+                        .long("play-timeout")
+                        .help("wait for flows to play when built (0 for none)")
+                        .value_name("sec")
+                        .validator(is_parseable::<u16>),
+                )
+                .arg(
+                    Arg::with_name("control-token")
+                        .long("control-token")
+                        .help("token required to authenticate control")
+                        .value_name("token"),
+                )
+                .arg(
+                    Arg::with_name("watch-file")
+                        .long("watch-file")
+                        .help("reload when configuration file changes")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
                 .arg(
                     Arg::with_name("flows")
                         .short("f")
@@ -242,6 +987,11 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .help("total number of flows")
                         .value_name("total")
                         .validator(is_parseable::<u8>),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("display configuration as JSON"),
                 ),
         )
         .subcommand(
@@ -252,10 +1002,23 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                     Arg::with_name("number")
                         .index(1)
                         .required(true)
-                        .help("flow index number")
+                        .help("flow index number (total flows appends)")
                         .takes_value(true)
                         .validator(move |v| check_flow_idx(n_flows, v)),
                 )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .help("flow name")
+                        .value_name("name"),
+                )
+                .arg(
+                    Arg::with_name("enabled")
+                        .long("enabled")
+                        .help("enable or disable flow")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
                 .arg(
                     Arg::with_name("location")
                         .short("u")
@@ -280,6 +1043,20 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .value_name("encoding")
                         .possible_values(ENCODINGS),
                 )
+                .arg(
+                    Arg::with_name("audio-encoding")
+                        .long("audio-encoding")
+                        .help("audio source encoding (audio-only flow)")
+                        .value_name("encoding")
+                        .possible_values(AUDIO_ENCODINGS),
+                )
+                .arg(
+                    Arg::with_name("audio-sink-encoding")
+                        .long("audio-sink-encoding")
+                        .help("audio sink encoding")
+                        .value_name("encoding")
+                        .possible_values(AUDIO_ENCODINGS),
+                )
                 .arg(
                     Arg::with_name("timeout")
                         .short("t")
@@ -288,6 +1065,13 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .value_name("sec")
                         .validator(is_parseable::<u16>),
                 )
+                .arg(
+                    Arg::with_name("connect-timeout")
+                        .long("connect-timeout")
+                        .help("initial connect timeout in seconds")
+                        .value_name("sec")
+                        .validator(is_parseable::<u16>),
+                )
                 .arg(
                     Arg::with_name("latency")
                         .short("l")
@@ -296,6 +1080,80 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .value_name("ms")
                         .validator(is_parseable::<u32>),
                 )
+                .arg(
+                    Arg::with_name("tcp-latency")
+                        .long("tcp-latency")
+                        .help("buffering latency for RTSP over TCP")
+                        .value_name("ms")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("max-dropout")
+                        .long("max-dropout")
+                        .help("maximum RTP dropout time in milliseconds")
+                        .value_name("ms")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("loop")
+                        .long("loop")
+                        .help("loop on end of stream (not rtsp or udp)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("refresh")
+                        .long("refresh")
+                        .help("HTTP PNG refresh interval (0 for none)")
+                        .value_name("ms")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("clock-rate")
+                        .long("clock-rate")
+                        .help("RTP video clock rate")
+                        .value_name("Hz")
+                        .validator(is_clock_rate),
+                )
+                .arg(
+                    Arg::with_name("rtp-caps")
+                        .long("rtp-caps")
+                        .help("RTP source caps (replaces defaults)")
+                        .value_name("caps"),
+                )
+                .arg(
+                    Arg::with_name("skip-first-bytes")
+                        .long("skip-first-bytes")
+                        .help("bytes to skip at start of UDP packets")
+                        .value_name("bytes")
+                        .validator(is_parseable::<u16>),
+                )
+                .arg(
+                    Arg::with_name("tls-validation")
+                        .long("tls-validation")
+                        .help("validate TLS certificates (rtsps)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("proxy")
+                        .long("proxy")
+                        .help("HTTP source proxy")
+                        .value_name("uri"),
+                )
+                .arg(
+                    Arg::with_name("ssl-strict")
+                        .long("ssl-strict")
+                        .help("strict HTTPS certificate checking")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("http-headers")
+                        .long("http-headers")
+                        .help("extra HTTP source headers (Name: value lines)")
+                        .value_name("headers"),
+                )
                 .arg(
                     Arg::with_name("address")
                         .short("a")
@@ -320,23 +1178,381 @@ fn create_app(config: &Config) -> App<'static, 'static> {
                         .possible_values(ENCODINGS),
                 )
                 .arg(
-                    Arg::with_name("overlay-text")
-                        .short("x")
-                        .long("overlay-text")
-                        .help("overlay text (requires transcoding)")
-                        .takes_value(true),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("run").about("Run streambed video system"),
-        )
-}
+                    Arg::with_name("http-port")
+                        .long("http-port")
+                        .help("sink HTTP port (MJPEG)")
+                        .takes_value(true)
+                        .validator(is_parseable::<u16>),
+                )
+                .arg(
+                    Arg::with_name("http-path")
+                        .long("http-path")
+                        .help("sink HTTP path")
+                        .value_name("path"),
+                )
+                .arg(
+                    Arg::with_name("hls-dir")
+                        .long("hls-dir")
+                        .help("sink HLS directory")
+                        .value_name("dir"),
+                )
+                .arg(
+                    Arg::with_name("pipe")
+                        .long("pipe")
+                        .help("sink pipe (fd:N or path)")
+                        .value_name("pipe"),
+                )
+                .arg(
+                    Arg::with_name("renditions")
+                        .long("renditions")
+                        .help("HLS renditions (WIDTHxHEIGHT@KBPS ...)")
+                        .value_name("renditions"),
+                )
+                .arg(
+                    Arg::with_name("max-bitrate")
+                        .long("max-bitrate")
+                        .help("maximum sink bitrate (may cause loss)")
+                        .value_name("bps")
+                        .validator(is_parseable::<u64>),
+                )
+                .arg(
+                    Arg::with_name("destinations")
+                        .long("destinations")
+                        .help("additional sink destinations (address port [ttl [iface]] lines)")
+                        .value_name("destinations"),
+                )
+                .arg(
+                    Arg::with_name("sink-buffer-size")
+                        .long("sink-buffer-size")
+                        .help("sink socket send buffer size")
+                        .value_name("bytes")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("sync")
+                        .long("sync")
+                        .help("sink clock sync (default per sink)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("max-lateness")
+                        .long("max-lateness")
+                        .help("sink maximum lateness (-1 for unlimited)")
+                        .value_name("ms")
+                        .allow_hyphen_values(true)
+                        .validator(is_parseable::<i64>),
+                )
+                .arg(
+                    Arg::with_name("config-interval")
+                        .long("config-interval")
+                        .help("in-band parameter set interval (-1 every IDR)")
+                        .value_name("sec")
+                        .allow_hyphen_values(true)
+                        .validator(is_parseable::<i32>),
+                )
+                .arg(
+                    Arg::with_name("h264-stream-format")
+                        .long("h264-stream-format")
+                        .help("H.264 stream format for RTP sink")
+                        .value_name("format")
+                        .possible_values(H264_STREAM_FORMATS),
+                )
+                .arg(
+                    Arg::with_name("h264-alignment")
+                        .long("h264-alignment")
+                        .help("H.264 alignment for RTP sink")
+                        .value_name("alignment")
+                        .possible_values(H264_ALIGNMENTS),
+                )
+                .arg(
+                    Arg::with_name("auto-multicast")
+                        .long("auto-multicast")
+                        .help("join multicast group (default true)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("multicast-loop")
+                        .long("multicast-loop")
+                        .help("multicast loopback (default false)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("ttl")
+                        .long("ttl")
+                        .help("multicast TTL (overrides global)")
+                        .value_name("ttl")
+                        .validator(is_parseable::<u8>),
+                )
+                .arg(
+                    Arg::with_name("dscp")
+                        .long("dscp")
+                        .help("DSCP (overrides global)")
+                        .value_name("dscp")
+                        .validator(is_dscp),
+                )
+                .arg(
+                    Arg::with_name("bframes")
+                        .long("bframes")
+                        .help("maximum B-frames (adds latency)")
+                        .value_name("frames")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("priority")
+                        .long("priority")
+                        .help("streaming thread nice value (-20 to 19)")
+                        .value_name("nice")
+                        .allow_hyphen_values(true)
+                        .validator(is_priority),
+                )
+                .arg(
+                    Arg::with_name("encoder-threads")
+                        .long("encoder-threads")
+                        .help("software encoder threads")
+                        .value_name("threads")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("sliced-threads")
+                        .long("sliced-threads")
+                        .help("sliced encoder threads (lower latency)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("low-latency")
+                        .long("low-latency")
+                        .help("tune encoders for low latency")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("constrained-baseline")
+                        .long("constrained-baseline")
+                        .help("constrained-baseline H264 profile")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("keyframe-loss")
+                        .long("keyframe-loss")
+                        .help("lost packets to request a key frame")
+                        .value_name("packets")
+                        .validator(is_parseable::<u64>),
+                )
+                .arg(
+                    Arg::with_name("rtcp-feedback")
+                        .long("rtcp-feedback")
+                        .help("receive RTCP feedback on RTP sink")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("loss-alert")
+                        .long("loss-alert")
+                        .help("packet loss percentage to raise alert")
+                        .value_name("percent")
+                        .validator(is_parseable::<f32>),
+                )
+                .arg(
+                    Arg::with_name("down-alert")
+                        .long("down-alert")
+                        .help("raise alert when no packets are received")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("alert-intervals")
+                        .long("alert-intervals")
+                        .help("stats updates to raise or clear alerts")
+                        .value_name("intervals")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("resolution")
+                        .long("resolution")
+                        .help("scaled resolution (requires transcoding)")
+                        .value_name("WxH"),
+                )
+                .arg(
+                    Arg::with_name("aspect-ratio")
+                        .long("aspect-ratio")
+                        .help("aspect ratio when scaling")
+                        .value_name("aspect")
+                        .possible_values(&["", "FILL", "PRESERVE"]),
+                )
+                .arg(
+                    Arg::with_name("stuck-action")
+                        .long("stuck-action")
+                        .help("action when flow is stuck")
+                        .value_name("action")
+                        .possible_values(&["", "RESTART", "WARN", "FLUSH"]),
+                )
+                .arg(
+                    Arg::with_name("stuck-check")
+                        .long("stuck-check")
+                        .help("check for stuck flow (default true)")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("gap-fill")
+                        .long("gap-fill")
+                        .help("fill stream gaps with black / silence")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("freeze-frame")
+                        .long("freeze-frame")
+                        .help("freeze last frame in window while stopped")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("fallback-source")
+                        .long("fallback-source")
+                        .help("source failures before showing color bars")
+                        .value_name("failures")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("overlay-text")
+                        .short("x")
+                        .long("overlay-text")
+                        .help("overlay text (requires transcoding)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("overlay-file")
+                        .long("overlay-file")
+                        .help("overlay text file, re-read periodically")
+                        .value_name("path"),
+                )
+                .arg(
+                    Arg::with_name("overlay-file-interval")
+                        .long("overlay-file-interval")
+                        .help("interval to re-read overlay text file")
+                        .value_name("ms")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("stats-overlay")
+                        .long("stats-overlay")
+                        .help("stats overlay: {pushed}, {lost}, {late}")
+                        .value_name("format"),
+                )
+                .arg(
+                    Arg::with_name("ticker-text")
+                        .long("ticker-text")
+                        .help("scrolling ticker text (requires transcoding)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ticker-speed")
+                        .long("ticker-speed")
+                        .help("ticker speed")
+                        .value_name("px/s")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("font-size")
+                        .long("font-size")
+                        .help("overlay font size at reference height")
+                        .value_name("pt")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("font-height")
+                        .long("font-height")
+                        .help("font reference height (0 for no scaling)")
+                        .value_name("px")
+                        .validator(is_parseable::<u32>),
+                )
+                .arg(
+                    Arg::with_name("overlay-color")
+                        .long("overlay-color")
+                        .help("overlay text color (hex RGB or ARGB)")
+                        .value_name("color"),
+                )
+                .arg(
+                    Arg::with_name("overlay-background")
+                        .long("overlay-background")
+                        .help("overlay background shading (0 for none)")
+                        .value_name("shading")
+                        .validator(is_parseable::<u8>),
+                )
+                .arg(
+                    Arg::with_name("timestamp-overlay")
+                        .long("timestamp-overlay")
+                        .help("overlay UTC timestamp of each frame")
+                        .value_name("bool")
+                        .possible_values(&["", "true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("display flow configuration as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Write an example configuration file")
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("overwrite existing configuration file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("monitor")
+                .about("Display live flow events from a running service")
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .help("host running streambed (default localhost)")
+                        .value_name("host")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run streambed video system")
+                .arg(
+                    Arg::with_name("no-restart")
+                        .long("no-restart")
+                        .help("disable automatic restart of flows"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check configuration for problems"),
+        )
+        .subcommand(
+            SubCommand::with_name("version")
+                .about("Report versions and codec capabilities")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("display report as JSON"),
+                ),
+        )
+}
 
+/// Log a flow parameter change, using its control (hyphenated) name
 macro_rules! set_param {
-    ($num:expr, $param:expr) => {
+    ($num:expr, $param:ident) => {
+        set_param!($num, stringify!($param), $param)
+    };
+    ($num:expr, $name:expr, $param:expr) => {
         info!(
-            concat!("Setting flow{} `", stringify!($param), "` => {}",),
-            $num, $param,
+            "Setting flow{} `{}` => {}",
+            $num,
+            $name.replace('_', "-"),
+            $param,
         );
     };
 }
@@ -371,7 +1587,16 @@ impl Config {
         }
     }
 
+    /// Read configuration from file, without panicking
+    fn read(path: &Path) -> Result<Self, Error> {
+        let rdr = File::open(path)?;
+        Ok(muon_rs::from_reader(rdr)?)
+    }
+
     /// Store configuration to file
+    ///
+    /// A temporary file is written, then renamed, so that readers never see
+    /// a partially written configuration.
     fn store(&self) {
         let path = Config::path();
         if !path.exists() {
@@ -379,16 +1604,192 @@ impl Config {
                 error!("{:?} creating {:?}", e.kind(), path);
             }
         }
-        match File::create(&path) {
+        let tmp = path.with_extension("muon~");
+        match File::create(&tmp) {
             Ok(writer) => {
                 if let Err(_e) = muon_rs::to_writer(writer, self) {
-                    error!("storing {:?}", path);
+                    error!("storing {:?}", tmp);
+                } else if let Err(e) = rename(&tmp, &path) {
+                    error!("{:?} renaming {:?}", e.kind(), tmp);
+                }
+            },
+            Err(e) => error!("{:?} writing {:?}", e.kind(), tmp),
+        }
+    }
+
+    /// Get video acceleration method
+    fn acceleration(&self) -> Result<Acceleration, Error> {
+        match &self.acceleration {
+            Some(a) => a.parse(),
+            None => Ok(Acceleration::NONE),
+        }
+    }
+
+    /// Check if software fallback is allowed
+    fn software_fallback(&self) -> bool {
+        self.software_fallback.unwrap_or(false)
+    }
+
+    /// Get control connection read timeout
+    fn control_timeout(&self) -> Option<Duration> {
+        match self.control_timeout.unwrap_or(35) {
+            0 => None,
+            sec => Some(Duration::from_secs(sec)),
+        }
+    }
+
+    /// Get delay after a control connection error
+    fn control_retry_delay(&self) -> Duration {
+        Duration::from_millis(self.control_retry_delay.unwrap_or(1000))
+    }
+
+    /// Get timeout waiting for flows to play when built
+    fn play_timeout(&self) -> Option<u16> {
+        match self.play_timeout.unwrap_or(0) {
+            0 => None,
+            sec => Some(sec),
+        }
+    }
+
+    /// Check if a source location is allowed
+    fn is_location_allowed(&self, location: &str) -> bool {
+        self.allowed_sources.is_empty()
+            || self
+                .allowed_sources
+                .iter()
+                .any(|entry| is_source_match(entry, location))
+    }
+
+    /// Check if a filesystem path is allowed
+    fn is_path_allowed(&self, path: &str) -> bool {
+        self.allowed_sources.is_empty()
+            || self
+                .allowed_sources
+                .iter()
+                .any(|entry| is_path_match(entry, path))
+    }
+
+    /// Check if a restricted flow parameter value is allowed
+    fn is_param_allowed(&self, name: &str, value: &str) -> bool {
+        match name {
+            "location" => self.is_location_allowed(value),
+            // Inherited descriptors need an `fd` entry
+            "pipe" if value.starts_with("fd:") => {
+                self.is_location_allowed(value)
+            },
+            "hls-dir" | "overlay-file" | "pipe" => self.is_path_allowed(value),
+            _ => true,
+        }
+    }
+
+    /// Get the first restricted parameter of a flow which is not allowed
+    fn disallowed_param<'a>(
+        &self,
+        flow_cfg: &'a FlowConfig,
+    ) -> Option<(&'static str, &'a str)> {
+        flow_cfg
+            .restricted_params()
+            .into_iter()
+            .find(|(name, value)| !self.is_param_allowed(name, value))
+    }
+
+    /// Get total maximum bitrate of enabled encoded flows (bits per second)
+    fn total_bitrate(&self) -> u64 {
+        self.flow
+            .iter()
+            .filter(|f| f.enabled() && f.is_encoded())
+            .filter_map(|f| f.max_bitrate)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Get scale for flow bitrates to fit the bandwidth budget
+    fn bitrate_scale(&self) -> Option<f64> {
+        match (self.bandwidth_budget, self.total_bitrate()) {
+            (Some(budget), total) if budget > 0 && total > budget => {
+                Some(budget as f64 / total as f64)
+            },
+            _ => None,
+        }
+    }
+
+    /// Get maximum bitrate of a flow, scaled to fit the bandwidth budget
+    ///
+    /// Only encoded flows are scaled; passthrough flows keep their rate.
+    fn max_bitrate(&self, flow_cfg: &FlowConfig) -> Option<u64> {
+        match (flow_cfg.max_bitrate, self.bitrate_scale()) {
+            (Some(rate), Some(scale)) if flow_cfg.is_encoded() => {
+                // Zero would mean unlimited
+                Some(((rate as f64 * scale) as u64).max(1))
+            },
+            (rate, _) => rate,
+        }
+    }
+
+    /// Log flow bitrates which are adjusted for the bandwidth budget
+    fn log_bandwidth(&self) {
+        if let (Some(budget), Some(scale)) =
+            (self.bandwidth_budget, self.bitrate_scale())
+        {
+            warn!(
+                "Flow bitrates total {} bps, over budget of {}; scaling by {:.3}",
+                self.total_bitrate(),
+                budget,
+                scale
+            );
+            for (number, flow_cfg) in self.flow.iter().enumerate() {
+                if let (true, Some(rate)) = (
+                    flow_cfg.enabled() && flow_cfg.is_encoded(),
+                    flow_cfg.max_bitrate,
+                ) {
+                    info!(
+                        "{} max-bitrate {} => {}",
+                        flow_cfg.label(number),
+                        rate,
+                        self.max_bitrate(flow_cfg).unwrap_or(rate)
+                    );
                 }
+            }
+        }
+        if self.bandwidth_budget.unwrap_or(0) > 0 {
+            let unlimited = self
+                .flow
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| {
+                    f.enabled() && f.is_encoded() && f.max_bitrate.is_none()
+                })
+                .map(|(number, f)| f.label(number))
+                .collect::<Vec<_>>();
+            if !unlimited.is_empty() {
+                warn!(
+                    "Flows without `max-bitrate` not in budget: {}",
+                    unlimited.join(", ")
+                );
+            }
+        }
+    }
+
+    /// Check a control authentication token
+    fn authenticate(&self, token: &str) -> bool {
+        let token = token.trim_end_matches(char::from(SEP_RECORD));
+        match &self.control_token {
+            Some(t) => {
+                // Compare every byte, to avoid leaking timing information
+                t.len() == token.len()
+                    && t.bytes()
+                        .zip(token.bytes())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b))
+                        == 0
             },
-            Err(e) => error!("{:?} writing {:?}", e.kind(), path),
+            None => true,
         }
     }
 
+    /// Check if configuration file should be watched
+    fn watch_file(&self) -> bool {
+        self.watch_file.unwrap_or(false)
+    }
+
     /// Config sub-command
     fn config_subcommand<'a, P: Parameters<'a>>(
         &mut self,
@@ -396,10 +1797,24 @@ impl Config {
     ) -> Result<(), Error> {
         let mut param = false;
         if let Some(acceleration) = params.value("acceleration") {
-            self.acceleration = Some(acceleration.to_string());
+            self.acceleration = if acceleration.len() > 0 {
+                acceleration.parse::<Acceleration>()?;
+                Some(acceleration.to_string())
+            } else {
+                None
+            };
             info!("Setting `acceleration` => {}", acceleration);
             param = true;
         }
+        if let Some(fallback) = params.value("software-fallback") {
+            self.software_fallback = if fallback.len() > 0 {
+                Some(fallback.parse()?)
+            } else {
+                None
+            };
+            info!("Setting `software-fallback` => {}", fallback);
+            param = true;
+        }
         if let Some(port) = params.value("control-port") {
             self.control_port = if port.len() > 0 {
                 Some(port.parse()?)
@@ -409,93 +1824,364 @@ impl Config {
             info!("Setting `control-port` => {}", port);
             param = true;
         }
-        if let Some(flows) = params.value("flows") {
-            let flows: usize = flows.parse()?;
-            if flows != self.flow.len() {
-                self.flow.resize_with(flows, Default::default);
-                info!("Setting `flows` => {}", flows);
-                param = true;
-            }
-        }
-        if !param {
-            println!("\n{}", muon_rs::to_string(&self)?);
-        }
-        self.store();
-        Ok(())
-    }
-
-    /// Flow sub-command
-    fn flow_subcommand<'a, P: Parameters<'a>>(
-        &mut self,
-        params: &'a P,
-    ) -> Result<usize, Error> {
-        let number = params
-            .value("number")
-            .ok_or(Error::Other("Missing flow number"))?;
-        let number: usize = number.parse()?;
-        if number >= self.flow.len() {
-            return Err(Error::Other("Invalid flow number"));
-        }
-        let mut flow = &mut self.flow[number];
-        let mut param = false;
-        if let Some(location) = params.value("location") {
-            if location.is_empty() {
-                return Err(Error::Other("Invalid location"));
-            }
-            flow.location = Location {
-                0: String::from(location),
+        if let Some(timeout) = params.value("control-timeout") {
+            self.control_timeout = if timeout.len() > 0 {
+                Some(timeout.parse()?)
+            } else {
+                None
             };
-            set_param!(number, location);
+            info!("Setting `control-timeout` => {}", timeout);
             param = true;
         }
-        if let Some(rtsp_transport) = params.value("rtsp-transport") {
-            flow.rtsp_transport = if rtsp_transport.len() > 0 {
-                Some(String::from(rtsp_transport))
+        if let Some(delay) = params.value("control-retry-delay") {
+            self.control_retry_delay = if delay.len() > 0 {
+                Some(delay.parse()?)
             } else {
                 None
             };
-            set_param!(number, rtsp_transport);
+            info!("Setting `control-retry-delay` => {}", delay);
             param = true;
         }
-        if let Some(source_encoding) = params.value("source-encoding") {
-            flow.source_encoding = if source_encoding.len() > 0 {
-                Some(String::from(source_encoding))
+        if let Some(ttl) = params.value("ttl") {
+            self.ttl = if ttl.len() > 0 {
+                Some(ttl.parse()?)
             } else {
                 None
             };
-            set_param!(number, source_encoding);
+            info!("Setting `ttl` => {}", ttl);
             param = true;
         }
-        if let Some(timeout) = params.value("timeout") {
-            flow.timeout = Some(timeout.parse()?);
-            set_param!(number, timeout);
+        if let Some(dscp) = params.value("dscp") {
+            self.dscp = if dscp.len() > 0 {
+                Some(parse_dscp(dscp)?)
+            } else {
+                None
+            };
+            info!("Setting `dscp` => {}", dscp);
             param = true;
         }
-        if let Some(latency) = params.value("latency") {
-            flow.latency = if latency.len() > 0 {
-                Some(latency.parse()?)
+        if let Some(budget) = params.value("bandwidth-budget") {
+            self.bandwidth_budget = if budget.len() > 0 {
+                Some(budget.parse()?)
             } else {
                 None
             };
-            set_param!(number, latency);
+            info!("Setting `bandwidth-budget` => {}", budget);
             param = true;
         }
-        if let Some(address) = params.value("address") {
-            flow.address = if address.len() > 0 {
-                Some(String::from(address))
+        if let Some(timeout) = params.value("play-timeout") {
+            self.play_timeout = if timeout.len() > 0 {
+                Some(timeout.parse()?)
             } else {
                 None
             };
-            set_param!(number, address);
+            info!("Setting `play-timeout` => {}", timeout);
             param = true;
         }
-        if let Some(port) = params.value("port") {
-            flow.port = if port.len() > 0 {
-                Some(port.parse()?)
+        if let Some(token) = params.value("control-token") {
+            self.control_token = if token.len() > 0 {
+                Some(token.to_string())
             } else {
                 None
             };
-            set_param!(number, port);
+            info!("Setting `control-token`");
+            param = true;
+        }
+        if let Some(watch_file) = params.value("watch-file") {
+            self.watch_file = if watch_file.len() > 0 {
+                Some(watch_file.parse()?)
+            } else {
+                None
+            };
+            info!("Setting `watch-file` => {}", watch_file);
+            param = true;
+        }
+        if let Some(flows) = params.value("flows") {
+            let flows: usize = flows.parse()?;
+            if flows != self.flow.len() {
+                self.resize_flows(flows);
+                info!("Setting `flows` => {}", flows);
+                param = true;
+            }
+        }
+        if !param {
+            if params.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&self)?);
+            } else {
+                println!("\n{}", muon_rs::to_string(&self)?);
+            }
+        }
+        self.store();
+        Ok(())
+    }
+
+    /// Resize the flows, keeping any removed flows for later
+    fn resize_flows(&mut self, flows: usize) {
+        while self.flow.len() > flows {
+            if let Some(flow) = self.flow.pop() {
+                warn!(
+                    "Removing flow{} `location` => {} (kept in removed_flow)",
+                    self.flow.len(),
+                    flow.location.0,
+                );
+                self.removed_flow.push(flow);
+            }
+        }
+        while self.flow.len() < flows {
+            let flow = self.removed_flow.pop().unwrap_or_default();
+            self.flow.push(flow);
+        }
+    }
+
+    /// Append a new flow with default settings
+    ///
+    /// Removed flows are only restored by increasing `flows`.
+    fn append_flow(&mut self) {
+        info!("Appending flow{}", self.flow.len());
+        self.flow.push(FlowConfig::default());
+    }
+
+    /// Flow sub-command
+    ///
+    /// A flow number equal to the total number of flows appends a new flow.
+    fn flow_subcommand<'a, P: Parameters<'a>>(
+        &mut self,
+        params: &'a P,
+    ) -> Result<usize, Error> {
+        let number = params
+            .value("number")
+            .ok_or(Error::Other("Missing flow number"))?;
+        let number: usize = number.parse()?;
+        for name in RESTRICTED_PARAMS {
+            if let Some(value) = params.value(name) {
+                if !self.is_param_allowed(name, value) {
+                    warn!(
+                        flow = number;
+                        "Flow{}: {} not allowed: {}",
+                        number, name, value
+                    );
+                    return Err(Error::Other("Parameter not allowed"));
+                }
+            }
+        }
+        if number == self.flow.len() && number < usize::from(u8::MAX) {
+            self.append_flow();
+        }
+        if number >= self.flow.len() {
+            return Err(Error::Other(
+                "Invalid flow number (set `flows` first)",
+            ));
+        }
+        let mut flow = &mut self.flow[number];
+        let mut param = false;
+        if let Some(name) = params.value("name") {
+            flow.name = if name.len() > 0 {
+                Some(String::from(name))
+            } else {
+                None
+            };
+            set_param!(number, name);
+            param = true;
+        }
+        if let Some(enabled) = params.value("enabled") {
+            flow.enabled = if enabled.len() > 0 {
+                Some(enabled.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, enabled);
+            param = true;
+        }
+        if let Some(location) = params.value("location") {
+            if location.is_empty() {
+                return Err(Error::Other("Invalid location"));
+            }
+            flow.location = Location {
+                0: String::from(location),
+            };
+            set_param!(number, location);
+            param = true;
+        }
+        if let Some(rtsp_transport) = params.value("rtsp-transport") {
+            flow.rtsp_transport = if rtsp_transport.len() > 0 {
+                Some(String::from(rtsp_transport))
+            } else {
+                None
+            };
+            set_param!(number, rtsp_transport);
+            param = true;
+        }
+        if let Some(source_encoding) = params.value("source-encoding") {
+            flow.source_encoding = if source_encoding.len() > 0 {
+                Some(String::from(source_encoding))
+            } else {
+                None
+            };
+            set_param!(number, source_encoding);
+            param = true;
+        }
+        if let Some(audio_encoding) = params.value("audio-encoding") {
+            flow.audio_encoding = if audio_encoding.len() > 0 {
+                audio_encoding.parse::<AudioEncoding>()?;
+                Some(String::from(audio_encoding))
+            } else {
+                None
+            };
+            set_param!(number, audio_encoding);
+            param = true;
+        }
+        if let Some(audio_sink_encoding) = params.value("audio-sink-encoding") {
+            flow.audio_sink_encoding = if audio_sink_encoding.len() > 0 {
+                audio_sink_encoding.parse::<AudioEncoding>()?;
+                Some(String::from(audio_sink_encoding))
+            } else {
+                None
+            };
+            set_param!(number, audio_sink_encoding);
+            param = true;
+        }
+        if let Some(timeout) = params.value("timeout") {
+            flow.timeout = Some(timeout.parse()?);
+            set_param!(number, timeout);
+            param = true;
+        }
+        if let Some(connect_timeout) = params.value("connect-timeout") {
+            flow.connect_timeout = if connect_timeout.len() > 0 {
+                Some(connect_timeout.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, connect_timeout);
+            param = true;
+        }
+        if let Some(latency) = params.value("latency") {
+            flow.latency = if latency.len() > 0 {
+                Some(latency.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, latency);
+            param = true;
+        }
+        if let Some(tcp_latency) = params.value("tcp-latency") {
+            flow.tcp_latency = if tcp_latency.len() > 0 {
+                Some(tcp_latency.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, tcp_latency);
+            param = true;
+        }
+        if let Some(max_dropout) = params.value("max-dropout") {
+            flow.max_dropout = if max_dropout.len() > 0 {
+                Some(max_dropout.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, max_dropout);
+            param = true;
+        }
+        if let Some(looping) = params.value("loop") {
+            flow.looping = if looping.len() > 0 {
+                Some(looping.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, "loop", looping);
+            param = true;
+        }
+        if let Some(refresh) = params.value("refresh") {
+            flow.refresh = if refresh.len() > 0 {
+                Some(refresh.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, refresh);
+            param = true;
+        }
+        if let Some(clock_rate) = params.value("clock-rate") {
+            flow.clock_rate = if clock_rate.len() > 0 {
+                Some(clock_rate.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, clock_rate);
+            param = true;
+        }
+        if let Some(rtp_caps) = params.value("rtp-caps") {
+            flow.rtp_caps = if rtp_caps.len() > 0 {
+                Some(String::from(rtp_caps))
+            } else {
+                None
+            };
+            set_param!(number, rtp_caps);
+            param = true;
+        }
+        if let Some(skip_first_bytes) = params.value("skip-first-bytes") {
+            flow.skip_first_bytes = if skip_first_bytes.len() > 0 {
+                Some(skip_first_bytes.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, skip_first_bytes);
+            param = true;
+        }
+        if let Some(tls_validation) = params.value("tls-validation") {
+            flow.tls_validation = if tls_validation.len() > 0 {
+                Some(
+                    tls_validation
+                        .parse()
+                        .or(Err(Error::Other("Invalid tls-validation")))?,
+                )
+            } else {
+                None
+            };
+            set_param!(number, tls_validation);
+            param = true;
+        }
+        if let Some(proxy) = params.value("proxy") {
+            flow.proxy = if proxy.len() > 0 {
+                Some(String::from(proxy))
+            } else {
+                None
+            };
+            set_param!(number, proxy);
+            param = true;
+        }
+        if let Some(ssl_strict) = params.value("ssl-strict") {
+            flow.ssl_strict = if ssl_strict.len() > 0 {
+                Some(ssl_strict.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, ssl_strict);
+            param = true;
+        }
+        if let Some(http_headers) = params.value("http-headers") {
+            flow.http_headers = if http_headers.len() > 0 {
+                Some(String::from(http_headers))
+            } else {
+                None
+            };
+            set_param!(number, http_headers);
+            param = true;
+        }
+        if let Some(address) = params.value("address") {
+            flow.address = if address.len() > 0 {
+                Some(String::from(address))
+            } else {
+                None
+            };
+            set_param!(number, address);
+            param = true;
+        }
+        if let Some(port) = params.value("port") {
+            flow.port = if port.len() > 0 {
+                Some(port.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, port);
             param = true;
         }
         if let Some(sink_encoding) = params.value("sink-encoding") {
@@ -507,108 +2193,1302 @@ impl Config {
             set_param!(number, sink_encoding);
             param = true;
         }
-        if let Some(overlay_text) = params.value("overlay-text") {
-            flow.overlay_text = if overlay_text.len() > 0 {
-                Some(String::from(overlay_text))
+        if let Some(http_port) = params.value("http-port") {
+            flow.http_port = if http_port.len() > 0 {
+                Some(http_port.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, http_port);
+            param = true;
+        }
+        if let Some(http_path) = params.value("http-path") {
+            flow.http_path = if http_path.len() > 0 {
+                Some(String::from(http_path))
+            } else {
+                None
+            };
+            set_param!(number, http_path);
+            param = true;
+        }
+        if let Some(hls_dir) = params.value("hls-dir") {
+            flow.hls_dir = if hls_dir.len() > 0 {
+                Some(String::from(hls_dir))
+            } else {
+                None
+            };
+            set_param!(number, hls_dir);
+            param = true;
+        }
+        if let Some(pipe) = params.value("pipe") {
+            flow.pipe = if pipe.len() > 0 {
+                Some(String::from(pipe))
+            } else {
+                None
+            };
+            set_param!(number, pipe);
+            param = true;
+        }
+        if let Some(renditions) = params.value("renditions") {
+            flow.renditions = if renditions.len() > 0 {
+                parse_renditions(renditions)?;
+                Some(String::from(renditions))
+            } else {
+                None
+            };
+            set_param!(number, renditions);
+            param = true;
+        }
+        if let Some(max_bitrate) = params.value("max-bitrate") {
+            flow.max_bitrate = if max_bitrate.len() > 0 {
+                Some(max_bitrate.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, max_bitrate);
+            param = true;
+        }
+        if let Some(destinations) = params.value("destinations") {
+            flow.destinations = if destinations.len() > 0 {
+                parse_destinations(destinations)?;
+                Some(String::from(destinations))
+            } else {
+                None
+            };
+            set_param!(number, destinations);
+            param = true;
+        }
+        if let Some(sink_buffer_size) = params.value("sink-buffer-size") {
+            flow.sink_buffer_size = if sink_buffer_size.len() > 0 {
+                Some(sink_buffer_size.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, sink_buffer_size);
+            param = true;
+        }
+        if let Some(sync) = params.value("sync") {
+            flow.sync = if sync.len() > 0 {
+                Some(sync.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, sync);
+            param = true;
+        }
+        if let Some(max_lateness) = params.value("max-lateness") {
+            flow.max_lateness = if max_lateness.len() > 0 {
+                Some(max_lateness.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, max_lateness);
+            param = true;
+        }
+        if let Some(config_interval) = params.value("config-interval") {
+            flow.config_interval = if config_interval.len() > 0 {
+                Some(config_interval.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, config_interval);
+            param = true;
+        }
+        if let Some(h264_stream_format) = params.value("h264-stream-format") {
+            if !H264_STREAM_FORMATS.contains(&h264_stream_format) {
+                return Err(Error::Other("Invalid stream format"));
+            }
+            flow.h264_stream_format = if h264_stream_format.len() > 0 {
+                Some(h264_stream_format.to_string())
+            } else {
+                None
+            };
+            set_param!(number, h264_stream_format);
+            param = true;
+        }
+        if let Some(h264_alignment) = params.value("h264-alignment") {
+            if !H264_ALIGNMENTS.contains(&h264_alignment) {
+                return Err(Error::Other("Invalid alignment"));
+            }
+            flow.h264_alignment = if h264_alignment.len() > 0 {
+                Some(h264_alignment.to_string())
+            } else {
+                None
+            };
+            set_param!(number, h264_alignment);
+            param = true;
+        }
+        if let Some(auto_multicast) = params.value("auto-multicast") {
+            flow.auto_multicast = if auto_multicast.len() > 0 {
+                Some(auto_multicast.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, auto_multicast);
+            param = true;
+        }
+        if let Some(multicast_loop) = params.value("multicast-loop") {
+            flow.multicast_loop = if multicast_loop.len() > 0 {
+                Some(multicast_loop.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, multicast_loop);
+            param = true;
+        }
+        if let Some(ttl) = params.value("ttl") {
+            flow.ttl = if ttl.len() > 0 {
+                Some(ttl.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, ttl);
+            param = true;
+        }
+        if let Some(dscp) = params.value("dscp") {
+            flow.dscp = if dscp.len() > 0 {
+                Some(parse_dscp(dscp)?)
+            } else {
+                None
+            };
+            set_param!(number, dscp);
+            param = true;
+        }
+        if let Some(bframes) = params.value("bframes") {
+            flow.bframes = if bframes.len() > 0 {
+                Some(bframes.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, bframes);
+            param = true;
+        }
+        if let Some(priority) = params.value("priority") {
+            flow.priority = if priority.len() > 0 {
+                Some(parse_priority(priority)?)
+            } else {
+                None
+            };
+            set_param!(number, priority);
+            param = true;
+        }
+        if let Some(encoder_threads) = params.value("encoder-threads") {
+            flow.encoder_threads = if encoder_threads.len() > 0 {
+                Some(encoder_threads.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, encoder_threads);
+            param = true;
+        }
+        if let Some(sliced_threads) = params.value("sliced-threads") {
+            flow.sliced_threads = if sliced_threads.len() > 0 {
+                Some(sliced_threads.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, sliced_threads);
+            param = true;
+        }
+        if let Some(low_latency) = params.value("low-latency") {
+            flow.low_latency = if low_latency.len() > 0 {
+                Some(low_latency.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, low_latency);
+            param = true;
+        }
+        if let Some(constrained_baseline) = params.value("constrained-baseline")
+        {
+            flow.constrained_baseline = if constrained_baseline.len() > 0 {
+                Some(constrained_baseline.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, constrained_baseline);
+            param = true;
+        }
+        if let Some(keyframe_loss) = params.value("keyframe-loss") {
+            flow.keyframe_loss = if keyframe_loss.len() > 0 {
+                Some(keyframe_loss.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, keyframe_loss);
+            param = true;
+        }
+        if let Some(rtcp_feedback) = params.value("rtcp-feedback") {
+            flow.rtcp_feedback = if rtcp_feedback.len() > 0 {
+                Some(rtcp_feedback.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, rtcp_feedback);
+            param = true;
+        }
+        if let Some(loss_alert) = params.value("loss-alert") {
+            flow.loss_alert = if loss_alert.len() > 0 {
+                Some(loss_alert.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, loss_alert);
+            param = true;
+        }
+        if let Some(down_alert) = params.value("down-alert") {
+            flow.down_alert = if down_alert.len() > 0 {
+                Some(down_alert.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, down_alert);
+            param = true;
+        }
+        if let Some(alert_intervals) = params.value("alert-intervals") {
+            flow.alert_intervals = if alert_intervals.len() > 0 {
+                Some(alert_intervals.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, alert_intervals);
+            param = true;
+        }
+        if let Some(resolution) = params.value("resolution") {
+            flow.resolution = if resolution.len() > 0 {
+                parse_resolution(resolution)?;
+                Some(String::from(resolution))
+            } else {
+                None
+            };
+            set_param!(number, resolution);
+            param = true;
+        }
+        if let Some(aspect_ratio) = params.value("aspect-ratio") {
+            flow.aspect_ratio = if aspect_ratio.len() > 0 {
+                aspect_ratio.parse::<AspectRatio>()?;
+                Some(String::from(aspect_ratio))
+            } else {
+                None
+            };
+            set_param!(number, aspect_ratio);
+            param = true;
+        }
+        if let Some(stuck_action) = params.value("stuck-action") {
+            flow.stuck_action = if stuck_action.len() > 0 {
+                stuck_action.parse::<StuckAction>()?;
+                Some(String::from(stuck_action))
+            } else {
+                None
+            };
+            set_param!(number, stuck_action);
+            param = true;
+        }
+        if let Some(stuck_check) = params.value("stuck-check") {
+            flow.stuck_check = if stuck_check.len() > 0 {
+                Some(stuck_check.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, stuck_check);
+            param = true;
+        }
+        if let Some(gap_fill) = params.value("gap-fill") {
+            flow.gap_fill = if gap_fill.len() > 0 {
+                Some(gap_fill.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, gap_fill);
+            param = true;
+        }
+        if let Some(freeze_frame) = params.value("freeze-frame") {
+            flow.freeze_frame = if freeze_frame.len() > 0 {
+                Some(freeze_frame.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, freeze_frame);
+            param = true;
+        }
+        if let Some(fallback_source) = params.value("fallback-source") {
+            flow.fallback_source = if fallback_source.len() > 0 {
+                Some(fallback_source.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, fallback_source);
+            param = true;
+        }
+        if let Some(overlay_text) = params.value("overlay-text") {
+            flow.overlay_text = if overlay_text.len() > 0 {
+                Some(String::from(overlay_text))
+            } else {
+                None
+            };
+            set_param!(number, overlay_text);
+            param = true;
+        }
+        if let Some(overlay_file) = params.value("overlay-file") {
+            flow.overlay_file = if overlay_file.len() > 0 {
+                Some(String::from(overlay_file))
+            } else {
+                None
+            };
+            set_param!(number, overlay_file);
+            param = true;
+        }
+        if let Some(overlay_file_interval) =
+            params.value("overlay-file-interval")
+        {
+            flow.overlay_file_interval = if overlay_file_interval.len() > 0 {
+                Some(overlay_file_interval.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, overlay_file_interval);
+            param = true;
+        }
+        if let Some(stats_overlay) = params.value("stats-overlay") {
+            flow.stats_overlay = if stats_overlay.len() > 0 {
+                Some(String::from(stats_overlay))
+            } else {
+                None
+            };
+            set_param!(number, stats_overlay);
+            param = true;
+        }
+        if let Some(ticker_text) = params.value("ticker-text") {
+            flow.ticker_text = if ticker_text.len() > 0 {
+                Some(String::from(ticker_text))
+            } else {
+                None
+            };
+            set_param!(number, ticker_text);
+            param = true;
+        }
+        if let Some(ticker_speed) = params.value("ticker-speed") {
+            flow.ticker_speed = if ticker_speed.len() > 0 {
+                Some(ticker_speed.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, ticker_speed);
+            param = true;
+        }
+        if let Some(font_size) = params.value("font-size") {
+            flow.font_size = if font_size.len() > 0 {
+                Some(font_size.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, font_size);
+            param = true;
+        }
+        if let Some(font_height) = params.value("font-height") {
+            flow.font_height = if font_height.len() > 0 {
+                Some(font_height.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, font_height);
+            param = true;
+        }
+        if let Some(overlay_color) = params.value("overlay-color") {
+            flow.overlay_color = if overlay_color.len() > 0 {
+                parse_color(overlay_color)?;
+                Some(String::from(overlay_color))
+            } else {
+                None
+            };
+            set_param!(number, overlay_color);
+            param = true;
+        }
+        if let Some(overlay_background) = params.value("overlay-background") {
+            flow.overlay_background = if overlay_background.len() > 0 {
+                Some(overlay_background.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, overlay_background);
+            param = true;
+        }
+        if let Some(timestamp_overlay) = params.value("timestamp-overlay") {
+            flow.timestamp_overlay = if timestamp_overlay.len() > 0 {
+                Some(timestamp_overlay.parse()?)
+            } else {
+                None
+            };
+            set_param!(number, timestamp_overlay);
+            param = true;
+        }
+        if !param {
+            if params.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(flow)?);
+            } else {
+                println!("\n{}", muon_rs::to_string(flow)?);
+            }
+        }
+        self.store();
+        Ok(number)
+    }
+
+    /// Convert config into a Vec of Flows (`None` for disabled flows)
+    fn into_flows(
+        self,
+        fb: Sender<Feedback>,
+        restart: bool,
+    ) -> Result<Vec<Option<Flow>>, Error> {
+        let mut flows = vec![];
+        let mut failed = vec![];
+        self.log_bandwidth();
+        for number in 0..self.flow.len() {
+            match self.create_flow(number, fb.clone(), restart) {
+                Ok(flow) => flows.push(flow),
+                // Report failures (with build stage) and keep going
+                Err(e) => {
+                    self.log_flow_error(number, &e);
+                    failed.push(self.flow[number].label(number));
+                    flows.push(None);
+                },
+            }
+        }
+        if !failed.is_empty() {
+            warn!(
+                "{} flow(s) failed to start: {}",
+                failed.len(),
+                failed.join(", ")
+            );
+        }
+        Ok(flows)
+    }
+
+    /// Log a flow which failed to build or play
+    fn log_flow_error(&self, number: usize, e: &Error) {
+        if let Some(flow_cfg) = self.flow.get(number) {
+            error!(
+                flow = number, name = flow_cfg.name.as_deref();
+                "{} failed to start: {}",
+                flow_cfg.label(number), e
+            );
+        }
+    }
+
+    /// Create a flow, logging failure and leaving it stopped (`None`)
+    fn create_flow_or_log(
+        &self,
+        number: usize,
+        fb: Sender<Feedback>,
+        restart: bool,
+    ) -> Option<Flow> {
+        match self.create_flow(number, fb, restart) {
+            Ok(flow) => flow,
+            Err(e) => {
+                self.log_flow_error(number, &e);
+                None
+            },
+        }
+    }
+
+    /// Create a flow, unless it is disabled
+    fn create_flow(
+        &self,
+        number: usize,
+        fb: Sender<Feedback>,
+        restart: bool,
+    ) -> Result<Option<Flow>, Error> {
+        let acceleration = self.acceleration()?;
+        if let Some(flow_cfg) = self.flow.iter().skip(number).next() {
+            if !flow_cfg.enabled() {
+                info!(
+                    flow = number, name = flow_cfg.name.as_deref();
+                    "{} disabled",
+                    flow_cfg.label(number)
+                );
+                return Ok(None);
+            }
+            if let Some((name, value)) = self.disallowed_param(flow_cfg) {
+                error!(
+                    flow = number, name = flow_cfg.name.as_deref();
+                    "{} {} not allowed: {}",
+                    flow_cfg.label(number), name, value
+                );
+                return Err(Error::Other("Parameter not allowed"));
+            }
+            let flow = FlowBuilder::new(number)
+                .with_name(flow_cfg.name.as_deref())
+                .with_acceleration(acceleration)
+                .with_software_fallback(self.software_fallback())
+                .with_source(flow_cfg.source())
+                .with_overlay_text(flow_cfg.overlay_text())
+                .with_overlay_file(flow_cfg.overlay_file.as_deref())
+                .with_overlay_file_interval(flow_cfg.overlay_file_interval)
+                .with_stats_overlay(flow_cfg.stats_overlay())
+                .with_ticker(flow_cfg.ticker_text())
+                .with_ticker_speed(flow_cfg.ticker_speed)
+                .with_timestamp_overlay(flow_cfg.timestamp_overlay())
+                .with_font_size(flow_cfg.font_size)
+                .with_font_height(flow_cfg.font_height)
+                .with_audio_sink_encoding(flow_cfg.audio_sink_encoding())
+                .with_overlay_color(flow_cfg.overlay_color())
+                .with_overlay_background(flow_cfg.overlay_background)
+                .with_sinks(flow_cfg.sinks())
+                .with_max_bitrate(self.max_bitrate(flow_cfg))
+                .with_sink_buffer_size(flow_cfg.sink_buffer_size)
+                .with_sync(flow_cfg.sync)
+                .with_max_lateness(flow_cfg.max_lateness)
+                .with_config_interval(flow_cfg.config_interval)
+                .with_h264_format(
+                    flow_cfg.h264_stream_format.as_deref(),
+                    flow_cfg.h264_alignment.as_deref(),
+                )
+                .with_auto_multicast(flow_cfg.auto_multicast.unwrap_or(true))
+                .with_multicast_loop(flow_cfg.multicast_loop.unwrap_or(false))
+                .with_ttl(flow_cfg.ttl.or(self.ttl).map(i32::from))
+                .with_dscp(flow_cfg.dscp.or(self.dscp).map(i32::from))
+                .with_destinations(flow_cfg.destinations())
+                .with_renditions(flow_cfg.renditions())
+                .with_bframes(flow_cfg.bframes.unwrap_or(0))
+                .with_encoder_threads(flow_cfg.encoder_threads)
+                .with_priority(flow_cfg.priority)
+                .with_sliced_threads(flow_cfg.sliced_threads.unwrap_or(false))
+                .with_low_latency(flow_cfg.low_latency.unwrap_or(true))
+                .with_constrained_baseline(
+                    flow_cfg.constrained_baseline.unwrap_or(false),
+                )
+                .with_keyframe_loss(flow_cfg.keyframe_loss())
+                .with_rtcp_feedback(flow_cfg.rtcp_feedback.unwrap_or(false))
+                .with_loss_alert(flow_cfg.loss_alert)
+                .with_down_alert(flow_cfg.down_alert.unwrap_or(false))
+                .with_alert_intervals(flow_cfg.alert_intervals)
+                .with_resolution(flow_cfg.resolution())
+                .with_scale_aspect(flow_cfg.aspect_ratio())
+                .with_stuck_action(flow_cfg.stuck_action())
+                .with_stuck_check(flow_cfg.stuck_check.unwrap_or(true))
+                .with_gap_fill(flow_cfg.gap_fill.unwrap_or(false))
+                .with_fallback_source(flow_cfg.fallback_source)
+                .with_freeze_frame(flow_cfg.freeze_frame.unwrap_or(false))
+                .with_feedback(Some(fb))
+                .with_restart(restart)
+                .with_play_timeout(self.play_timeout())
+                .build()?;
+            Ok(Some(flow))
+        } else {
+            Err(Error::Other("Invalid flow number"))
+        }
+    }
+}
+
+/// Format a log record as a JSON line
+///
+/// Flow messages have `flow` (index) and `name` key-values.
+fn log_json(record: &log::Record) -> String {
+    let mut line = serde_json::json!({
+        "level": record.level().to_string(),
+        "target": record.target(),
+    });
+    let kvs = record.key_values();
+    if let Some(flow) = kvs.get(Key::from_str("flow")) {
+        if let Some(flow) = flow.to_u64() {
+            line["flow"] = flow.into();
+        }
+    }
+    if let Some(name) = kvs.get(Key::from_str("name")) {
+        if let Some(name) = name.to_borrowed_str() {
+            line["name"] = name.into();
+        }
+    }
+    line["message"] = record.args().to_string().into();
+    line.to_string()
+}
+
+/// Initialize logger (`STREAMBED_LOG_FORMAT=json` for JSON lines)
+fn init_logger() {
+    let env = Env::default().default_filter_or("info");
+    let mut builder = env_logger::from_env(env);
+    builder.format_timestamp(None);
+    if let Ok("json") = std::env::var("STREAMBED_LOG_FORMAT").as_deref() {
+        builder.format(|buf, record| writeln!(buf, "{}", log_json(record)));
+    }
+    builder.init();
+}
+
+/// Main function
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    init_logger();
+    let mut config = Config::load();
+    match create_app(&config).get_matches().subcommand() {
+        ("config", Some(matches)) => config.config_subcommand(matches)?,
+        ("flow", Some(matches)) => {
+            config.flow_subcommand(matches)?;
+        },
+        ("init", Some(matches)) => {
+            init_subcommand(matches.is_present("force"))?
+        },
+        ("monitor", Some(matches)) => monitor_subcommand(
+            &config,
+            matches.value_of("host").unwrap_or("localhost"),
+        )?,
+        ("run", Some(matches)) => {
+            run_subcommand(config, !matches.is_present("no-restart"))?
+        },
+        ("validate", Some(_)) => validate_subcommand()?,
+        ("version", Some(matches)) => {
+            version_subcommand(matches.is_present("json"))?
+        },
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Version and capability report
+#[derive(Serialize)]
+struct Report {
+    /// Streambed version
+    streambed: &'static str,
+    /// GStreamer version
+    gstreamer: String,
+    /// Capabilities of all encodings
+    encodings: Vec<Capability>,
+}
+
+/// Capabilities of one encoding
+#[derive(Serialize)]
+struct Capability {
+    /// Video encoding
+    encoding: String,
+    /// Acceleration methods available for encoding
+    encode: Vec<String>,
+    /// Acceleration methods available for decoding
+    decode: Vec<String>,
+    /// RTP payloader available
+    pay: bool,
+    /// RTP depayloader available
+    depay: bool,
+}
+
+impl Report {
+    /// Print the report in human-readable form
+    fn print(&self) {
+        println!("streambed {}", self.streambed);
+        println!("{}\n", self.gstreamer);
+        println!(
+            "{:<9} {:<16} {:<16} {:<5} {:<5}",
+            "encoding", "encode", "decode", "pay", "depay"
+        );
+        let list = |v: &[String]| match v.len() {
+            0 => "-".to_string(),
+            _ => v.join(","),
+        };
+        let yes_no = |v: bool| if v { "yes" } else { "no" };
+        for cap in &self.encodings {
+            println!(
+                "{:<9} {:<16} {:<16} {:<5} {:<5}",
+                cap.encoding,
+                list(&cap.encode),
+                list(&cap.decode),
+                yes_no(cap.pay),
+                yes_no(cap.depay),
+            );
+        }
+    }
+}
+
+impl Capability {
+    /// Check capabilities of an encoding
+    fn new(enc: &Encoding) -> Self {
+        let pay = enc.pay_factory();
+        let depay = enc.depay_factory();
+        Capability {
+            encoding: format!("{:?}", enc),
+            encode: available_accel(|a| enc.encode_factories(a)),
+            decode: available_accel(|a| enc.decode_factories(a)),
+            pay: has_factories(pay.as_ref().map(std::slice::from_ref)),
+            depay: has_factories(depay.as_ref().map(std::slice::from_ref)),
+        }
+    }
+}
+
+/// Check if all element factories are available
+fn has_factories(factories: Option<&[&str]>) -> bool {
+    match factories {
+        Some(factories) => factories
+            .iter()
+            .all(|f| gstreamer::ElementFactory::find(f).is_some()),
+        None => false,
+    }
+}
+
+/// Get acceleration methods available for an encoding
+fn available_accel<'a, F>(check: F) -> Vec<String>
+where
+    F: Fn(Acceleration) -> Option<&'a [&'a str]>,
+{
+    Acceleration::ALL
+        .iter()
+        .filter(|a| has_factories(check(**a)))
+        .map(|a| format!("{:?}", a))
+        .collect()
+}
+
+/// Version sub-command
+fn version_subcommand(json: bool) -> Result<(), Error> {
+    gstreamer::init().expect("gstreamer init failed!");
+    let gstreamer = gstreamer::version_string().to_string();
+    let encodings = Encoding::ALL.iter().map(Capability::new).collect();
+    let report = Report {
+        streambed: VERSION,
+        gstreamer,
+        encodings,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        report.print();
+    }
+    Ok(())
+}
+
+/// Validate sub-command
+fn validate_subcommand() -> Result<(), Error> {
+    let path = Config::path();
+    let config = Config::read(&path)?;
+    let mut problems = vec![];
+    if let Err(e) = config.acceleration() {
+        problems.push(format!("Config: invalid `acceleration`: {}", e));
+    }
+    if config.control_port == Some(0) {
+        problems.push("Config: `control-port` out of range: 0".to_string());
+    }
+    for (number, flow) in config.flow.iter().enumerate() {
+        for problem in flow.validate() {
+            problems.push(format!("Flow{}: {}", number, problem));
+        }
+        if let Some((name, value)) = config.disallowed_param(flow) {
+            problems.push(format!(
+                "Flow{}: `{}` not allowed: {}",
+                number, name, value
+            ));
+        }
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    match problems.len() {
+        0 => {
+            println!("{:?}: OK", path);
+            Ok(())
+        },
+        n => {
+            println!("{:?}: {} problem(s) found", path, n);
+            Err(Error::Other("Invalid configuration"))
+        },
+    }
+}
+
+/// Init sub-command
+fn init_subcommand(force: bool) -> Result<(), Error> {
+    let path = Config::path();
+    if path.exists() && !force {
+        println!("{:?} exists (use --force to overwrite)", path);
+        return Err(Error::Other("Configuration exists"));
+    }
+    let config = Config {
+        flow: vec![FlowConfig::default()],
+        ..Default::default()
+    };
+    let mut skeleton = INIT_CONFIG.to_string();
+    skeleton.push_str(&muon_rs::to_string(&config)?);
+    skeleton.push_str(INIT_FLOW);
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    File::create(&path)?.write_all(skeleton.as_bytes())?;
+    println!("{:?}: written", path);
+    Ok(())
+}
+
+/// Monitor sub-command
+fn monitor_subcommand(config: &Config, host: &str) -> Result<(), Error> {
+    let port = config.control_port.unwrap_or(8001);
+    let mut socket = TcpStream::connect((host, port))?;
+    if let Some(token) = &config.control_token {
+        let mut msg = b"auth".to_vec();
+        msg.push(SEP_RECORD);
+        msg.extend(token.as_bytes());
+        msg.push(SEP_GROUP);
+        socket.write_all(&msg)?;
+    }
+    write_message(&mut socket, "monitor", &[])?;
+    let mut reader = BufReader::new(socket);
+    let mut buf = vec![];
+    while let Some(msg) = read_command(&mut reader, &mut buf)? {
+        if let Some(line) = format_event(&msg) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Format an event message as one line of text
+fn format_event(msg: &str) -> Option<String> {
+    let mut mp = msg.splitn(2, char::from(SEP_RECORD));
+    let params = match (mp.next(), mp.next()) {
+        (Some("event"), Some(params)) => params,
+        _ => return None,
+    };
+    let mut line = format!("Flow{}", params.value("number")?);
+    if let Some(name) = params.value("name") {
+        line.push_str(&format!(" ({})", name));
+    }
+    line.push_str(&format!(" {}", params.value("event")?));
+    for key in &EVENT_DETAILS {
+        if let Some(value) = params.value(key) {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    Some(line)
+}
+
+/// Run sub-command
+fn run_subcommand(config: Config, restart: bool) -> Result<(), Error> {
+    gstreamer::init().expect("gstreamer init failed!");
+    let (tx, rx) = channel();
+    let control_port = config.control_port.unwrap_or(8001);
+    let watch_file = config.watch_file();
+    let flows = config.clone().into_flows(tx.clone(), restart)?;
+    let running = Arc::new(Mutex::new(Running {
+        config,
+        flows,
+        restart,
+        subscribers: vec![],
+    }));
+    let address: IpAddr = "::".parse()?;
+    let listener = TcpListener::bind((address, control_port))?;
+    if watch_file {
+        let w_running = Arc::clone(&running);
+        let w_tx = tx.clone();
+        thread::spawn(move || watch_thread(w_running, w_tx));
+    }
+    let c_running = Arc::clone(&running);
+    thread::spawn(move || command_thread(listener, c_running, tx));
+    thread::spawn(move || feedback_thread(running, rx));
+    let mainloop = glib::MainLoop::new(None, false);
+    mainloop.run();
+    Ok(())
+}
+
+/// Control connection subscription
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Subscription {
+    /// All flow events (`event` messages)
+    Events,
+    /// Flow statistics (`stats` messages)
+    Stats,
+}
+
+/// Running flows, with the configuration used to build them
+struct Running {
+    /// Configuration of running flows
+    config: Config,
+    /// Running flows (`None` for disabled flows)
+    flows: Vec<Option<Flow>>,
+    /// Automatic restart of flows
+    restart: bool,
+    /// Connections subscribed to events or stats
+    subscribers: Vec<(Subscription, SyncSender<Vec<u8>>)>,
+}
+
+impl Running {
+    /// Update running flows to match a new configuration
+    ///
+    /// Only flows with a changed configuration are rebuilt.
+    fn update(
+        &mut self,
+        config: Config,
+        fb: &Sender<Feedback>,
+    ) -> Result<(), Error> {
+        let acceleration = config.acceleration()?;
+        let mut rebuild_all =
+            Some(acceleration) != self.config.acceleration().ok();
+        if rebuild_all {
+            info!(
+                "Acceleration changed to {:?}, rebuilding flows",
+                acceleration
+            );
+        }
+        if config.software_fallback() != self.config.software_fallback() {
+            info!("Software fallback changed, rebuilding flows");
+            rebuild_all = true;
+        }
+        if config.ttl != self.config.ttl || config.dscp != self.config.dscp {
+            info!("Default TTL / DSCP changed, rebuilding flows");
+            rebuild_all = true;
+        }
+        if config.bitrate_scale() != self.config.bitrate_scale() {
+            info!("Bandwidth budget scale changed, rebuilding flows");
+            config.log_bandwidth();
+            rebuild_all = true;
+        }
+        self.flows.truncate(config.flow.len());
+        for number in 0..config.flow.len() {
+            if number < self.flows.len() {
+                let old = &self.config.flow[number];
+                let new = &config.flow[number];
+                if !rebuild_all && old.is_ticker_change(new) {
+                    self.update_ticker(number, old, new);
+                } else if rebuild_all || old != new {
+                    debug!(
+                        flow = number, name = new.name.as_deref();
+                        "{} changed, rebuilding",
+                        new.label(number)
+                    );
+                    self.flows[number] = config.create_flow_or_log(
+                        number,
+                        fb.clone(),
+                        self.restart,
+                    );
+                }
             } else {
-                None
-            };
-            set_param!(number, overlay_text);
-            param = true;
+                let flow =
+                    config.create_flow_or_log(number, fb.clone(), self.restart);
+                self.flows.push(flow);
+            }
         }
-        if !param {
-            println!("\n{}", muon_rs::to_string(flow)?);
+        self.config = config;
+        Ok(())
+    }
+
+    /// Update the ticker of a flow while playing
+    fn update_ticker(&self, number: usize, old: &FlowConfig, new: &FlowConfig) {
+        if let Some(Some(flow)) = self.flows.get(number) {
+            let mut updates = vec![];
+            if let (true, Some(text)) =
+                (old.ticker_text != new.ticker_text, &new.ticker_text)
+            {
+                updates.push(Ticker::Text(text.clone()));
+            }
+            if old.ticker_speed != new.ticker_speed {
+                updates.push(Ticker::Speed(new.ticker_speed));
+            }
+            for update in updates {
+                debug!(
+                    flow = number;
+                    "Flow{} ticker update {:?}",
+                    number, update
+                );
+                if let Err(e) = flow.update_ticker(update) {
+                    warn!(flow = number; "Flow{}: {}", number, e);
+                }
+            }
         }
-        self.store();
-        Ok(number)
     }
 
-    /// Convert config into a Vec of Flows
-    fn into_flows(self, fb: Sender<Feedback>) -> Result<Vec<Flow>, Error> {
-        let mut flows = vec![];
-        for number in 0..self.flow.len() {
-            flows.push(self.create_flow(number, fb.clone())?);
+    /// Pause or resume a running flow
+    fn pause_flow(&self, params: &str, pause: bool) -> Result<(), Error> {
+        let number: usize = params
+            .value("number")
+            .ok_or(Error::Other("Missing flow number"))?
+            .parse()?;
+        match self.flows.get(number) {
+            Some(Some(flow)) if pause => flow.pause(),
+            Some(Some(flow)) => flow.resume(),
+            _ => Err(Error::Other("Flow not running")),
         }
-        Ok(flows)
     }
 
-    /// Create a flow
-    fn create_flow(
-        &self,
-        number: usize,
-        fb: Sender<Feedback>,
-    ) -> Result<Flow, Error> {
-        let acceleration = match &self.acceleration {
-            Some(a) => a.parse::<Acceleration>()?,
-            None => Acceleration::NONE,
+    /// Tear down a flow and rebuild it from the running configuration
+    fn restart_flow(
+        &mut self,
+        params: &str,
+        fb: &Sender<Feedback>,
+    ) -> Result<(), Error> {
+        let number: usize = params
+            .value("number")
+            .ok_or(Error::Other("Missing flow number"))?
+            .parse()?;
+        match self.config.flow.get(number) {
+            Some(flow_cfg) => {
+                info!(
+                    flow = number, name = flow_cfg.name.as_deref();
+                    "{} restart requested",
+                    flow_cfg.label(number)
+                )
+            },
+            None => return Err(Error::Other("Invalid flow number")),
+        }
+        if let Some(flow) = self.flows.get_mut(number) {
+            // Drop the old pipeline before building the new one
+            *flow = None;
+            *flow = self.config.create_flow_or_log(
+                number,
+                fb.clone(),
+                self.restart,
+            );
+        }
+        Ok(())
+    }
+
+    /// Write a config message with the running configuration
+    fn write_config<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        let mut config = self.config.clone();
+        // Never send the control token back
+        config.control_token = None;
+        let muon = muon_rs::to_string(&config)?;
+        write_message(out, "config", &[("muon", muon)])
+    }
+
+    /// Send an event message to all subscribed connections
+    fn publish_event(&mut self, feedback: &Feedback) -> Result<(), Error> {
+        if self.subscribers.is_empty() {
+            return Ok(());
+        }
+        if let Feedback::Stats(idx, stats) = feedback {
+            let mut params = self.flow_params(*idx);
+            params.extend(stats_params(stats));
+            let mut msg = vec![];
+            write_message(&mut msg, "stats", &params)?;
+            self.publish(Subscription::Stats, msg);
+        }
+        let (idx, event, details) = match feedback {
+            Feedback::Playing(idx) => (idx, "PLAYING", vec![]),
+            Feedback::Stopped(idx) => (idx, "STOPPED", vec![]),
+            Feedback::Paused(idx) => (idx, "PAUSED", vec![]),
+            Feedback::Stats(idx, stats) => (idx, "STATS", stats_params(stats)),
+            Feedback::Error(idx, elem, msg) => (
+                idx,
+                "ERROR",
+                vec![("element", elem.clone()), ("message", msg.clone())],
+            ),
+            Feedback::Fallback(idx, elem) => {
+                (idx, "FALLBACK", vec![("element", elem.clone())])
+            },
+            Feedback::Stuck(idx, action) => {
+                (idx, "STUCK", vec![("action", format!("{:?}", action))])
+            },
+            Feedback::Redirect(idx, location) => {
+                (idx, "REDIRECT", vec![("location", location.clone())])
+            },
+            Feedback::Alert(idx, alert) => {
+                (idx, "ALERT", vec![("alert", format!("{:?}", alert))])
+            },
         };
-        if let Some(flow_cfg) = self.flow.iter().skip(number).next() {
-            let flow = FlowBuilder::new(number)
-                .with_acceleration(acceleration)
-                .with_source(flow_cfg.source())
-                .with_overlay_text(flow_cfg.overlay_text())
-                .with_sink(flow_cfg.sink())
-                .with_feedback(Some(fb))
-                .build()?;
-            Ok(flow)
-        } else {
-            Err(Error::Other("Invalid flow number"))
+        let mut params = self.flow_params(*idx);
+        params.push(("event", event.to_string()));
+        params.extend(details);
+        let mut msg = vec![];
+        write_message(&mut msg, "event", &params)?;
+        self.publish(Subscription::Events, msg);
+        Ok(())
+    }
+
+    /// Get number and name parameters of a flow
+    fn flow_params(&self, idx: usize) -> Vec<(&'static str, String)> {
+        let mut params = vec![("number", idx.to_string())];
+        if let Some(name) =
+            self.config.flow.get(idx).and_then(|f| f.name.as_ref())
+        {
+            params.push(("name", name.clone()));
+        }
+        params
+    }
+
+    /// Send a message to subscribers, without blocking
+    ///
+    /// Subscribers which are disconnected or too slow are dropped.
+    fn publish(&mut self, subscription: Subscription, msg: Vec<u8>) {
+        self.subscribers.retain(|(sub, tx)| {
+            *sub != subscription || tx.try_send(msg.clone()).is_ok()
+        });
+    }
+
+    /// Write a status message for each flow
+    fn write_status<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        for (number, flow_cfg) in self.config.flow.iter().enumerate() {
+            let flow = self.flows.get(number).and_then(|f| f.as_ref());
+            let state = match flow {
+                None => "STOPPED",
+                Some(f) if f.is_paused() => "PAUSED",
+                Some(f) if f.last_state_change().is_none() => "STARTING",
+                Some(f) if f.is_playing() => "PLAYING",
+                Some(_) => "FAILED",
+            };
+            let mut params = vec![
+                ("number", number.to_string()),
+                ("location", flow_cfg.location.0.clone()),
+                ("state", state.to_string()),
+            ];
+            if let Some(name) = &flow_cfg.name {
+                params.push(("name", name.clone()));
+            }
+            let since = flow
+                .and_then(|f| f.last_state_change())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+            if let Some(since) = since {
+                params.push(("since", since.as_secs().to_string()));
+            }
+            if let Some(uptime) = flow.and_then(|f| f.uptime()) {
+                params.push(("uptime", uptime.as_secs().to_string()));
+            }
+            if let Some((width, height, rate)) =
+                flow.and_then(|f| f.source_caps())
+            {
+                params.push(("width", width.to_string()));
+                params.push(("height", height.to_string()));
+                let rate = format!("{}/{}", rate.numer(), rate.denom());
+                params.push(("framerate", rate));
+            }
+            write_message(out, "status", &params)?;
         }
+        Ok(())
     }
 }
 
-/// Main function
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let env = Env::default().default_filter_or("info");
-    env_logger::from_env(env).format_timestamp(None).init();
-    let mut config = Config::load();
-    match create_app(&config).get_matches().subcommand() {
-        ("config", Some(matches)) => config.config_subcommand(matches)?,
-        ("flow", Some(matches)) => {
-            config.flow_subcommand(matches)?;
-        },
-        ("run", Some(_matches)) => run_subcommand(config)?,
-        _ => unreachable!(),
+/// Get message parameters for flow statistics
+fn stats_params(stats: &Stats) -> Vec<(&'static str, String)> {
+    let mut params = vec![
+        ("pushed", stats.pushed.to_string()),
+        ("lost", stats.lost.to_string()),
+        ("late", stats.late.to_string()),
+        ("buffers", stats.buffers.to_string()),
+        ("bytes", stats.bytes.to_string()),
+        ("dropped", stats.dropped.to_string()),
+        ("latency", stats.latency.to_string()),
+        ("received", stats.received.to_string()),
+    ];
+    if let Some(jitter) = stats.jitter {
+        params.push(("jitter", jitter.to_string()));
+    }
+    if let Some(rtt) = stats.rtt {
+        params.push(("rtt", rtt.to_string()));
+    }
+    params
+}
+
+/// Write a message to a control connection
+fn write_message<W: Write>(
+    out: &mut W,
+    cmd: &str,
+    params: &[(&str, String)],
+) -> Result<(), Error> {
+    let mut msg = cmd.as_bytes().to_vec();
+    for (name, value) in params {
+        msg.push(SEP_RECORD);
+        msg.extend(name.as_bytes());
+        msg.push(SEP_UNIT);
+        msg.extend(value.as_bytes());
     }
+    msg.push(SEP_GROUP);
+    out.write_all(&msg)?;
     Ok(())
 }
 
-/// Run sub-command
-fn run_subcommand(config: Config) -> Result<(), Error> {
-    gstreamer::init().expect("gstreamer init failed!");
-    let (tx, rx) = channel();
-    let control_port = config.control_port.unwrap_or(8001);
-    let flows = config.into_flows(tx.clone())?;
-    let flows = Arc::new(Mutex::new(flows));
-    let address: IpAddr = "::".parse()?;
-    let listener = TcpListener::bind((address, control_port))?;
-    let c_flows = Arc::clone(&flows);
-    thread::spawn(move || command_thread(listener, c_flows, tx));
-    thread::spawn(move || feedback_thread(flows, rx));
-    let mainloop = glib::MainLoop::new(None, false);
-    mainloop.run();
+/// Thread to reload configuration when the file changes
+fn watch_thread(running: Arc<Mutex<Running>>, fb: Sender<Feedback>) {
+    let path = Config::path();
+    let mut modified = modified_time(&path);
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let m = modified_time(&path);
+        if m != modified {
+            modified = m;
+            if let Err(e) = reload_config(&path, &running, &fb) {
+                warn!("reloading {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Get the modified time of a file
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reload configuration file, rebuilding changed flows
+fn reload_config(
+    path: &Path,
+    running: &Arc<Mutex<Running>>,
+    fb: &Sender<Feedback>,
+) -> Result<(), Error> {
+    let config = Config::read(path)?;
+    let mut running = running.lock().unwrap();
+    if config != running.config {
+        info!("Reloading {:?}", path);
+        running.update(config, fb)?;
+    }
     Ok(())
 }
 
 /// Thread to receive feedback
 fn feedback_thread(
-    flows: Arc<Mutex<Vec<Flow>>>,
+    running: Arc<Mutex<Running>>,
     rx: Receiver<Feedback>,
 ) -> Result<(), Error> {
     loop {
         let state = rx.recv().unwrap();
-        let (n_playing, n_stopped) = count_flows(&flows);
+        if let Err(e) = running.lock().unwrap().publish_event(&state) {
+            warn!("publish_event: {}", e);
+        }
+        let (n_playing, n_stopped) = count_flows(&running);
         match state {
             Feedback::Playing(idx) => {
+                let (label, name) = flow_label(&running, idx);
                 info!(
-                    "Flow{} started: {} playing, {} stopped",
-                    idx, n_playing, n_stopped
+                    flow = idx, name = name.as_deref();
+                    "{} started: {} playing, {} stopped",
+                    label, n_playing, n_stopped
                 );
             },
             Feedback::Stopped(idx) => {
+                let (label, name) = flow_label(&running, idx);
+                info!(
+                    flow = idx, name = name.as_deref();
+                    "{} stopped: {} playing, {} stopped",
+                    label, n_playing, n_stopped
+                );
+            },
+            Feedback::Paused(idx) => {
+                let (label, name) = flow_label(&running, idx);
+                info!(flow = idx, name = name.as_deref(); "{} paused", label);
+            },
+            Feedback::Redirect(idx, location) => {
+                let (label, name) = flow_label(&running, idx);
+                info!(
+                    flow = idx, name = name.as_deref();
+                    "{} redirected: {}",
+                    label, location
+                );
+            },
+            Feedback::Fallback(idx, elem) => {
+                let (label, name) = flow_label(&running, idx);
+                info!(
+                    flow = idx, name = name.as_deref();
+                    "{} running in software fallback ({})",
+                    label, elem
+                );
+            },
+            Feedback::Alert(idx, Alert::DOWN) => {
+                let (label, name) = flow_label(&running, idx);
+                error!(
+                    flow = idx, name = name.as_deref();
+                    "{} alert: no packets",
+                    label
+                );
+            },
+            Feedback::Alert(idx, Alert::LOSS) => {
+                let (label, name) = flow_label(&running, idx);
+                warn!(
+                    flow = idx, name = name.as_deref();
+                    "{} alert: packet loss",
+                    label
+                );
+            },
+            Feedback::Alert(idx, Alert::RECOVERED) => {
+                let (label, name) = flow_label(&running, idx);
                 info!(
-                    "Flow{} stopped: {} playing, {} stopped",
-                    idx, n_playing, n_stopped
+                    flow = idx, name = name.as_deref();
+                    "{} alert: recovered",
+                    label
                 );
             },
             _ => (),
@@ -616,10 +3496,21 @@ fn feedback_thread(
     }
 }
 
+/// Get the label and name of a running flow
+fn flow_label(
+    running: &Arc<Mutex<Running>>,
+    idx: usize,
+) -> (String, Option<String>) {
+    match running.lock().unwrap().config.flow.get(idx) {
+        Some(flow_cfg) => (flow_cfg.label(idx), flow_cfg.name.clone()),
+        None => (format!("Flow{}", idx), None),
+    }
+}
+
 /// Count playing and stopped flows
-fn count_flows(flows: &Arc<Mutex<Vec<Flow>>>) -> (usize, usize) {
-    let flows = flows.lock().unwrap();
-    let n_playing = flows.iter().filter(|f| f.is_playing()).count();
+fn count_flows(running: &Arc<Mutex<Running>>) -> (usize, usize) {
+    let flows = &running.lock().unwrap().flows;
+    let n_playing = flows.iter().flatten().filter(|f| f.is_playing()).count();
     let n_stopped = flows.len() - n_playing;
     (n_playing, n_stopped)
 }
@@ -627,87 +3518,203 @@ fn count_flows(flows: &Arc<Mutex<Vec<Flow>>>) -> (usize, usize) {
 /// Thread to handle remote commands
 fn command_thread(
     listener: TcpListener,
-    mut flows: Arc<Mutex<Vec<Flow>>>,
+    mut running: Arc<Mutex<Running>>,
     fb: Sender<Feedback>,
 ) {
     loop {
-        if let Err(e) = process_connection(&listener, &mut flows, &fb) {
-            warn!("command_thread: {:?}", e);
+        match process_connection(&listener, &mut running, &fb) {
+            Ok(()) => (),
+            // Clients cycling connections should reconnect without delay
+            Err(e) if e.is_disconnect() => {
+                debug!("command_thread: {}", e);
+            },
+            // Back off after other errors, to avoid a tight error loop
+            Err(e) => {
+                warn!("command_thread: {:?}", e);
+                let delay =
+                    running.lock().unwrap().config.control_retry_delay();
+                thread::sleep(delay);
+            },
         }
-        thread::sleep(Duration::from_secs(1));
     }
 }
 
 /// Process a TCP connection
 fn process_connection(
     listener: &TcpListener,
-    mut flows: &mut Arc<Mutex<Vec<Flow>>>,
+    mut running: &mut Arc<Mutex<Running>>,
     fb: &Sender<Feedback>,
 ) -> Result<(), Error> {
     let (socket, remote) = listener.accept()?;
     info!("command connection OPENED: {:?}", remote);
-    socket.set_read_timeout(Some(Duration::from_secs(35)))?;
-    let res = process_commands(socket, &mut flows, fb.clone());
-    info!("command connection CLOSED: {:?}", remote);
-    res
+    let timeout = running.lock().unwrap().config.control_timeout();
+    socket.set_read_timeout(timeout)?;
+    let reader = BufReader::new(socket.try_clone()?);
+    match process_commands(reader, &socket, &mut running, fb.clone()) {
+        Ok(Some(subscription)) => {
+            info!("command connection {:?}: {:?}", subscription, remote);
+            let (tx, rx) = sync_channel(SUBSCRIBER_QUEUE);
+            running.lock().unwrap().subscribers.push((subscription, tx));
+            thread::spawn(move || subscriber_thread(socket, rx));
+            Ok(())
+        },
+        res => {
+            info!("command connection CLOSED: {:?}", remote);
+            res.map(|_| ())
+        },
+    }
 }
 
-/// Process remote commands
-fn process_commands(
-    socket: TcpStream,
-    flows: &mut Arc<Mutex<Vec<Flow>>>,
-    fb: Sender<Feedback>,
-) -> Result<(), Error> {
-    let mut buf = vec![];
-    let mut reader = BufReader::new(socket);
-    loop {
-        let n_bytes = reader.read_until(SEP_GROUP, &mut buf)?;
-        if n_bytes == 0 {
+/// Thread to send messages to a subscribed connection
+fn subscriber_thread(mut socket: TcpStream, rx: Receiver<Vec<u8>>) {
+    let remote = socket.peer_addr().ok();
+    while let Ok(msg) = rx.recv() {
+        if socket.write_all(&msg).is_err() {
             break;
         }
-        match buf.pop() {
-            Some(SEP_GROUP) => {
-                let cmd = std::str::from_utf8(&buf)?;
-                let mut flows = flows.lock().unwrap();
-                process_command(cmd, &mut flows, fb.clone())?;
-            },
-            Some(b) => {
-                debug!("Invalid command separator: 0x{:X}", b);
-                return Err(Error::Other("Invalid command separator"));
+    }
+    info!("subscriber connection CLOSED: {:?}", remote);
+}
+
+/// Control command
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    /// Authenticate (token)
+    Auth(&'a str),
+    /// Set flow values (parameters)
+    Flow(&'a str),
+    /// Set global values (parameters)
+    Config(&'a str),
+    /// List flow status
+    List,
+    /// Get running configuration
+    GetConfig,
+    /// Subscribe to flow events
+    Monitor,
+    /// Subscribe to flow statistics
+    Stats,
+    /// Pause a flow (parameters)
+    Pause(&'a str),
+    /// Resume a paused flow (parameters)
+    Resume(&'a str),
+    /// Rebuild a flow from its configuration (parameters)
+    Restart(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Parse a command (without group separator)
+    fn parse(cmd: &'a str) -> Result<Self, Error> {
+        let mut cp = cmd.splitn(2, char::from(SEP_RECORD));
+        match (cp.next(), cp.next()) {
+            (Some("auth"), Some(token)) => Ok(Command::Auth(token)),
+            (Some("flow"), Some(params)) => Ok(Command::Flow(params)),
+            (Some("config"), Some(params)) => Ok(Command::Config(params)),
+            (Some("list"), _) => Ok(Command::List),
+            (Some("get-config"), _) => Ok(Command::GetConfig),
+            (Some("monitor"), _) => Ok(Command::Monitor),
+            (Some("stats"), _) => Ok(Command::Stats),
+            (Some("pause"), Some(params)) => Ok(Command::Pause(params)),
+            (Some("resume"), Some(params)) => Ok(Command::Resume(params)),
+            (Some("restart"), Some(params)) => Ok(Command::Restart(params)),
+            _ => {
+                debug!("Invalid command: {:?}", cmd);
+                Err(Error::Other("Invalid command"))
             },
-            None => break,
         }
-        buf.clear();
     }
-    Ok(())
+}
+
+/// Read one command, ending with a group separator
+///
+/// Returns `None` at end of input.
+fn read_command<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+) -> Result<Option<String>, Error> {
+    buf.clear();
+    let n_bytes = reader.read_until(SEP_GROUP, buf)?;
+    if n_bytes == 0 {
+        return Ok(None);
+    }
+    match buf.pop() {
+        Some(SEP_GROUP) => Ok(Some(std::str::from_utf8(buf)?.to_string())),
+        Some(b) => {
+            debug!("Invalid command separator: 0x{:X}", b);
+            Err(Error::Other("Invalid command separator"))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Process remote commands
+///
+/// Returns a subscription if the connection subscribed with `monitor` or
+/// `stats`.
+fn process_commands<R: BufRead, W: Write>(
+    mut reader: R,
+    mut out: W,
+    running: &mut Arc<Mutex<Running>>,
+    fb: Sender<Feedback>,
+) -> Result<Option<Subscription>, Error> {
+    let mut buf = vec![];
+    let mut authenticated =
+        running.lock().unwrap().config.control_token.is_none();
+    while let Some(cmd) = read_command(&mut reader, &mut buf)? {
+        let cmd = Command::parse(&cmd)?;
+        let mut running = running.lock().unwrap();
+        if let Command::Auth(token) = cmd {
+            authenticated = running.config.authenticate(token);
+            if !authenticated {
+                drop(running);
+                thread::sleep(AUTH_DELAY);
+                return Err(Error::Other("Authentication failed"));
+            }
+        } else if authenticated && cmd == Command::Monitor {
+            return Ok(Some(Subscription::Events));
+        } else if authenticated && cmd == Command::Stats {
+            return Ok(Some(Subscription::Stats));
+        } else if authenticated {
+            let config = running.config.clone();
+            process_command(cmd, config, &mut running, fb.clone(), &mut out)?;
+        } else {
+            return Err(Error::Other("Not authenticated"));
+        }
+    }
+    Ok(None)
 }
 
 /// Process a remote command
-fn process_command(
-    cmd: &str,
-    flows: &mut Vec<Flow>,
+///
+/// `flow` and `config` commands are applied to `config`, which then
+/// replaces the running configuration.
+fn process_command<W: Write>(
+    cmd: Command,
+    mut config: Config,
+    running: &mut Running,
     fb: Sender<Feedback>,
+    out: &mut W,
 ) -> Result<(), Error> {
-    // Maybe someday, use SEP_RECORD instead of \x1E
-    if cmd.starts_with("flow\x1E") {
-        let params = &cmd[5..];
-        let mut config = Config::load();
-        let number = config.flow_subcommand(&params)?;
-        match flows.get_mut(number) {
-            Some(flow) => *flow = config.create_flow(number, fb)?,
-            None => return Err(Error::Other("Invalid flow number")),
-        }
-        return Ok(());
-    } else if cmd.starts_with("config\x1E") {
-        let params = &cmd[7..];
-        let mut config = Config::load();
-        config.config_subcommand(&params)?;
-        flows.clear();
-        flows.extend(config.into_flows(fb)?);
-        return Ok(());
+    match cmd {
+        Command::Flow(params) => {
+            // Pipe sinks write to any descriptor or FIFO; config file only
+            if params.is_present("pipe") {
+                warn!("Control: `pipe` can only be set in config file");
+                return Err(Error::Other("Parameter not allowed"));
+            }
+            config.flow_subcommand(&params)?;
+            running.update(config, &fb)
+        },
+        Command::Config(params) => {
+            config.config_subcommand(&params)?;
+            running.update(config, &fb)
+        },
+        Command::List => running.write_status(out),
+        Command::GetConfig => running.write_config(out),
+        Command::Pause(params) => running.pause_flow(params, true),
+        Command::Resume(params) => running.pause_flow(params, false),
+        Command::Restart(params) => running.restart_flow(params, &fb),
+        Command::Auth(_) | Command::Monitor | Command::Stats => Ok(()),
     }
-    debug!("Invalid command: {:?}", cmd);
-    Err(Error::Other("Invalid command"))
 }
 
 impl<'a> Parameters<'a> for &'a str {
@@ -715,6 +3722,11 @@ impl<'a> Parameters<'a> for &'a str {
         self.split(char::from(SEP_RECORD))
             .find_map(|p| param_value(p, key))
     }
+
+    fn is_present(&'a self, key: &'a str) -> bool {
+        self.split(char::from(SEP_RECORD))
+            .any(|p| p.split(char::from(SEP_UNIT)).next() == Some(key))
+    }
 }
 
 /// Get the value of one parameter
@@ -729,3 +3741,328 @@ fn param_value<'a>(params: &'a str, key: &str) -> Option<&'a str> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    #[test]
+    fn resize_flows_round_trip() {
+        let mut config = Config::default();
+        config.resize_flows(3);
+        for (i, flow) in config.flow.iter_mut().enumerate() {
+            flow.location = Location(format!("rtsp://10.0.0.{}/", i));
+        }
+        let flows = config.flow.clone();
+        config.resize_flows(1);
+        assert_eq!(config.flow[..], flows[..1]);
+        assert_eq!(config.removed_flow.len(), 2);
+        config.resize_flows(3);
+        assert_eq!(config.flow, flows);
+        assert!(config.removed_flow.is_empty());
+    }
+
+    /// Create running state with no flows
+    fn running() -> Running {
+        Running {
+            config: Config::default(),
+            flows: vec![],
+            restart: false,
+            subscribers: vec![],
+        }
+    }
+
+    #[test]
+    fn read_commands() {
+        let mut reader = &b"list\x1Dget-config\x1D"[..];
+        let mut buf = vec![];
+        let cmd = read_command(&mut reader, &mut buf).unwrap();
+        assert_eq!(cmd.as_deref(), Some("list"));
+        let cmd = read_command(&mut reader, &mut buf).unwrap();
+        assert_eq!(cmd.as_deref(), Some("get-config"));
+        assert!(read_command(&mut reader, &mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_command_bad_separator() {
+        let mut reader = &b"list\x1E"[..];
+        let mut buf = vec![];
+        assert!(read_command(&mut reader, &mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_commands() {
+        let params = "number\x1F0\x1Elocation\x1Frtsp://10.0.0.1/";
+        let cmd = format!("flow\x1E{}", params);
+        assert_eq!(Command::parse(&cmd).unwrap(), Command::Flow(params));
+        assert_eq!(Command::parse("list").unwrap(), Command::List);
+        assert_eq!(Command::parse("stats").unwrap(), Command::Stats);
+    }
+
+    #[test]
+    fn parse_unknown_command() {
+        assert!(Command::parse("bogus").is_err());
+        assert!(Command::parse("").is_err());
+        // Commands with parameters need a record separator
+        assert!(Command::parse("flow").is_err());
+        assert!(Command::parse("auth\x1Ftoken").is_err());
+    }
+
+    #[test]
+    fn param_values() {
+        let params = "number\x1F2\x1Ename\x1F\x1Ejson";
+        assert_eq!(params.value("number"), Some("2"));
+        assert_eq!(params.value("name"), Some(""));
+        assert_eq!(params.value("location"), None);
+        assert!(params.is_present("json"));
+        assert!(!params.is_present("location"));
+    }
+
+    #[test]
+    fn flow_command_missing_number() {
+        let mut running = running();
+        let (fb, _rx) = channel();
+        let mut out = vec![];
+        let cmd = Command::parse("flow\x1Elocation\x1Ftest").unwrap();
+        let config = Config::default();
+        let res = process_command(cmd, config, &mut running, fb, &mut out);
+        assert!(res.is_err());
+        assert!(running.config.flow.is_empty());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn flow_command_pipe_rejected() {
+        let mut running = running();
+        let (fb, _rx) = channel();
+        let mut out = vec![];
+        let cmd =
+            Command::parse("flow\x1Enumber\x1F0\x1Epipe\x1Ffd:1").unwrap();
+        let config = Config::default();
+        let res = process_command(cmd, config, &mut running, fb, &mut out);
+        assert!(res.is_err());
+        assert!(running.config.flow.is_empty());
+    }
+
+    #[test]
+    fn update_keeps_config_when_flow_fails() {
+        let mut running = running();
+        let (fb, _rx) = channel();
+        let mut config = Config {
+            allowed_sources: vec!["rtsp".to_string()],
+            ..Config::default()
+        };
+        config.resize_flows(2);
+        running.update(config.clone(), &fb).unwrap();
+        assert_eq!(running.config, config);
+        assert_eq!(running.flows.len(), 2);
+        assert!(running.flows.iter().all(|f| f.is_none()));
+    }
+
+    #[test]
+    fn get_config_command() {
+        let mut running = running();
+        let (fb, _rx) = channel();
+        let mut out = vec![];
+        let config = running.config.clone();
+        process_command(Command::GetConfig, config, &mut running, fb, &mut out)
+            .unwrap();
+        assert!(out.starts_with(b"config\x1Emuon\x1F"));
+        assert_eq!(out.last(), Some(&SEP_GROUP));
+    }
+
+    #[test]
+    fn rapid_reconnects_not_delayed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut running = running();
+        running.config.control_retry_delay = Some(10_000);
+        let running = Arc::new(Mutex::new(running));
+        let (fb, _rx) = channel();
+        thread::spawn(move || command_thread(listener, running, fb));
+        let start = Instant::now();
+        for _ in 0..5 {
+            let mut socket = TcpStream::connect(addr).unwrap();
+            socket.write_all(b"get-config\x1D").unwrap();
+            socket.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut out = vec![];
+            socket.read_to_end(&mut out).unwrap();
+            assert!(out.starts_with(b"config\x1Emuon\x1F"));
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn log_json_flow() {
+        let kvs = [
+            ("flow", log::kv::Value::from(3u64)),
+            ("name", log::kv::Value::from("Cam): one")),
+        ];
+        let line = log_json(
+            &log::Record::builder()
+                .args(format_args!("Flow3 (Cam): one) started"))
+                .level(log::Level::Info)
+                .target("streambed")
+                .key_values(&kvs)
+                .build(),
+        );
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["flow"], 3);
+        assert_eq!(line["name"], "Cam): one");
+        assert_eq!(line["message"], "Flow3 (Cam): one) started");
+    }
+
+    #[test]
+    fn log_json_plain() {
+        let line = log_json(
+            &log::Record::builder()
+                .args(format_args!("Reloading"))
+                .level(log::Level::Warn)
+                .build(),
+        );
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert!(line.get("flow").is_none());
+        assert_eq!(line["message"], "Reloading");
+    }
+
+    #[test]
+    fn append_flow_is_default() {
+        let mut config = Config::default();
+        config.resize_flows(2);
+        config.flow[1].location = Location("rtsp://10.0.0.1/".to_string());
+        config.resize_flows(1);
+        config.append_flow();
+        assert_eq!(config.flow[1], FlowConfig::default());
+        assert_eq!(config.removed_flow.len(), 1);
+    }
+
+    #[test]
+    fn stats_params_optional() {
+        let mut stats = Stats {
+            pushed: 10,
+            latency: 200,
+            ..Stats::default()
+        };
+        let params = stats_params(&stats);
+        assert_eq!(params.len(), 8);
+        assert_eq!(params[0], ("pushed", "10".to_string()));
+        assert_eq!(params[6], ("latency", "200".to_string()));
+        stats.rtt = Some(1500);
+        let params = stats_params(&stats);
+        assert_eq!(params.last(), Some(&("rtt", "1500".to_string())));
+        assert!(!params.iter().any(|(name, _)| *name == "jitter"));
+    }
+
+    #[test]
+    fn source_match() {
+        assert!(is_source_match("rtsp", "rtsp://10.0.0.5/stream"));
+        assert!(is_source_match("RTSP", "rtsp://10.0.0.5/stream"));
+        assert!(is_source_match(
+            "rtsp://10.0.0.5",
+            "rtsp://u:p@10.0.0.5:554/"
+        ));
+        assert!(is_source_match("rtp://[ff02::1]", "rtp://[ff02::1]:5000"));
+        assert!(!is_source_match("rtsp://10.0.0.5", "rtsp://10.0.0.6/"));
+        assert!(!is_source_match("rtsp://10.0.0.5", "rtsp:///stream"));
+        assert!(!is_source_match("http", "rtsp://10.0.0.5/"));
+        assert!(!is_source_match("/srv", "rtsp://10.0.0.5/"));
+    }
+
+    #[test]
+    fn source_match_test() {
+        assert!(is_source_match("test", ""));
+        assert!(is_source_match("test", "test:"));
+        assert!(is_source_match("test:", ""));
+        assert!(!is_source_match("rtsp", ""));
+        assert!(is_source_match("fd", "fd:3"));
+    }
+
+    #[test]
+    fn path_match() {
+        assert!(is_path_match("/srv/hls", "/srv/hls"));
+        assert!(is_path_match("/srv/hls", "/srv/hls/cam1"));
+        assert!(!is_path_match("/srv/hls", "/srv/hls2"));
+        assert!(!is_path_match("/srv/hls", "/srv/hls/../../etc"));
+        assert!(!is_path_match("/srv/hls", "hls"));
+        assert!(!is_path_match("rtsp", "/srv/hls"));
+    }
+
+    #[test]
+    fn disallowed_params() {
+        let mut config = Config::default();
+        config.resize_flows(1);
+        assert_eq!(config.disallowed_param(&config.flow[0]), None);
+        config.allowed_sources = vec!["rtsp".to_string()];
+        assert_eq!(
+            config.disallowed_param(&config.flow[0]),
+            Some(("location", "test"))
+        );
+        config.allowed_sources.push("test".to_string());
+        assert_eq!(config.disallowed_param(&config.flow[0]), None);
+        config.flow[0].hls_dir = Some("/var/www/hls".to_string());
+        assert_eq!(
+            config.disallowed_param(&config.flow[0]),
+            Some(("hls-dir", "/var/www/hls"))
+        );
+        config.allowed_sources.push("/var/www".to_string());
+        config.flow[0].pipe = Some("fd:1".to_string());
+        assert_eq!(
+            config.disallowed_param(&config.flow[0]),
+            Some(("pipe", "fd:1"))
+        );
+        config.allowed_sources.push("fd".to_string());
+        config.flow[0].overlay_file = Some("/etc/passwd".to_string());
+        assert_eq!(
+            config.disallowed_param(&config.flow[0]),
+            Some(("overlay-file", "/etc/passwd"))
+        );
+    }
+
+    #[test]
+    fn default_flow_builds_test_source() {
+        gstreamer::init().unwrap();
+        if gstreamer::ElementFactory::find("videotestsrc").is_none() {
+            return;
+        }
+        let mut config = Config::default();
+        config.resize_flows(1);
+        let (fb, _rx) = channel();
+        assert!(config.create_flow(0, fb, false).unwrap().is_some());
+    }
+
+    /// Create config with RTP flows of (sink encoding, max bitrate)
+    fn bitrate_config(flows: &[(&str, u64)]) -> Config {
+        let mut config = Config::default();
+        config.resize_flows(flows.len());
+        for (flow, (encoding, rate)) in config.flow.iter_mut().zip(flows) {
+            flow.source_encoding = Some("H264".to_string());
+            flow.address = Some("239.0.0.1".to_string());
+            flow.port = Some(5000);
+            flow.sink_encoding = Some(encoding.to_string());
+            flow.max_bitrate = Some(*rate);
+        }
+        config
+    }
+
+    #[test]
+    fn total_bitrate_saturates() {
+        let config = bitrate_config(&[("H265", u64::MAX), ("H265", 1000)]);
+        assert_eq!(config.total_bitrate(), u64::MAX);
+    }
+
+    #[test]
+    fn bitrate_budget_skips_passthrough() {
+        let mut config =
+            bitrate_config(&[("H264", 4_000_000), ("H265", 2_000_000)]);
+        config.bandwidth_budget = Some(1_000_000);
+        assert!(!config.flow[0].is_encoded());
+        assert!(config.flow[1].is_encoded());
+        assert_eq!(config.total_bitrate(), 2_000_000);
+        assert_eq!(config.max_bitrate(&config.flow[0]), Some(4_000_000));
+        assert_eq!(config.max_bitrate(&config.flow[1]), Some(1_000_000));
+    }
+}